@@ -0,0 +1,498 @@
+//! Derive macro for the `AnkiNote` trait of [genanki-rs](https://docs.rs/genanki-rs). Enable it
+//! with the `derive` feature of genanki-rs instead of depending on this crate directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitBool, LitInt, LitStr};
+
+/// Names Anki fills in for every card, in addition to the fields of the note
+const SPECIAL_FIELDS: &[&str] = &[
+    "FrontSide",
+    "Tags",
+    "Type",
+    "Deck",
+    "Subdeck",
+    "Card",
+    "CardFlag",
+];
+
+/// Implements `AnkiNote` for a struct with named fields
+///
+/// Every struct field is a field of the model, in declaration order, and its value is converted
+/// with `ToString`. The model is configured on the struct:
+///
+/// * `#[anki(id = ...)]`: id of the model, required
+/// * `#[anki(name = "...")]`: name of the model, default is the name of the struct
+/// * `#[anki(css = "...")]`: CSS of the cards
+/// * `#[anki(cloze)]`: makes the model a cloze model
+/// * `#[anki(template(name = "...", qfmt = "...", afmt = "..."))]`: adds a card template, at least
+///   one is required
+///
+/// and on the struct fields:
+///
+/// * `#[anki(rename = "...")]`: name of the model field, default is the name of the struct field
+///   in upper camel case
+/// * `#[anki(font = "...", size = ..., rtl, sticky)]`: options of the model field
+/// * `#[anki(sort_field)]`: sorts the notes by this field in the browser
+/// * `#[anki(guid)]`: computes the guid of the note from the fields marked with it, so the note
+///   keeps its identity when other fields change
+/// * `#[anki(tags)]`: the field holds the tags of the note instead of a model field, anything
+///   iterable over values implementing `ToString`
+/// * `#[anki(skip)]`: the field is not part of the note
+///
+/// It is a compile error if a template refers to a field the struct does not have.
+///
+/// ```rust
+/// use genanki_rs::AnkiNote;
+///
+/// #[derive(AnkiNote)]
+/// #[anki(id = 1607392319, name = "Vocabulary", css = ".card { font-size: 24px; }")]
+/// #[anki(template(name = "Recognition", qfmt = "{{Word}}", afmt = "{{FrontSide}}<hr id=answer>{{Meaning}}"))]
+/// #[anki(template(name = "Recall", qfmt = "{{Meaning}}", afmt = "{{FrontSide}}<hr id=answer>{{Word}}"))]
+/// struct Vocabulary {
+///     #[anki(guid, sort_field)]
+///     word: String,
+///     #[anki(font = "Arial")]
+///     meaning: String,
+///     #[anki(tags)]
+///     tags: Vec<String>,
+/// }
+///
+/// let note = Vocabulary {
+///     word: "Haus".to_string(),
+///     meaning: "house".to_string(),
+///     tags: vec!["german".to_string()],
+/// }
+/// .to_note()
+/// .unwrap();
+/// assert_eq!(note.cards().len(), 2);
+/// ```
+///
+/// A typo in a template does not compile:
+///
+/// ```rust,compile_fail
+/// # use genanki_rs::AnkiNote;
+/// #[derive(AnkiNote)]
+/// #[anki(id = 1607392319)]
+/// #[anki(template(name = "Card 1", qfmt = "{{Word}}", afmt = "{{Meanig}}"))]
+/// struct Vocabulary {
+///     word: String,
+///     meaning: String,
+/// }
+/// ```
+#[proc_macro_derive(AnkiNote, attributes(anki))]
+pub fn derive_anki_note(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Options of the model, from the attributes of the struct
+#[derive(Default)]
+struct ModelOptions {
+    id: Option<LitInt>,
+    name: Option<LitStr>,
+    css: Option<LitStr>,
+    cloze: bool,
+    templates: Vec<TemplateOptions>,
+}
+
+struct TemplateOptions {
+    name: LitStr,
+    qfmt: LitStr,
+    afmt: LitStr,
+}
+
+/// What a struct field is used for, from its attributes
+enum FieldKind {
+    Field(FieldOptions),
+    Tags,
+    Skip,
+}
+
+struct FieldOptions {
+    name: String,
+    font: Option<LitStr>,
+    size: Option<LitInt>,
+    rtl: bool,
+    sticky: bool,
+    sort_field: bool,
+    guid: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let struct_fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "AnkiNote can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "AnkiNote can only be derived for structs",
+            ))
+        }
+    };
+
+    let options = parse_model_options(&input)?;
+    let id = options.id.as_ref().ok_or_else(|| {
+        syn::Error::new_spanned(&input.ident, "missing model id, add #[anki(id = ...)]")
+    })?;
+    if options.templates.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "missing template, add #[anki(template(name = \"...\", qfmt = \"...\", afmt = \"...\"))]",
+        ));
+    }
+    let name = options
+        .name
+        .clone()
+        .unwrap_or_else(|| LitStr::new(&input.ident.to_string(), input.ident.span()));
+
+    let mut fields = vec![];
+    let mut tags = None;
+    for field in struct_fields {
+        let ident = field.ident.as_ref().expect("named field");
+        match parse_field_kind(field)? {
+            FieldKind::Field(options) => {
+                if fields
+                    .iter()
+                    .any(|(_, other): &(_, FieldOptions)| other.name == options.name)
+                {
+                    return Err(syn::Error::new_spanned(
+                        ident,
+                        format!("duplicate field name `{}`", options.name),
+                    ));
+                }
+                fields.push((ident, options));
+            }
+            FieldKind::Tags => {
+                if tags.is_some() {
+                    return Err(syn::Error::new_spanned(ident, "duplicate tags field"));
+                }
+                tags = Some(ident);
+            }
+            FieldKind::Skip => {}
+        }
+    }
+    if fields.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "a note needs at least one field",
+        ));
+    }
+
+    let field_names: Vec<&str> = fields
+        .iter()
+        .map(|(_, field)| field.name.as_str())
+        .collect();
+    for template in &options.templates {
+        for format in [&template.qfmt, &template.afmt] {
+            if let Some(unknown) = unknown_fields(&format.value(), &field_names).first() {
+                return Err(syn::Error::new_spanned(
+                    format,
+                    format!(
+                        "template `{}` refers to unknown field `{}`, the fields are {}",
+                        template.name.value(),
+                        unknown,
+                        field_names.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    let sort_fields: Vec<usize> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, field))| field.sort_field)
+        .map(|(index, _)| index)
+        .collect();
+    if sort_fields.len() > 1 {
+        return Err(syn::Error::new_spanned(
+            fields[sort_fields[1]].0,
+            "only one field can be the sort field",
+        ));
+    }
+
+    let model_fields = fields.iter().map(|(_, field)| {
+        let name = &field.name;
+        let mut tokens = quote!(::genanki_rs::Field::new(#name));
+        if let Some(font) = &field.font {
+            tokens = quote!(#tokens.font(#font));
+        }
+        if let Some(size) = &field.size {
+            tokens = quote!(#tokens.size(#size));
+        }
+        if field.rtl {
+            tokens = quote!(#tokens.rtl(true));
+        }
+        if field.sticky {
+            tokens = quote!(#tokens.sticky(true));
+        }
+        tokens
+    });
+    let templates = options.templates.iter().map(|template| {
+        let TemplateOptions { name, qfmt, afmt } = template;
+        quote!(::genanki_rs::Template::new(#name).qfmt(#qfmt).afmt(#afmt))
+    });
+    let css = options.css.as_ref().map(|css| quote!(.css(#css)));
+    let model_type = if options.cloze {
+        Some(quote!(.model_type(::genanki_rs::ModelType::Cloze)))
+    } else {
+        None
+    };
+    let sort_field_index = sort_fields.first().map(|&index| {
+        let index = index as i64;
+        quote!(.sort_field_index(#index))
+    });
+
+    let values = fields
+        .iter()
+        .map(|(ident, _)| quote!(::std::string::ToString::to_string(&self.#ident)));
+    let tags = tags.map(|ident| quote!(let note = note.tags(&self.#ident);));
+    let guid_fields: Vec<usize> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, field))| field.guid)
+        .map(|(index, _)| index)
+        .collect();
+    let guid = if guid_fields.is_empty() {
+        None
+    } else {
+        Some(quote!(let note = note.guid(::genanki_rs::guid_for(&[#(&values[#guid_fields]),*]));))
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::genanki_rs::AnkiNote for #ident #ty_generics #where_clause {
+            fn model() -> ::genanki_rs::Model {
+                ::genanki_rs::Model::new(
+                    #id,
+                    #name,
+                    ::std::vec![#(#model_fields),*],
+                    ::std::vec![#(#templates),*],
+                )
+                #css
+                #model_type
+                #sort_field_index
+            }
+
+            fn to_note(&self) -> ::std::result::Result<::genanki_rs::Note, ::genanki_rs::Error> {
+                let values: ::std::vec::Vec<::std::string::String> = ::std::vec![#(#values),*];
+                let note = ::genanki_rs::Note::new(
+                    <Self as ::genanki_rs::AnkiNote>::model(),
+                    values.iter().map(::std::string::String::as_str).collect(),
+                )?;
+                #tags
+                #guid
+                ::std::result::Result::Ok(note)
+            }
+        }
+    })
+}
+
+fn parse_model_options(input: &DeriveInput) -> syn::Result<ModelOptions> {
+    let mut options = ModelOptions::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("anki"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                options.id = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("css") {
+                options.css = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("cloze") {
+                options.cloze = parse_flag(&meta)?;
+            } else if meta.path.is_ident("template") {
+                options.templates.push(parse_template(&meta)?);
+            } else {
+                return Err(meta.error("unknown model attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+fn parse_template(meta: &ParseNestedMeta) -> syn::Result<TemplateOptions> {
+    let mut name = None;
+    let mut qfmt = None;
+    let mut afmt = None;
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("qfmt") {
+            qfmt = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("afmt") {
+            afmt = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unknown template attribute"));
+        }
+        Ok(())
+    })?;
+    let missing = |key| meta.error(format!("missing `{}` of the template", key));
+    Ok(TemplateOptions {
+        name: name.ok_or_else(|| missing("name"))?,
+        qfmt: qfmt.ok_or_else(|| missing("qfmt"))?,
+        afmt: afmt.ok_or_else(|| missing("afmt"))?,
+    })
+}
+
+fn parse_field_kind(field: &syn::Field) -> syn::Result<FieldKind> {
+    let ident = field.ident.as_ref().expect("named field");
+    let mut options = FieldOptions {
+        name: upper_camel_case(&ident.to_string()),
+        font: None,
+        size: None,
+        rtl: false,
+        sticky: false,
+        sort_field: false,
+        guid: false,
+    };
+    let mut tags = false;
+    let mut skip = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("anki"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                options.name = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("font") {
+                options.font = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("size") {
+                options.size = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("rtl") {
+                options.rtl = parse_flag(&meta)?;
+            } else if meta.path.is_ident("sticky") {
+                options.sticky = parse_flag(&meta)?;
+            } else if meta.path.is_ident("sort_field") {
+                options.sort_field = parse_flag(&meta)?;
+            } else if meta.path.is_ident("guid") {
+                options.guid = parse_flag(&meta)?;
+            } else if meta.path.is_ident("tags") {
+                tags = parse_flag(&meta)?;
+            } else if meta.path.is_ident("skip") {
+                skip = parse_flag(&meta)?;
+            } else {
+                return Err(meta.error("unknown field attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    match (tags, skip) {
+        (true, true) => Err(syn::Error::new_spanned(
+            ident,
+            "a field cannot be both `tags` and `skip`",
+        )),
+        (true, false) => Ok(FieldKind::Tags),
+        (false, true) => Ok(FieldKind::Skip),
+        (false, false) => Ok(FieldKind::Field(options)),
+    }
+}
+
+/// Parses `flag` or `flag = true/false`
+fn parse_flag(meta: &ParseNestedMeta) -> syn::Result<bool> {
+    if meta.input.peek(syn::Token![=]) {
+        Ok(meta.value()?.parse::<LitBool>()?.value)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Converts a snake case identifier like `capital_city` to `CapitalCity`
+fn upper_camel_case(ident: &str) -> String {
+    ident
+        .trim_start_matches("r#")
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Returns the fields `template` refers to which are neither in `fields` nor filled in by Anki.
+/// Filters like `{{cloze:Text}}`, sections and cloze conditionals like `{{#c1}}` are understood.
+fn unknown_fields<'a>(template: &'a str, fields: &[&str]) -> Vec<&'a str> {
+    let mut unknown = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+        if tag.starts_with('!') {
+            continue;
+        }
+        let key = tag
+            .trim_start_matches(['#', '^', '/'])
+            .rsplit(':')
+            .next()
+            .unwrap_or("")
+            .trim();
+        let is_cloze = key
+            .strip_prefix('c')
+            .is_some_and(|number| number.parse::<u32>().is_ok())
+            && tag.len() > key.len();
+        if key.is_empty() || is_cloze || SPECIAL_FIELDS.contains(&key) || fields.contains(&key) {
+            continue;
+        }
+        unknown.push(key);
+    }
+    unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_names_are_upper_camel_case() {
+        assert_eq!(upper_camel_case("country"), "Country");
+        assert_eq!(upper_camel_case("capital_city"), "CapitalCity");
+        assert_eq!(upper_camel_case("r#type"), "Type");
+    }
+
+    #[test]
+    fn unknown_template_fields() {
+        let fields = ["Front", "Back", "Text"];
+        assert!(unknown_fields("{{Front}}<hr id=answer>{{ Back }}", &fields).is_empty());
+        assert!(unknown_fields("{{FrontSide}}{{Tags}}{{!comment}}", &fields).is_empty());
+        assert!(unknown_fields(
+            "{{#Back}}{{text:Back}}{{/Back}}{{^Front}}x{{/Front}}",
+            &fields
+        )
+        .is_empty());
+        assert!(unknown_fields("{{cloze:Text}}{{#c1}}one{{/c1}}", &fields).is_empty());
+        assert_eq!(
+            unknown_fields("{{Front}}{{Answer}}", &fields),
+            vec!["Answer"]
+        );
+        assert_eq!(unknown_fields("{{type:Answer}}", &fields), vec!["Answer"]);
+        assert_eq!(
+            unknown_fields("{{#Extra}}{{/Extra}}", &fields),
+            vec!["Extra", "Extra"]
+        );
+    }
+}
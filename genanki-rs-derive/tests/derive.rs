@@ -0,0 +1,95 @@
+use genanki_rs::{guid_for, AnkiNote, Deck, Error};
+use serde_json::json;
+
+#[derive(AnkiNote)]
+#[anki(
+    id = 1607392319,
+    name = "Country Capitals",
+    css = ".card { color: black; }"
+)]
+#[anki(template(
+    name = "Card 1",
+    qfmt = "What is the capital of {{Country}}?",
+    afmt = "{{FrontSide}}<hr id=answer>{{Capital}}{{#Capital Notes}}<br>{{Capital Notes}}{{/Capital Notes}}"
+))]
+struct Capital {
+    #[anki(guid)]
+    country: String,
+    #[anki(sort_field, font = "Arial", size = 24)]
+    capital: &'static str,
+    #[anki(rename = "Capital Notes", sticky)]
+    notes: String,
+    population: u64,
+    #[anki(tags)]
+    tags: Vec<&'static str>,
+    #[anki(skip)]
+    #[allow(dead_code)]
+    source: Option<String>,
+}
+
+#[derive(AnkiNote)]
+#[anki(id = 998877661, cloze)]
+#[anki(template(
+    name = "Cloze",
+    qfmt = "{{cloze:Text}}",
+    afmt = "{{cloze:Text}}<br>{{Extra}}"
+))]
+struct ClozeNote {
+    text: String,
+    extra: String,
+}
+
+fn capital() -> Capital {
+    Capital {
+        country: "France".to_string(),
+        capital: "Paris",
+        notes: "".to_string(),
+        population: 2_161_000,
+        tags: vec!["europe", "capitals"],
+        source: None,
+    }
+}
+
+#[test]
+fn model_is_generated_from_struct() {
+    let model = Capital::model();
+    assert_eq!(model.id, 1607392319);
+    assert_eq!(
+        model.field_names(),
+        vec!["Country", "Capital", "Capital Notes", "Population"]
+    );
+    assert_eq!(ClozeNote::model().field_names(), vec!["Text", "Extra"]);
+}
+
+#[test]
+fn note_is_generated_from_value() -> Result<(), Error> {
+    let note = capital().to_note()?;
+    assert_eq!(note.cards().len(), 1);
+    assert_eq!(note.fields(), ["France", "Paris", "", "2161000"]);
+    assert_eq!(note.field("Capital Notes"), Some(""));
+    assert_eq!(note.get_tags(), ["europe", "capitals"]);
+    assert_eq!(note.get_guid(), guid_for(&["France"]));
+    let value = serde_json::to_value(&note).unwrap();
+    assert_eq!(value["model"]["sort_field_index"], json!(1));
+    assert_eq!(value["model"]["fields"][1]["font"], json!("Arial"));
+
+    let mut deck = Deck::new(2059400110, "Country Capitals", "");
+    deck.add_note(note);
+    let out_file = tempfile::NamedTempFile::new()?;
+    deck.write_to_file(out_file.path().to_str().unwrap())?;
+    Ok(())
+}
+
+#[test]
+fn cloze_note_has_card_per_deletion() -> Result<(), Error> {
+    let note = ClozeNote {
+        text: "{{c1::Paris}} is the capital of {{c2::France}}".to_string(),
+        extra: "".to_string(),
+    }
+    .to_note()?;
+    assert_eq!(note.cards().len(), 2);
+    let model = serde_json::to_value(ClozeNote::model()).unwrap();
+    assert_eq!(model["type"], json!("cloze"));
+    assert_eq!(model["name"], json!("ClozeNote"));
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::{Error, Model, Note};
+
+/// A Rust type whose values are notes of one `Model`
+///
+/// Instead of passing field values as positional strings to [`Note::new`], the fields of a note
+/// type can be the fields of a struct. With the `derive` feature, `#[derive(AnkiNote)]` generates
+/// the model from the struct and checks at compile time that its templates only refer to fields
+/// of the struct:
+///
+/// ```rust,ignore
+/// use genanki_rs::{AnkiNote, Deck, Error};
+///
+/// #[derive(AnkiNote)]
+/// #[anki(id = 1607392319, name = "Country Capitals")]
+/// #[anki(template(
+///     name = "Card 1",
+///     qfmt = "What is the capital of {{Country}}?",
+///     afmt = "{{FrontSide}}<hr id=answer>{{Capital}}"
+/// ))]
+/// struct Capital {
+///     #[anki(guid)]
+///     country: String,
+///     capital: String,
+///     #[anki(tags)]
+///     tags: Vec<String>,
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let mut deck = Deck::new(2059400110, "Country Capitals", "");
+///     let capital = Capital { country: "France".into(), capital: "Paris".into(), tags: vec![] };
+///     deck.add_note(capital.to_note()?);
+///     Ok(())
+/// }
+/// ```
+///
+/// Field names are the struct field names in upper camel case, unless they are renamed with
+/// `#[anki(rename = "...")]`. See the documentation of the derive macro for all attributes.
+///
+/// The trait can also be implemented by hand:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, AnkiNote, Error, Model, Note};
+///
+/// struct Capital {
+///     country: String,
+///     capital: String,
+/// }
+///
+/// impl AnkiNote for Capital {
+///     fn model() -> Model {
+///         basic_model()
+///     }
+///
+///     fn to_note(&self) -> Result<Note, Error> {
+///         Note::new(Self::model(), vec![&self.country, &self.capital])
+///     }
+/// }
+/// ```
+pub trait AnkiNote {
+    /// Returns the model of the notes
+    fn model() -> Model;
+
+    /// Creates the note for `self`
+    fn to_note(&self) -> Result<Note, Error>;
+}
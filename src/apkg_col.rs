@@ -105,3 +105,23 @@ INSERT INTO col VALUES(
     '{}'
 );
 "#;
+
+/// `col` row of a schema 18 collection. The JSON columns are left empty because their content
+/// lives in dedicated tables in this schema version.
+pub const APKG_COL_V18: &str = r#"
+INSERT INTO col VALUES(
+    null,
+    1411124400,
+    1425279151694,
+    1425279151690,
+    18,
+    0,
+    0,
+    0,
+    '',
+    '',
+    '',
+    '',
+    ''
+);
+"#;
@@ -71,3 +71,138 @@ CREATE INDEX ix_cards_sched on cards (did, queue, due);
 CREATE INDEX ix_revlog_cid on revlog (cid);
 CREATE INDEX ix_notes_csum on notes (csum);
 "#;
+
+/// Schema 18 collection as written by Anki 2.1.50 and later. Notetypes, decks, deck options and
+/// tags live in their own tables instead of JSON blobs in `col`, and names use the `unicase`
+/// collation which has to be registered on the connection.
+pub const APKG_SCHEMA_V18: &str = r#"
+CREATE TABLE col (
+    id              integer primary key,
+    crt             integer not null,
+    mod             integer not null,
+    scm             integer not null,
+    ver             integer not null,
+    dty             integer not null,
+    usn             integer not null,
+    ls              integer not null,
+    conf            text not null,
+    models          text not null,
+    decks           text not null,
+    dconf           text not null,
+    tags            text not null
+);
+CREATE TABLE notes (
+    id              integer primary key,
+    guid            text not null,
+    mid             integer not null,
+    mod             integer not null,
+    usn             integer not null,
+    tags            text not null,
+    flds            text not null,
+    sfld            integer not null,
+    csum            integer not null,
+    flags           integer not null,
+    data            text not null
+);
+CREATE TABLE cards (
+    id              integer primary key,
+    nid             integer not null,
+    did             integer not null,
+    ord             integer not null,
+    mod             integer not null,
+    usn             integer not null,
+    type            integer not null,
+    queue           integer not null,
+    due             integer not null,
+    ivl             integer not null,
+    factor          integer not null,
+    reps            integer not null,
+    lapses          integer not null,
+    left            integer not null,
+    odue            integer not null,
+    odid            integer not null,
+    flags           integer not null,
+    data            text not null
+);
+CREATE TABLE revlog (
+    id              integer primary key,
+    cid             integer not null,
+    usn             integer not null,
+    ease            integer not null,
+    ivl             integer not null,
+    lastIvl         integer not null,
+    factor          integer not null,
+    time            integer not null,
+    type            integer not null
+);
+CREATE TABLE graves (
+    oid             integer not null,
+    type            integer not null,
+    usn             integer not null,
+    PRIMARY KEY (oid, type)
+) WITHOUT ROWID;
+CREATE TABLE deck_config (
+    id              integer primary key not null,
+    name            text not null collate unicase,
+    mtime_secs      integer not null,
+    usn             integer not null,
+    config          blob not null
+);
+CREATE TABLE config (
+    KEY             text not null primary key,
+    usn             integer not null,
+    mtime_secs      integer not null,
+    val             blob not null
+) WITHOUT ROWID;
+CREATE TABLE fields (
+    ntid            integer not null,
+    ord             integer not null,
+    name            text not null collate unicase,
+    config          blob not null,
+    PRIMARY KEY (ntid, ord)
+) WITHOUT ROWID;
+CREATE TABLE templates (
+    ntid            integer not null,
+    ord             integer not null,
+    name            text not null collate unicase,
+    mtime_secs      integer not null,
+    usn             integer not null,
+    config          blob not null,
+    PRIMARY KEY (ntid, ord)
+) WITHOUT ROWID;
+CREATE TABLE notetypes (
+    id              integer not null primary key,
+    name            text not null collate unicase,
+    mtime_secs      integer not null,
+    usn             integer not null,
+    config          blob not null
+);
+CREATE TABLE decks (
+    id              integer primary key not null,
+    name            text not null collate unicase,
+    mtime_secs      integer not null,
+    usn             integer not null,
+    common          blob not null,
+    kind            blob not null
+);
+CREATE TABLE tags (
+    tag             text not null primary key collate unicase,
+    usn             integer not null,
+    collapsed       boolean not null,
+    config          blob null
+) WITHOUT ROWID;
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+CREATE INDEX idx_graves_pending on graves (usn);
+CREATE UNIQUE INDEX idx_fields_name_ntid on fields (name, ntid);
+CREATE UNIQUE INDEX idx_templates_name_ntid on templates (name, ntid);
+CREATE INDEX idx_templates_usn on templates (usn);
+CREATE UNIQUE INDEX idx_notetypes_name on notetypes (name);
+CREATE INDEX idx_notetypes_usn on notetypes (usn);
+CREATE UNIQUE INDEX idx_decks_name on decks (name);
+"#;
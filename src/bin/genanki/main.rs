@@ -0,0 +1,181 @@
+//! `genanki` builds Anki packages from a JSON deck spec and CSV/TSV/JSON note data, and inspects
+//! existing packages. Build it with `cargo install genanki-rs --features cli`.
+
+mod spec;
+
+use clap::{Parser, Subcommand};
+use genanki_rs::{Deck, Package, Validator};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::spec::{Input, Spec};
+
+#[derive(Parser)]
+#[command(name = "genanki", version, about = "Builds and inspects Anki packages")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Builds an .apkg from a deck spec
+    Build {
+        /// JSON file describing the model, decks, note data and media directory
+        spec: PathBuf,
+        /// Package to write, overrides the output of the spec
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Unix timestamp used for ids and modification times, to make the output reproducible
+        #[arg(long)]
+        timestamp: Option<f64>,
+        /// Fails instead of skipping notes which cannot be loaded and ignoring missing media
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Checks a deck spec, its note data and media without writing a package
+    Validate {
+        /// JSON file describing the model, decks, note data and media directory
+        spec: PathBuf,
+    },
+    /// Lists the decks, notes and media files of an .apkg
+    Inspect {
+        /// Package to inspect
+        package: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Build {
+            spec,
+            output,
+            timestamp,
+            strict,
+        } => build(&spec, output, timestamp, strict),
+        Command::Validate { spec } => validate(&spec),
+        Command::Inspect { package } => inspect(&package),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Writes the package described by `spec`. Returns whether it was written.
+fn build(
+    spec: &Path,
+    output: Option<PathBuf>,
+    timestamp: Option<f64>,
+    strict: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let input = Spec::load(spec)?;
+    let output = output
+        .or_else(|| input.output.clone())
+        .ok_or("no output given, pass --output or set \"output\" in the spec")?;
+    let errors = report(&input);
+    if strict && errors > 0 {
+        eprintln!("{} errors, no package written", errors);
+        return Ok(false);
+    }
+
+    let note_count: usize = input.decks.iter().map(|deck| deck.notes().len()).sum();
+    let deck_count = input.decks.len();
+    let media_count = input.media_files.len();
+    let mut package = Package::new_with_media(input.decks, input.media_files);
+    let output_str = output
+        .to_str()
+        .ok_or_else(|| format!("invalid output path {}", output.display()))?;
+    match timestamp {
+        Some(timestamp) => package.write_to_file_timestamp(output_str, timestamp)?,
+        None => package.write_to_file(output_str)?,
+    }
+    println!(
+        "Wrote {} notes in {} decks and {} media files to {}",
+        note_count,
+        deck_count,
+        media_count,
+        output.display()
+    );
+    Ok(true)
+}
+
+/// Loads everything `spec` refers to and reports all problems. Returns whether there were none.
+fn validate(spec: &Path) -> Result<bool, Box<dyn Error>> {
+    let input = Spec::load(spec)?;
+    let errors = report(&input);
+    let note_count: usize = input.decks.iter().map(|deck| deck.notes().len()).sum();
+    println!(
+        "{} notes, {} media files, {} errors",
+        note_count,
+        input.media_files.len(),
+        errors
+    );
+    Ok(errors == 0)
+}
+
+/// Prints the notes which could not be loaded, the media issues and the note validation warnings
+/// of `input` to stderr, and returns the number of errors among them
+fn report(input: &Input) -> usize {
+    for error in &input.note_errors {
+        eprintln!("error: {}", error);
+    }
+    Validator::new()
+        .sink(|issue| eprintln!("warning: {}", issue))
+        .validate(&input.decks);
+    let issues =
+        Package::new_with_media(input.decks.clone(), input.media_files.clone()).check_media();
+    let mut errors = input.note_errors.len();
+    for issue in issues {
+        if issue.is_error() {
+            eprintln!("error: {}", issue);
+            errors += 1;
+        } else {
+            eprintln!("warning: {}", issue);
+        }
+    }
+    errors
+}
+
+fn inspect(path: &Path) -> Result<bool, Box<dyn Error>> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| format!("invalid package path {}", path.display()))?;
+    let package = Package::read_from_file(path_str)?;
+    for deck in package.decks() {
+        print_deck(deck, 0);
+    }
+    println!("{} media files", package.media_files().len());
+    for media_file in package.media_files() {
+        println!("  {}", media_file.name());
+    }
+    let issues = package.check_media();
+    for issue in &issues {
+        println!(
+            "{}: {}",
+            if issue.is_error() { "error" } else { "warning" },
+            issue
+        );
+    }
+    Ok(true)
+}
+
+fn print_deck(deck: &Deck, depth: usize) {
+    let cards: usize = deck.notes().iter().map(|note| note.cards().len()).sum();
+    println!(
+        "{}{} (id {}): {} notes, {} cards",
+        "  ".repeat(depth),
+        deck.name(),
+        deck.id(),
+        deck.notes().len(),
+        cards
+    );
+    for subdeck in deck.subdecks() {
+        print_deck(subdeck, depth + 1);
+    }
+}
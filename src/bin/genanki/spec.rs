@@ -0,0 +1,461 @@
+use genanki_rs::{
+    basic_and_reversed_card_model, basic_model, basic_optional_reversed_card_model,
+    basic_type_in_the_answer_model, cloze_model, Column, CsvImporter, Deck, MediaFile, Model, Note,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Declarative description of a package, read from a JSON file
+///
+/// ```json
+/// {
+///     "model": "basic",
+///     "decks": [{ "id": 2059400110, "name": "Country Capitals" }],
+///     "notes": "capitals.csv",
+///     "csv": { "tags_column": "Tags" },
+///     "media_dir": "media",
+///     "output": "capitals.apkg"
+/// }
+/// ```
+///
+/// Relative paths are resolved against the directory of the spec file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    /// Name of a builtin model or a custom model in the serde format of `Model`
+    model: serde_json::Value,
+    /// The first deck is the default deck, the others can be selected per note
+    decks: Vec<DeckSpec>,
+    /// CSV/TSV file (`.csv`, `.tsv`, `.txt`) or JSON file (`.json`) with the notes
+    notes: PathBuf,
+    #[serde(default)]
+    csv: CsvSpec,
+    /// Directory whose files are added to the package as media files
+    media_dir: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeckSpec {
+    id: i64,
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CsvSpec {
+    delimiter: Option<char>,
+    has_header: Option<bool>,
+    fields: Option<Vec<ColumnSpec>>,
+    tags_column: Option<ColumnSpec>,
+    guid_column: Option<ColumnSpec>,
+    deck_column: Option<ColumnSpec>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum ColumnSpec {
+    Name(String),
+    Index(usize),
+}
+
+impl From<ColumnSpec> for Column {
+    fn from(column: ColumnSpec) -> Self {
+        match column {
+            ColumnSpec::Name(name) => Column::Name(name),
+            ColumnSpec::Index(index) => Column::Index(index),
+        }
+    }
+}
+
+/// A note of a JSON notes file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoteSpec {
+    fields: FieldsSpec,
+    #[serde(default)]
+    tags: Vec<String>,
+    guid: Option<String>,
+    /// Name of one of the decks of the spec, default is the first deck
+    deck: Option<String>,
+}
+
+/// Field values in the order of the model fields, or by field name. Fields missing from a map are
+/// left empty.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldsSpec {
+    List(Vec<String>),
+    Map(BTreeMap<String, String>),
+}
+
+/// Everything a spec refers to, loaded and ready to be written into a package
+pub struct Input {
+    pub decks: Vec<Deck>,
+    pub media_files: Vec<MediaFile>,
+    /// Notes which could not be loaded, with the file and line or index they come from
+    pub note_errors: Vec<String>,
+    pub output: Option<PathBuf>,
+}
+
+impl Spec {
+    /// Reads the spec at `path` and loads the notes and media files it refers to
+    pub fn load(path: &Path) -> Result<Input, Box<dyn Error>> {
+        let spec: Spec = serde_json::from_reader(File::open(path)?)
+            .map_err(|e| format!("invalid spec {}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let model = to_model(&spec.model)?;
+        let decks: Vec<Deck> = spec
+            .decks
+            .iter()
+            .map(|deck| Deck::new(deck.id, &deck.name, &deck.description))
+            .collect();
+        if decks.is_empty() {
+            return Err("the spec does not contain any decks".into());
+        }
+
+        let notes_path = base.join(&spec.notes);
+        let is_json = notes_path.extension().is_some_and(|ext| ext == "json");
+        let (decks, note_errors) = if is_json {
+            load_json_notes(&notes_path, model, decks)?
+        } else {
+            spec.csv.load(&notes_path, model, decks)?
+        };
+
+        let media_files = match &spec.media_dir {
+            Some(dir) => load_media_dir(&base.join(dir))?,
+            None => vec![],
+        };
+        Ok(Input {
+            decks,
+            media_files,
+            note_errors,
+            output: spec.output.map(|output| base.join(output)),
+        })
+    }
+}
+
+fn to_model(spec: &serde_json::Value) -> Result<Model, Box<dyn Error>> {
+    let name = match spec.as_str() {
+        Some(name) => name,
+        None => {
+            return Model::deserialize(spec).map_err(|e| format!("invalid model: {}", e).into());
+        }
+    };
+    match name {
+        "basic" => Ok(basic_model()),
+        "basic-and-reversed-card" => Ok(basic_and_reversed_card_model()),
+        "basic-optional-reversed-card" => Ok(basic_optional_reversed_card_model()),
+        "basic-type-in-the-answer" => Ok(basic_type_in_the_answer_model()),
+        "cloze" => Ok(cloze_model()),
+        _ => Err(format!("unknown builtin model {:?}", name).into()),
+    }
+}
+
+impl CsvSpec {
+    fn load(
+        &self,
+        path: &Path,
+        model: Model,
+        decks: Vec<Deck>,
+    ) -> Result<(Vec<Deck>, Vec<String>), Box<dyn Error>> {
+        let mut decks = decks.into_iter();
+        let mut importer = CsvImporter::new(model, decks.next().expect("no default deck"));
+        for deck in decks {
+            importer = importer.with_deck(deck);
+        }
+        let is_tsv = path
+            .extension()
+            .is_some_and(|ext| ext == "tsv" || ext == "txt");
+        importer = importer.delimiter(self.delimiter.unwrap_or(if is_tsv { '\t' } else { ',' }));
+        if let Some(has_header) = self.has_header {
+            importer = importer.has_header(has_header);
+        }
+        if let Some(fields) = &self.fields {
+            importer = importer.fields(fields.iter().cloned());
+        }
+        if let Some(column) = &self.tags_column {
+            importer = importer.tags_column(column.clone());
+        }
+        if let Some(column) = &self.guid_column {
+            importer = importer.guid_column(column.clone());
+        }
+        if let Some(column) = &self.deck_column {
+            importer = importer.deck_column(column.clone());
+        }
+        let import = importer
+            .import_file(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let errors = import
+            .errors()
+            .iter()
+            .map(|error| format!("{}:{}: {}", path.display(), error.line(), error.error()))
+            .collect();
+        Ok((import.into_decks(), errors))
+    }
+}
+
+fn load_json_notes(
+    path: &Path,
+    model: Model,
+    mut decks: Vec<Deck>,
+) -> Result<(Vec<Deck>, Vec<String>), Box<dyn Error>> {
+    let notes: Vec<NoteSpec> = serde_json::from_reader(File::open(path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut errors = vec![];
+    for (index, spec) in notes.into_iter().enumerate() {
+        let deck_index = match &spec.deck {
+            Some(name) => match decks.iter().position(|deck| deck.name() == name) {
+                Some(deck_index) => deck_index,
+                None => {
+                    errors.push(format!(
+                        "{}: note {}: the spec has no deck called {:?}",
+                        path.display(),
+                        index,
+                        name
+                    ));
+                    continue;
+                }
+            },
+            None => 0,
+        };
+        let field_names = model.field_names();
+        let fields: Vec<&str> = match &spec.fields {
+            FieldsSpec::List(values) => values.iter().map(String::as_str).collect(),
+            FieldsSpec::Map(values) => {
+                if let Some(name) = values
+                    .keys()
+                    .find(|name| !field_names.contains(&name.as_str()))
+                {
+                    errors.push(format!(
+                        "{}: note {}: the model has no field called {:?}",
+                        path.display(),
+                        index,
+                        name
+                    ));
+                    continue;
+                }
+                field_names
+                    .iter()
+                    .map(|&name| values.get(name).map_or("", String::as_str))
+                    .collect()
+            }
+        };
+        if fields.len() != field_names.len() {
+            let e = genanki_rs::Error::ModelFieldCountMismatch(field_names.len(), fields.len());
+            errors.push(format!("{}: note {}: {}", path.display(), index, e));
+            continue;
+        }
+        let note = Note::new_with_options(
+            model.clone(),
+            fields,
+            None,
+            Some(spec.tags.iter().map(String::as_str).collect()),
+            spec.guid.as_deref(),
+        );
+        match note {
+            Ok(note) => decks[deck_index].add_note(note),
+            Err(e) => errors.push(format!("{}: note {}: {}", path.display(), index, e)),
+        }
+    }
+    Ok((decks, errors))
+}
+
+fn load_media_dir(dir: &Path) -> Result<Vec<MediaFile>, Box<dyn Error>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(MediaFile::from_path)
+        .collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn decks() -> Vec<Deck> {
+        vec![
+            Deck::new(1, "Capitals", ""),
+            Deck::new(2, "Capitals::Europe", ""),
+        ]
+    }
+
+    fn write(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn fronts(deck: &Deck) -> Vec<&str> {
+        deck.notes()
+            .iter()
+            .map(|note| note.fields()[0].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn builtin_and_custom_models() {
+        for (name, model) in [
+            ("basic", basic_model()),
+            ("basic-and-reversed-card", basic_and_reversed_card_model()),
+            (
+                "basic-optional-reversed-card",
+                basic_optional_reversed_card_model(),
+            ),
+            ("basic-type-in-the-answer", basic_type_in_the_answer_model()),
+            ("cloze", cloze_model()),
+        ] {
+            assert_eq!(to_model(&json!(name)).unwrap().id, model.id, "{}", name);
+        }
+        assert_eq!(
+            to_model(&json!("basic-reversed"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "unknown builtin model \"basic-reversed\""
+        );
+
+        let model = to_model(&json!({
+            "id": 1607392319,
+            "name": "Simple Model",
+            "fields": [{ "name": "Question" }, { "name": "Answer" }],
+            "templates": [{ "name": "Card 1", "qfmt": "{{Question}}", "afmt": "{{Answer}}" }]
+        }))
+        .unwrap();
+        assert_eq!(model.id, 1607392319);
+        assert_eq!(model.field_names(), ["Question", "Answer"]);
+        let error = to_model(&json!({ "id": 1, "name": "No fields" }))
+            .err()
+            .unwrap();
+        assert!(error.to_string().starts_with("invalid model: "));
+    }
+
+    #[test]
+    fn json_notes() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "notes.json",
+            r#"[
+                { "fields": ["France", "Paris"], "tags": ["europe"] },
+                { "fields": { "Front": "Italy", "Back": "Rome" }, "deck": "Capitals::Europe" },
+                { "fields": { "Back": "Madrid" }, "guid": "es" },
+                { "fields": ["Japan", "Tokyo"], "deck": "Asia" },
+                { "fields": { "Front": "Peru", "Capital": "Lima" } },
+                { "fields": ["Chile"] }
+            ]"#,
+        );
+        let (decks, errors) = load_json_notes(&path, basic_model(), decks()).unwrap();
+        assert_eq!(fronts(&decks[0]), ["France", ""]);
+        assert_eq!(decks[0].notes()[0].get_tags(), ["europe"]);
+        assert_eq!(decks[0].notes()[1].fields(), ["", "Madrid"]);
+        assert_eq!(decks[0].notes()[1].get_guid(), "es");
+        assert_eq!(fronts(&decks[1]), ["Italy"]);
+        let path = path.display();
+        assert_eq!(
+            errors,
+            [
+                format!("{}: note 3: the spec has no deck called \"Asia\"", path),
+                format!(
+                    "{}: note 4: the model has no field called \"Capital\"",
+                    path
+                ),
+                format!(
+                    "{}: note 5: {}",
+                    path,
+                    genanki_rs::Error::ModelFieldCountMismatch(2, 1)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_delimiter_follows_the_extension() {
+        let dir = TempDir::new().unwrap();
+        let load = |spec: &CsvSpec, name: &str, contents: &str| {
+            let path = write(&dir, name, contents);
+            let (decks, errors) = spec.load(&path, basic_model(), decks()).unwrap();
+            assert!(errors.is_empty(), "{:?}", errors);
+            decks[0].notes()[0].fields().to_vec()
+        };
+        let spec = CsvSpec::default();
+        let csv = "Front,Back\nFrance,\"Paris, Île-de-France\"\n";
+        assert_eq!(
+            load(&spec, "notes.csv", csv),
+            ["France", "Paris, Île-de-France"]
+        );
+        let tsv = "Front\tBack\nFrance,Italy\tParis,Rome\n";
+        assert_eq!(
+            load(&spec, "notes.tsv", tsv),
+            ["France,Italy", "Paris,Rome"]
+        );
+        assert_eq!(
+            load(&spec, "notes.txt", tsv),
+            ["France,Italy", "Paris,Rome"]
+        );
+
+        let spec = CsvSpec {
+            delimiter: Some(';'),
+            has_header: Some(false),
+            ..CsvSpec::default()
+        };
+        assert_eq!(
+            load(&spec, "notes.csv", "France;Paris\n"),
+            ["France", "Paris"]
+        );
+    }
+
+    #[test]
+    fn spec_paths_are_relative_to_the_spec() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("media")).unwrap();
+        write(&dir, "media/flag.png", "flag");
+        write(
+            &dir,
+            "capitals.csv",
+            "Front,Back,Tags\n<img src=\"flag.png\">France,Paris,europe\n",
+        );
+        let spec = write(
+            &dir,
+            "spec.json",
+            r#"{
+                "model": "basic",
+                "decks": [{ "id": 2059400110, "name": "Country Capitals" }],
+                "notes": "capitals.csv",
+                "csv": { "tags_column": "Tags" },
+                "media_dir": "media",
+                "output": "capitals.apkg"
+            }"#,
+        );
+        let input = Spec::load(&spec).unwrap();
+        assert!(input.note_errors.is_empty());
+        assert_eq!(input.decks[0].notes()[0].get_tags(), ["europe"]);
+        assert_eq!(input.media_files[0].name(), "flag.png");
+        assert_eq!(input.output, Some(dir.path().join("capitals.apkg")));
+
+        let spec = write(
+            &dir,
+            "empty.json",
+            r#"{ "model": "basic", "decks": [], "notes": "x.csv" }"#,
+        );
+        assert_eq!(
+            Spec::load(&spec).err().unwrap().to_string(),
+            "the spec does not contain any decks"
+        );
+    }
+}
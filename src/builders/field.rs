@@ -1,4 +1,5 @@
 use crate::db_entries::Fld;
+use serde::{Deserialize, Serialize};
 
 /// Field to be fed into a `Model`.
 ///
@@ -18,12 +19,20 @@ use crate::db_entries::Fld;
 /// * `rtl` - `false`
 /// * `font` - `Liberation Sans`
 /// * `size` - `20`
-#[derive(Clone)]
+///
+/// Fields can be serialized and deserialized with serde, as a table with the name and the options
+/// which differ from the defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Field {
     name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     sticky: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     rtl: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     font: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     size: Option<i64>,
 }
 
@@ -64,16 +73,29 @@ impl Field {
     }
 }
 
-impl Into<Fld> for Field {
-    fn into(self) -> Fld {
+impl From<Field> for Fld {
+    fn from(field: Field) -> Self {
         Fld {
-            name: self.name.to_string(),
+            name: field.name,
             media: vec![],
-            sticky: self.sticky.unwrap_or(false),
-            rtl: self.rtl.unwrap_or(false),
+            sticky: field.sticky.unwrap_or(false),
+            rtl: field.rtl.unwrap_or(false),
             ord: 0,
-            font: self.font.unwrap_or("Liberation Sans".to_string()),
-            size: self.size.unwrap_or(20),
+            font: field.font.unwrap_or_else(|| "Liberation Sans".to_string()),
+            size: field.size.unwrap_or(20),
+        }
+    }
+}
+
+impl From<Fld> for Field {
+    fn from(field: Fld) -> Self {
+        let default = Fld::from(Field::new(""));
+        Field {
+            sticky: Some(field.sticky).filter(|&sticky| sticky != default.sticky),
+            rtl: Some(field.rtl).filter(|&rtl| rtl != default.rtl),
+            font: Some(field.font).filter(|font| *font != default.font),
+            size: Some(field.size).filter(|&size| size != default.size),
+            name: field.name,
         }
     }
 }
@@ -1,5 +1,7 @@
 mod field;
+mod note;
 mod template;
 
 pub use field::Field;
+pub use note::NoteBuilder;
 pub use template::Template;
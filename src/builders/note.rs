@@ -0,0 +1,150 @@
+use crate::{Error, Model, Note};
+
+/// Builder for a `Note` which sets the fields by name instead of by position
+///
+/// Fields which are not set are empty, and setting a field the model does not have makes
+/// [`NoteBuilder::build`] fail with [`Error::UnknownField`].
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, Error, Note};
+///
+/// # fn main() -> Result<(), Error> {
+/// let note = Note::builder(basic_model())
+///     .field("Back", "Paris")
+///     .field("Front", "Capital of France")
+///     .with_tag("geography")
+///     .build()?;
+/// assert_eq!(note.fields(), ["Capital of France", "Paris"]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct NoteBuilder {
+    model: Model,
+    fields: Vec<String>,
+    unknown_field: Option<String>,
+    sort_field: Option<String>,
+    tags: Vec<String>,
+    guid: Option<String>,
+}
+
+impl NoteBuilder {
+    /// Creates a builder for a note of `model` with all fields empty
+    pub fn new(model: Model) -> Self {
+        let fields = vec![String::new(); model.field_names().len()];
+        Self {
+            model,
+            fields,
+            unknown_field: None,
+            sort_field: None,
+            tags: vec![],
+            guid: None,
+        }
+    }
+
+    /// Sets the value of the field called `name`, replacing a value set before
+    pub fn field(mut self, name: &str, value: impl ToString) -> Self {
+        match self.model.field_names().iter().position(|&n| n == name) {
+            Some(index) => self.fields[index] = value.to_string(),
+            None => {
+                self.unknown_field.get_or_insert_with(|| name.to_string());
+            }
+        }
+        self
+    }
+
+    /// Sets the value the note is sorted by in the Anki browser, see [`Note::sort_field`]
+    pub fn sort_field(mut self, sort_field: impl ToString) -> Self {
+        self.sort_field = Some(sort_field.to_string());
+        self
+    }
+
+    /// Sets or replaces tags with the provided ones
+    pub fn tags(mut self, tags: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.tags = tags.into_iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    /// Adds an additional tag
+    pub fn with_tag(mut self, tag: impl ToString) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// Sets the GUID of the note, default is the hash of all fields
+    pub fn guid(mut self, guid: impl ToString) -> Self {
+        self.guid = Some(guid.to_string());
+        self
+    }
+
+    /// Creates the note
+    ///
+    /// Returns `Err` if a field was set which the model does not have, or if the fields or tags
+    /// are invalid
+    pub fn build(self) -> Result<Note, Error> {
+        if let Some(name) = self.unknown_field {
+            return Err(Error::UnknownField(name));
+        }
+        Note::new_with_options(
+            self.model,
+            self.fields.iter().map(String::as_str).collect(),
+            self.sort_field.as_deref(),
+            Some(self.tags.iter().map(String::as_str).collect()),
+            self.guid.as_deref(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, guid_for};
+
+    #[test]
+    fn unset_fields_are_empty() {
+        let note = NoteBuilder::new(basic_model())
+            .field("Back", "Paris")
+            .build()
+            .unwrap();
+        assert_eq!(note.fields(), ["", "Paris"]);
+        assert_eq!(note.field("Front"), Some(""));
+        assert_eq!(note.get_guid(), guid_for(&["", "Paris"]));
+    }
+
+    #[test]
+    fn later_values_replace_earlier_ones() {
+        let note = NoteBuilder::new(basic_model())
+            .field("Front", "Capital of Germany")
+            .field("Back", "Berlin")
+            .field("Front", "Capital of France")
+            .field("Back", "Paris")
+            .tags(["a", "b"])
+            .with_tag("c")
+            .guid("custom")
+            .build()
+            .unwrap();
+        assert_eq!(note.fields(), ["Capital of France", "Paris"]);
+        assert_eq!(note.get_tags(), ["a", "b", "c"]);
+        assert_eq!(note.get_guid(), "custom");
+    }
+
+    #[test]
+    fn unknown_field_is_an_error() {
+        let result = NoteBuilder::new(basic_model())
+            .field("Question", "Capital of France")
+            .field("Answer", "Paris")
+            .build();
+        assert!(matches!(result, Err(Error::UnknownField(name)) if name == "Question"));
+    }
+
+    #[test]
+    fn invalid_tags_are_an_error() {
+        let result = NoteBuilder::new(basic_model())
+            .field("Front", "Capital of France")
+            .with_tag("two words")
+            .build();
+        assert!(result.is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::db_entries::Tmpl;
+use serde::{Deserialize, Serialize};
 
 /// Template to be fed into a `Model`.
 /// A Template represents the structure of `Notes` (Flashcards) in the deck and can be created using
@@ -12,13 +13,21 @@ use crate::db_entries::Tmpl;
 /// let template2 = Template::new("Card 2").qfmt("{{Back}}").afmt("{{FrontSide}}\n\n<hr id=answer>\n\n{{Front}}");
 /// ```
 ///
-#[derive(Clone)]
+/// Templates can be serialized and deserialized with serde, as a table with the name and the
+/// formats which are set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Template {
     name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     qfmt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     did: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     bafmt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     afmt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     bqfmt: Option<String>,
 }
 
@@ -66,16 +75,30 @@ impl Template {
     }
 }
 
-impl Into<Tmpl> for Template {
-    fn into(self) -> Tmpl {
+impl From<Template> for Tmpl {
+    fn from(template: Template) -> Self {
         Tmpl {
-            name: self.name,
-            qfmt: self.qfmt.unwrap_or("".to_string()),
-            did: self.did,
-            bafmt: self.bafmt.unwrap_or("".to_string()),
-            afmt: self.afmt.unwrap_or("".to_string()),
+            name: template.name,
+            qfmt: template.qfmt.unwrap_or_default(),
+            did: template.did,
+            bafmt: template.bafmt.unwrap_or_default(),
+            afmt: template.afmt.unwrap_or_default(),
             ord: 0,
-            bqfmt: self.bqfmt.unwrap_or("".to_string()),
+            bqfmt: template.bqfmt.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<Tmpl> for Template {
+    fn from(template: Tmpl) -> Self {
+        let non_empty = |format: String| Some(format).filter(|format| !format.is_empty());
+        Template {
+            name: template.name,
+            qfmt: non_empty(template.qfmt),
+            did: template.did,
+            bafmt: non_empty(template.bafmt),
+            afmt: non_empty(template.afmt),
+            bqfmt: non_empty(template.bqfmt),
         }
     }
 }
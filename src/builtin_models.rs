@@ -281,6 +281,67 @@ pub fn cloze_model() -> Model {
     )
 }
 
+/// Front and back side of [`image_occlusion_model`], which only differ in the button to toggle
+/// the masks and the `Back Extra` field
+const IMAGE_OCCLUSION_QFMT: &str = "{{#Header}}<div>{{Header}}</div>{{/Header}}
+<div style=\"display: none\">{{cloze:Occlusion}}</div>
+<div id=\"err\"></div>
+<div id=\"image-occlusion-container\">
+    {{Image}}
+    <canvas id=\"image-occlusion-canvas\"></canvas>
+</div>
+<script>
+try {
+    anki.imageOcclusion.setup();
+} catch (exc) {
+    document.getElementById(\"err\").innerHTML = `Error loading image occlusion. Is your Anki version up to date?<br><br>${exc}`;
+}
+</script>
+";
+
+/// Returns a cloze `Model` for Image Occlusion notes like the one built into Anki 23.10 and
+/// later, whose script draws the masks over the image.
+///
+/// Its fields are `Occlusion`, with one cloze deletion per shape like
+/// `{{c1::image-occlusion:rect:left=.1:top=.2:width=.3:height=.4:oi=1}}`, `Image`, with the
+/// `<img>` tag of the occluded image, `Header`, `Back Extra` and `Comments`. Notes are easiest
+/// created with [`ImageOcclusion`](crate::ImageOcclusion).
+///
+/// ```rust
+/// use genanki_rs::image_occlusion_model;
+/// let my_model = image_occlusion_model();
+/// ```
+pub fn image_occlusion_model() -> Model {
+    Model::new_with_options(
+        1685470720,
+        "Image Occlusion (genanki)",
+        vec![
+            Field::new("Occlusion").font("Arial"),
+            Field::new("Image").font("Arial"),
+            Field::new("Header").font("Arial"),
+            Field::new("Back Extra").font("Arial"),
+            Field::new("Comments").font("Arial"),
+        ],
+        vec![Template::new("Image Occlusion")
+            .qfmt(IMAGE_OCCLUSION_QFMT)
+            .afmt(&format!(
+                "{}\n<div><button id=\"toggle\">Toggle Masks</button></div>\n\
+                 {{{{#Back Extra}}}}<div>{{{{Back Extra}}}}</div>{{{{/Back Extra}}}}\n",
+                IMAGE_OCCLUSION_QFMT
+            ))],
+        Some(
+            "#image-occlusion-canvas {\n --inactive-shape-color: #ffeba2;\n --active-shape-color: #ff8e8e;\n \
+             --inactive-shape-border: 1px #212121;\n --active-shape-border: 1px #212121;\n \
+             --highlight-shape-color: #ff8e8e00;\n --highlight-shape-border: 1px #ff8e8e;\n}\n\n\
+             .card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n",
+        ),
+        Some(ModelType::Cloze),
+        None,
+        None,
+        None,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::{Deck, Note};
@@ -318,6 +379,19 @@ mod tests {
             )
             .unwrap(),
         );
+        my_deck.add_note(
+            Note::new(
+                image_occlusion_model(),
+                vec![
+                    "{{c1::image-occlusion:rect:left=.1:top=.2:width=.3:height=.4:oi=1}}",
+                    "<img src=\"map.png\">",
+                    "Capitals",
+                    "",
+                    "",
+                ],
+            )
+            .unwrap(),
+        );
 
         let out_file = NamedTempFile::new().unwrap().into_temp_path();
         my_deck.write_to_file(out_file.to_str().unwrap()).unwrap();
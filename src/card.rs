@@ -1,23 +1,366 @@
 use rusqlite::{params, Transaction};
+use serde::{Deserialize, Serialize};
 use std::ops::RangeFrom;
 
+use crate::collection::COLLECTION_CREATION_TIME;
+use crate::revlog::ReviewLogEntry;
 use crate::{error::database_error, Error};
 
-#[derive(Clone)]
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// The minimum ease Anki's scheduler allows for review cards
+const MIN_EASE: f64 = 1.3;
+
+/// Where a card is in its learning process
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CardType {
+    /// The card has never been studied
+    New,
+    /// The card is in its initial learning steps
+    Learning,
+    /// The card graduated from learning and is reviewed in intervals of days
+    Review,
+    /// The card was forgotten during a review and is in its relearning steps
+    Relearning,
+}
+
+/// Scheduling state of a card, e.g. to migrate the progress of learners from other spaced
+/// repetition tools.
+///
+/// Every card starts out as a new card, see [`Note::with_card_state`](crate::Note::with_card_state)
+/// to change that. Due dates are Unix timestamps in seconds, intervals are given in days.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::CardState;
+///
+/// // Due on 2021-01-01, reviewed 5 times of which it was forgotten once
+/// let state = CardState::review(1609459200, 30, 2.5).reps(5).lapses(1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CardState {
+    card_type: CardType,
+    due: i64,
+    interval: i64,
+    ease: f64,
+    remaining_steps: i64,
+    reps: i64,
+    lapses: i64,
+}
+
+impl Default for CardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CardState {
+    /// A new card, which has never been studied
+    pub fn new() -> Self {
+        Self {
+            card_type: CardType::New,
+            due: 0,
+            interval: 0,
+            ease: 0.0,
+            remaining_steps: 0,
+            reps: 0,
+            lapses: 0,
+        }
+    }
+
+    /// A card in its initial learning steps, which is `due` at a timestamp and has
+    /// `remaining_steps` learning steps left before it graduates. It has been reviewed once.
+    pub fn learning(due: i64, remaining_steps: i64) -> Self {
+        Self {
+            card_type: CardType::Learning,
+            due,
+            remaining_steps,
+            reps: 1,
+            ..Self::new()
+        }
+    }
+
+    /// A card which is reviewed with an `interval` in days and an `ease`, e.g. `2.5` for 250%,
+    /// and is next `due` on the day of a timestamp. It has been reviewed once.
+    pub fn review(due: i64, interval: i64, ease: f64) -> Self {
+        Self {
+            card_type: CardType::Review,
+            due,
+            interval,
+            ease,
+            reps: 1,
+            ..Self::new()
+        }
+    }
+
+    /// A forgotten review card, which is `due` at a timestamp and has `remaining_steps`
+    /// relearning steps left. `interval` and `ease` are used once it is reviewed again. It has
+    /// been reviewed twice and forgotten once.
+    pub fn relearning(due: i64, interval: i64, ease: f64, remaining_steps: i64) -> Self {
+        Self {
+            card_type: CardType::Relearning,
+            due,
+            interval,
+            ease,
+            remaining_steps,
+            reps: 2,
+            lapses: 1,
+        }
+    }
+
+    /// Sets the number of times the card has been reviewed
+    pub fn reps(self, reps: i64) -> Self {
+        Self { reps, ..self }
+    }
+
+    /// Sets the number of times the card has been forgotten
+    pub fn lapses(self, lapses: i64) -> Self {
+        Self { lapses, ..self }
+    }
+
+    /// Returns the type of the card
+    pub fn card_type(&self) -> CardType {
+        self.card_type
+    }
+
+    /// Returns when the card is due as a Unix timestamp, `0` for new cards
+    pub fn due(&self) -> i64 {
+        self.due
+    }
+
+    /// Returns the interval of the card in days
+    pub fn interval(&self) -> i64 {
+        self.interval
+    }
+
+    /// Returns the ease of the card
+    pub fn ease(&self) -> f64 {
+        self.ease
+    }
+
+    /// Returns the number of learning or relearning steps left
+    pub fn remaining_steps(&self) -> i64 {
+        self.remaining_steps
+    }
+
+    /// Returns the number of times the card has been reviewed
+    pub fn get_reps(&self) -> i64 {
+        self.reps
+    }
+
+    /// Returns the number of times the card has been forgotten
+    pub fn get_lapses(&self) -> i64 {
+        self.lapses
+    }
+
+    /// Checks that the state is consistent with what Anki's scheduler expects
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::InvalidCardState(reason.to_string()));
+        if self.reps < 0 || self.lapses < 0 {
+            return invalid("reps and lapses must not be negative");
+        }
+        if self.lapses > self.reps {
+            return invalid("a card cannot be forgotten more often than it was reviewed");
+        }
+        match self.card_type {
+            CardType::New => {
+                if self.reps != 0 {
+                    return invalid("new cards cannot have been reviewed");
+                }
+            }
+            CardType::Learning | CardType::Relearning => {
+                if self.remaining_steps < 1 {
+                    return invalid("learning cards need at least one remaining step");
+                }
+                if self.reps < 1 {
+                    return invalid("learning cards must have been reviewed at least once");
+                }
+            }
+            CardType::Review => {
+                if self.reps < 1 {
+                    return invalid("review cards must have been reviewed at least once");
+                }
+            }
+        }
+        if matches!(self.card_type, CardType::Review | CardType::Relearning) {
+            if self.interval < 1 {
+                return invalid("the interval of review cards must be at least one day");
+            }
+            if self.ease < MIN_EASE {
+                return invalid("the ease of review cards must be at least 1.3");
+            }
+        }
+        if self.card_type == CardType::Relearning && self.lapses < 1 {
+            return invalid("relearning cards must have been forgotten at least once");
+        }
+        if self.card_type != CardType::New && self.due < COLLECTION_CREATION_TIME {
+            return invalid("the due date lies before the creation of the collection");
+        }
+        Ok(())
+    }
+
+    /// Returns the `type`, `queue`, `due`, `ivl`, `factor` and `left` columns of the card
+    fn to_columns(&self) -> (i64, i64, i64, i64, i64, i64) {
+        let factor = (self.ease * 1000.0).round() as i64;
+        // `left` holds the steps left today in the thousands and the steps left until graduation
+        let left = self.remaining_steps * 1000 + self.remaining_steps;
+        let day = (self.due - COLLECTION_CREATION_TIME) / SECONDS_PER_DAY;
+        // Learning cards due after the first day of the collection are interday learning cards,
+        // which are due on a day like review cards instead of at a timestamp
+        let (learning_queue, learning_due) = if day > 0 { (3, day) } else { (1, self.due) };
+        match self.card_type {
+            CardType::New => (0, 0, 0, 0, 0, 0),
+            CardType::Learning => (1, learning_queue, learning_due, 0, 0, left),
+            CardType::Review => (2, 2, day, self.interval, factor, 0),
+            CardType::Relearning => (3, learning_queue, learning_due, self.interval, factor, left),
+        }
+    }
+
+    /// Rebuilds the state from the columns of a card of a collection created at `creation_time`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_columns(
+        card_type: i64,
+        due: i64,
+        interval: i64,
+        factor: i64,
+        reps: i64,
+        lapses: i64,
+        left: i64,
+        creation_time: i64,
+    ) -> Self {
+        let card_type = match card_type {
+            1 => CardType::Learning,
+            2 => CardType::Review,
+            3 => CardType::Relearning,
+            _ => CardType::New,
+        };
+        let due = match card_type {
+            CardType::New => 0,
+            CardType::Review => creation_time + due * SECONDS_PER_DAY,
+            // Interday learning cards store a day number instead of a timestamp
+            CardType::Learning | CardType::Relearning if due < creation_time => {
+                creation_time + due * SECONDS_PER_DAY
+            }
+            CardType::Learning | CardType::Relearning => due,
+        };
+        Self {
+            card_type,
+            due,
+            interval,
+            ease: factor as f64 / 1000.0,
+            remaining_steps: left % 1000,
+            reps,
+            lapses,
+        }
+    }
+}
+
+/// A colored flag which can be set on a card in the browser
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    Red = 1,
+    Orange = 2,
+    Green = 3,
+    Blue = 4,
+    Pink = 5,
+    Turquoise = 6,
+    Purple = 7,
+}
+
+impl Flag {
+    fn from_flags(flags: i64) -> Option<Self> {
+        match flags & 0b111 {
+            1 => Some(Flag::Red),
+            2 => Some(Flag::Orange),
+            3 => Some(Flag::Green),
+            4 => Some(Flag::Blue),
+            5 => Some(Flag::Pink),
+            6 => Some(Flag::Turquoise),
+            7 => Some(Flag::Purple),
+            _ => None,
+        }
+    }
+}
+
+/// A card generated from one template of the model of a `Note`.
+///
+/// Cards are created together with their note. They can be changed through the note, e.g. with
+/// [`Note::suspend_card`](crate::Note::suspend_card).
+#[derive(Clone, Debug)]
 pub struct Card {
-    pub ord: i64,
-    pub suspend: bool,
+    pub(crate) ord: i64,
+    pub(crate) suspend: bool,
+    pub(crate) bury: bool,
+    pub(crate) flag: Option<Flag>,
+    pub(crate) deck_id: Option<i64>,
+    pub(crate) state: CardState,
+    pub(crate) reviews: Vec<ReviewLogEntry>,
 }
 
 impl Card {
-    pub fn new(ord: i64, suspend: bool) -> Self {
-        Self { ord, suspend }
+    pub(crate) fn new(ord: i64, suspend: bool) -> Self {
+        Self {
+            ord,
+            suspend,
+            bury: false,
+            flag: None,
+            deck_id: None,
+            state: CardState::new(),
+            reviews: vec![],
+        }
     }
-    #[allow(dead_code)]
+
+    /// Returns the ordinal of the template the card was generated from. For cloze notes, `c1`
+    /// has the ordinal 0.
     pub fn ord(&self) -> i64 {
         self.ord
     }
-    pub fn write_to_db(
+
+    /// Returns whether the card is suspended
+    pub fn is_suspended(&self) -> bool {
+        self.suspend
+    }
+
+    /// Returns whether the card is buried
+    pub fn is_buried(&self) -> bool {
+        self.bury
+    }
+
+    /// Returns the flag of the card, if any
+    pub fn flag(&self) -> Option<Flag> {
+        self.flag
+    }
+
+    /// Returns the id of the deck the card is in, if it differs from the deck of its note
+    pub fn deck_id(&self) -> Option<i64> {
+        self.deck_id
+    }
+
+    /// Returns the scheduling state of the card
+    pub fn state(&self) -> &CardState {
+        &self.state
+    }
+
+    /// Returns the review history of the card
+    pub fn reviews(&self) -> &[ReviewLogEntry] {
+        &self.reviews
+    }
+
+    /// Rebuilds a card from its `queue`, `flags` and `did` columns
+    pub(crate) fn from_columns(ord: i64, queue: i64, flags: i64, deck_id: Option<i64>) -> Self {
+        Self {
+            bury: queue == -2 || queue == -3,
+            flag: Flag::from_flags(flags),
+            deck_id,
+            ..Self::new(ord, queue == -1)
+        }
+    }
+
+    pub(crate) fn write_to_db(
         &self,
         transaction: &Transaction,
         timestamp: f64,
@@ -25,32 +368,139 @@ impl Card {
         note_id: usize,
         id_gen: &mut RangeFrom<usize>,
     ) -> Result<(), Error> {
-        let queue = if self.suspend { -1 } else { 0 };
+        let (card_type, queue, due, ivl, factor, left) = self.state.to_columns();
+        let queue = if self.suspend {
+            -1
+        } else if self.bury {
+            -2
+        } else {
+            queue
+        };
+        let card_id = id_gen.next().expect("Should always have a next id");
         transaction
             .execute(
                 "INSERT INTO cards VALUES(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);",
                 params![
-                    id_gen.next(),    // id
-                    note_id,          // nid
-                    deck_id,          // did
-                    self.ord,         // ord
-                    timestamp as i64, // mod
-                    -1,               // usn
-                    0,                // type (=0 for non-Cloze)
-                    queue,            // queue
-                    0,                // due
-                    0,                // ivl
-                    0,                // factor
-                    0,                // reps
-                    0,                // lapses
-                    0,                // left
-                    0,                // odue
-                    0,                // odid
-                    0,                // flags
-                    "",               // data
+                    card_id,                                 // id
+                    note_id,                                 // nid
+                    self.deck_id.unwrap_or(deck_id),         // did
+                    self.ord,                                // ord
+                    timestamp as i64,                        // mod
+                    -1,                                      // usn
+                    card_type,                               // type
+                    queue,                                   // queue
+                    due,                                     // due
+                    ivl,                                     // ivl
+                    factor,                                  // factor
+                    self.state.reps,                         // reps
+                    self.state.lapses,                       // lapses
+                    left,                                    // left
+                    0,                                       // odue
+                    0,                                       // odid
+                    self.flag.map_or(0, |flag| flag as i64), // flags
+                    "",                                      // data
                 ],
             )
             .map_err(database_error)?;
+        for review in &self.reviews {
+            review.write_to_db(transaction, card_id)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUE: i64 = 1_600_000_000;
+
+    #[test]
+    fn valid_states() {
+        assert!(CardState::new().validate().is_ok());
+        assert!(CardState::learning(DUE, 2).validate().is_ok());
+        assert!(CardState::review(DUE, 10, 2.5)
+            .reps(8)
+            .lapses(2)
+            .validate()
+            .is_ok());
+        assert!(CardState::relearning(DUE, 1, 1.3, 1).validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_states() {
+        let invalid = [
+            CardState::new().reps(1),
+            CardState::learning(DUE, 0),
+            CardState::learning(DUE, 1).reps(0),
+            CardState::review(DUE, 0, 2.5),
+            CardState::review(DUE, 10, 1.0),
+            CardState::review(DUE, 10, 2.5).reps(1).lapses(2),
+            CardState::review(0, 10, 2.5),
+            CardState::relearning(DUE, 10, 2.5, 1).lapses(0),
+            CardState::relearning(DUE, 10, 2.5, 1).reps(-1),
+        ];
+        for state in &invalid {
+            assert!(
+                matches!(state.validate(), Err(Error::InvalidCardState(_))),
+                "{:?} should be invalid",
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn columns_round_trip() {
+        let day_100 = COLLECTION_CREATION_TIME + 100 * SECONDS_PER_DAY;
+        let states = [
+            CardState::new(),
+            CardState::learning(COLLECTION_CREATION_TIME + 600, 2),
+            CardState::learning(day_100, 2),
+            CardState::review(day_100, 30, 2.3).reps(4),
+            CardState::relearning(COLLECTION_CREATION_TIME + 600, 3, 1.7, 1)
+                .reps(9)
+                .lapses(3),
+            CardState::relearning(day_100, 3, 1.7, 1).reps(9).lapses(3),
+        ];
+        for state in &states {
+            let (card_type, _, due, ivl, factor, left) = state.to_columns();
+            let read = CardState::from_columns(
+                card_type,
+                due,
+                ivl,
+                factor,
+                state.reps,
+                state.lapses,
+                left,
+                COLLECTION_CREATION_TIME,
+            );
+            assert_eq!(&read, state);
+        }
+        assert_eq!(
+            CardState::review(day_100, 30, 2.3).to_columns(),
+            (2, 2, 100, 30, 2300, 0)
+        );
+    }
+
+    #[test]
+    fn learning_columns() {
+        let intraday = COLLECTION_CREATION_TIME + 600;
+        let interday = COLLECTION_CREATION_TIME + 100 * SECONDS_PER_DAY + 600;
+        assert_eq!(
+            CardState::learning(intraday, 2).to_columns(),
+            (1, 1, intraday, 0, 0, 2002)
+        );
+        assert_eq!(
+            CardState::learning(interday, 2).to_columns(),
+            (1, 3, 100, 0, 0, 2002)
+        );
+        assert_eq!(
+            CardState::relearning(intraday, 3, 1.7, 1).to_columns(),
+            (3, 1, intraday, 3, 1700, 1001)
+        );
+        assert_eq!(
+            CardState::relearning(interday, 3, 1.7, 1).to_columns(),
+            (3, 3, 100, 3, 1700, 1001)
+        );
+    }
+}
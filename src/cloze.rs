@@ -0,0 +1,293 @@
+//! Parses cloze deletions like `{{c1::Paris::city}}` the way Anki does.
+//!
+//! Deletions can be nested up to a depth of 10, and the text after `::` in a deletion is its
+//! hint. A `}}` always closes the innermost open deletion, also inside a hint. Deletions which
+//! are never closed are left out, and `}}` outside of any deletion is kept as text.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// Anki ignores cloze openings nested deeper than this
+const MAX_NESTING: usize = 10;
+
+/// A cloze deletion in the text of a field, e.g. `{{c1::Paris::city}}`
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::parse_clozes;
+///
+/// let text = "{{c1::Paris}} is the capital of {{c2::France {{c3::!}}::country}}";
+/// let clozes = parse_clozes(text);
+/// assert_eq!(clozes[0].ordinal(), 1);
+/// assert_eq!(clozes[0].text(), "Paris");
+/// assert_eq!(clozes[1].text(), "France !");
+/// assert_eq!(clozes[1].hint(), Some("country"));
+/// assert_eq!(clozes[1].nested()[0].ordinal(), 3);
+/// assert_eq!(&text[clozes[1].span()], "{{c2::France {{c3::!}}::country}}");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClozeDeletion {
+    ordinal: u16,
+    span: Range<usize>,
+    nodes: Vec<Node>,
+    hint: Option<String>,
+}
+
+/// Part of a text with cloze deletions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Node {
+    Text(String),
+    Cloze(ClozeDeletion),
+}
+
+impl ClozeDeletion {
+    /// Returns the number of the deletion, e.g. `1` for `{{c1::...}}`. Each number becomes a
+    /// card, `c0` and `c1` both the first one.
+    pub fn ordinal(&self) -> u16 {
+        self.ordinal
+    }
+
+    /// Returns the text which is hidden on the question side, including the text of nested
+    /// deletions
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.nodes {
+            match node {
+                Node::Text(part) => text.push_str(part),
+                Node::Cloze(cloze) => text.push_str(&cloze.text()),
+            }
+        }
+        text
+    }
+
+    /// Returns the hint shown instead of `...` on the question side, if there is one
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    /// Returns the deletions directly inside this one
+    pub fn nested(&self) -> Vec<&ClozeDeletion> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Cloze(cloze) => Some(cloze),
+                Node::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the byte range of the whole deletion, from `{{c` to `}}`, in the parsed text
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub(crate) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+}
+
+/// Returns the outermost cloze deletions of `text`. Nested deletions are returned by
+/// [`ClozeDeletion::nested`].
+pub fn parse_clozes(text: &str) -> Vec<ClozeDeletion> {
+    parse(text)
+        .nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Cloze(cloze) => Some(cloze),
+            Node::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Returns the numbers of all cloze deletions in `text`, including nested ones
+pub(crate) fn cloze_numbers(text: &str) -> BTreeSet<u16> {
+    fn collect(nodes: &[Node], numbers: &mut BTreeSet<u16>) {
+        for node in nodes {
+            if let Node::Cloze(cloze) = node {
+                numbers.insert(cloze.ordinal);
+                collect(&cloze.nodes, numbers);
+            }
+        }
+    }
+    let mut numbers = BTreeSet::new();
+    collect(&parse(text).nodes, &mut numbers);
+    numbers
+}
+
+/// A text split into plain text and cloze deletions
+pub(crate) struct Parsed {
+    pub(crate) nodes: Vec<Node>,
+    /// Byte ranges of the openings which are never closed and of the closings outside of any
+    /// deletion
+    pub(crate) unbalanced: Vec<Range<usize>>,
+}
+
+enum Token {
+    Open(u16),
+    Close,
+    Text,
+}
+
+/// Returns the ordinal and the length of the cloze opening `{{cN::` at the start of `text`
+fn cloze_opening(text: &str) -> Option<(u16, usize)> {
+    let rest = text.strip_prefix("{{c")?;
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    let ordinal = rest[..digits].parse().ok()?;
+    rest[digits..]
+        .starts_with("::")
+        .then_some((ordinal, "{{c".len() + digits + "::".len()))
+}
+
+/// Splits `text` into cloze openings, closings and the text between them
+fn tokenize(text: &str) -> Vec<(Token, Range<usize>)> {
+    let mut tokens = vec![];
+    let mut text_start = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let token = if let Some((ordinal, len)) = cloze_opening(rest) {
+            Some((Token::Open(ordinal), len))
+        } else if rest.starts_with("}}") {
+            Some((Token::Close, 2))
+        } else {
+            None
+        };
+        match token {
+            Some((token, len)) => {
+                if text_start < pos {
+                    tokens.push((Token::Text, text_start..pos));
+                }
+                tokens.push((token, pos..pos + len));
+                pos += len;
+                text_start = pos;
+            }
+            None => pos += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    if text_start < text.len() {
+        tokens.push((Token::Text, text_start..text.len()));
+    }
+    tokens
+}
+
+pub(crate) fn parse(text: &str) -> Parsed {
+    let mut open: Vec<ClozeDeletion> = vec![];
+    let mut nodes = vec![];
+    let mut unbalanced = vec![];
+    for (token, range) in tokenize(text) {
+        match token {
+            Token::Open(ordinal) => {
+                if open.len() < MAX_NESTING {
+                    open.push(ClozeDeletion {
+                        ordinal,
+                        span: range,
+                        nodes: vec![],
+                        hint: None,
+                    });
+                }
+            }
+            Token::Text => {
+                let mut part = &text[range];
+                match open.last_mut() {
+                    Some(cloze) => {
+                        if let Some((head, hint)) = part.split_once("::") {
+                            part = head;
+                            cloze.hint = Some(hint.to_string());
+                        }
+                        cloze.nodes.push(Node::Text(part.to_string()));
+                    }
+                    None => nodes.push(Node::Text(part.to_string())),
+                }
+            }
+            Token::Close => match open.pop() {
+                Some(mut cloze) => {
+                    cloze.span.end = range.end;
+                    match open.last_mut() {
+                        Some(outer) => outer.nodes.push(Node::Cloze(cloze)),
+                        None => nodes.push(Node::Cloze(cloze)),
+                    }
+                }
+                None => {
+                    nodes.push(Node::Text(text[range.clone()].to_string()));
+                    unbalanced.push(range);
+                }
+            },
+        }
+    }
+    unbalanced.extend(open.into_iter().map(|cloze| cloze.span));
+    unbalanced.sort_by_key(|span| span.start);
+    Parsed { nodes, unbalanced }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(text: &str) -> Vec<u16> {
+        cloze_numbers(text).into_iter().collect()
+    }
+
+    // Cases from the tests of Anki's cloze module
+    #[test]
+    fn anki_cloze_numbers() {
+        assert!(numbers("test").is_empty());
+        assert_eq!(numbers("{{c2::te}}{{c1::s}}t{{"), [1, 2]);
+        assert_eq!(numbers("{{c1::foo {{c2::bar}} baz}}"), [1, 2]);
+        assert_eq!(numbers("{{c2::te{{c1::s}}}}t{{"), [1, 2]);
+        assert_eq!(numbers("{{c1::}}"), [1]);
+    }
+
+    #[test]
+    fn hints() {
+        let clozes = parse_clozes("foo {{c1::bar {{c2::baz::qux}}::quux}}");
+        assert_eq!(clozes[0].hint(), Some("quux"));
+        assert_eq!(clozes[0].text(), "bar baz");
+        assert_eq!(clozes[0].nested()[0].hint(), Some("qux"));
+        assert_eq!(clozes[0].nested()[0].text(), "baz");
+
+        // `}}` ends the hint, and `::` in the text starts one
+        let clozes = parse_clozes("{{c1::a::b}}c}}");
+        assert_eq!(
+            (clozes[0].text(), clozes[0].hint()),
+            ("a".to_string(), Some("b"))
+        );
+        assert_eq!(
+            parse_clozes("{{c1::std::vector}}")[0].hint(),
+            Some("vector")
+        );
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn unbalanced() {
+        let parsed = parse("{{c1::a {{c2::b}}");
+        assert_eq!(parsed.unbalanced, [0..6]);
+        assert!(parsed.nodes.is_empty());
+
+        let parsed = parse("a}} {{c1::b}}");
+        assert_eq!(parsed.unbalanced, [1..3]);
+        assert_eq!(parsed.nodes[0], Node::Text("a".to_string()));
+        assert_eq!(parsed.nodes[1], Node::Text("}}".to_string()));
+
+        assert!(parse("{{c1::a}} {{Front}} {c1::b}").unbalanced.len() == 1);
+        assert!(parse("{{c99999::a}}").unbalanced == [11..13]);
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn nesting_is_limited() {
+        let text = format!("{}x{}", "{{c1::".repeat(11), "}}".repeat(11));
+        let parsed = parse(&text);
+        assert_eq!(parsed.unbalanced, [text.len() - 2..text.len()]);
+        assert_eq!(numbers(&text), [1]);
+    }
+
+    #[test]
+    fn multibyte_text() {
+        let text = "日本{{c1::東京::首都}}です";
+        let clozes = parse_clozes(text);
+        assert_eq!(clozes[0].text(), "東京");
+        assert_eq!(clozes[0].hint(), Some("首都"));
+        assert_eq!(&text[clozes[0].span()], "{{c1::東京::首都}}");
+    }
+}
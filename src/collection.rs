@@ -0,0 +1,314 @@
+use crate::apkg_col::{APKG_COL, APKG_COL_V18};
+use crate::apkg_schema::{APKG_SCHEMA, APKG_SCHEMA_V18};
+use crate::db_entries::{DeckConfigDbEntry, DeckDbEntry, ModelDbEntry};
+use crate::error::{database_error, json_error};
+use crate::protobuf::Message;
+use crate::Error;
+use rusqlite::{params, Connection, Transaction};
+use std::collections::BTreeMap;
+
+/// Creation time (`crt`) of the collection in `APKG_COL` and `APKG_COL_V18`, which is the day
+/// the due dates of review cards are counted from
+pub(crate) const COLLECTION_CREATION_TIME: i64 = 1411124400;
+
+/// Version of the collection database schema written into a package.
+///
+/// In schema 11 decks, models and deck options are JSON blobs in the `col` row, while schema 18
+/// stores them in dedicated tables with protobuf encoded settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Schema {
+    V11,
+    V18,
+}
+
+impl Schema {
+    /// Registers what is needed on a fresh connection to use this schema
+    pub(crate) fn prepare_connection(self, conn: &Connection) -> Result<(), Error> {
+        if self == Schema::V18 {
+            conn.create_collation("unicase", |a, b| a.to_lowercase().cmp(&b.to_lowercase()))
+                .map_err(database_error)?;
+        }
+        Ok(())
+    }
+
+    /// Creates all tables and the `col` row, together with the "Default" deck and deck options
+    pub(crate) fn create(self, transaction: &Transaction) -> Result<(), Error> {
+        match self {
+            Schema::V11 => {
+                transaction
+                    .execute_batch(APKG_SCHEMA)
+                    .map_err(database_error)?;
+                transaction
+                    .execute_batch(APKG_COL)
+                    .map_err(database_error)?;
+            }
+            Schema::V18 => {
+                transaction
+                    .execute_batch(APKG_SCHEMA_V18)
+                    .map_err(database_error)?;
+                transaction
+                    .execute_batch(APKG_COL_V18)
+                    .map_err(database_error)?;
+                self.add_deck(transaction, &default_deck_db_entry())?;
+                self.add_deck_config(transaction, &DeckConfigDbEntry::default())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_deck(
+        self,
+        transaction: &Transaction,
+        deck: &DeckDbEntry,
+    ) -> Result<(), Error> {
+        match self {
+            Schema::V11 => update_col_json(transaction, "decks", deck.id, deck),
+            Schema::V18 => {
+                let normal = Message::new()
+                    .int(1, deck.conf)
+                    .int(2, deck.extend_new)
+                    .int(3, deck.extend_rev)
+                    .string(4, &deck.desc);
+                transaction
+                    .execute(
+                        "INSERT OR REPLACE INTO decks VALUES(?,?,?,?,?,?)",
+                        params![
+                            deck.id,
+                            deck.name.replace("::", "\x1f"),
+                            deck.deck_db_entry_mod,
+                            deck.usn,
+                            Message::new()
+                                .bool(1, deck.collapsed)
+                                .bool(2, deck.collapsed)
+                                .into_bytes(),
+                            Message::new().message(1, normal).into_bytes(),
+                        ],
+                    )
+                    .map_err(database_error)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn add_deck_config(
+        self,
+        transaction: &Transaction,
+        config: &DeckConfigDbEntry,
+    ) -> Result<(), Error> {
+        match self {
+            Schema::V11 => update_col_json(transaction, "dconf", config.id, config),
+            Schema::V18 => {
+                // The legacy order 0 means random, while the protobuf enum uses 1 for random
+                let insert_order = if config.new.order == 0 { 1 } else { 0 };
+                let blob = Message::new()
+                    .packed_floats(1, config.new.delays.iter().map(|&d| d as f32))
+                    .packed_floats(2, config.lapse.delays.iter().map(|&d| d as f32))
+                    .int(9, config.new.per_day)
+                    .int(10, config.rev.per_day)
+                    .float(11, config.new.initial_factor as f32 / 1000.0)
+                    .float(12, config.rev.ease4 as f32)
+                    .float(13, 1.2)
+                    .float(14, config.lapse.mult as f32)
+                    .float(15, config.rev.ivl_fct as f32)
+                    .int(16, config.rev.max_ivl)
+                    .int(17, config.lapse.min_int)
+                    .int(18, config.new.ints.first().copied().unwrap_or(1))
+                    .int(19, config.new.ints.get(1).copied().unwrap_or(4))
+                    .uint(20, insert_order)
+                    .int(21, config.lapse.leech_action)
+                    .int(22, config.lapse.leech_fails)
+                    .bool(23, !config.autoplay)
+                    .int(24, config.max_taken)
+                    .bool(25, config.timer != 0)
+                    .bool(26, !config.replayq)
+                    .bool(27, config.new.bury)
+                    .bool(28, config.rev.bury)
+                    .into_bytes();
+                transaction
+                    .execute(
+                        "INSERT OR REPLACE INTO deck_config VALUES(?,?,?,?,?)",
+                        params![
+                            config.id,
+                            config.name,
+                            config.deck_config_db_entry_mod,
+                            config.usn,
+                            blob,
+                        ],
+                    )
+                    .map_err(database_error)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn add_model(
+        self,
+        transaction: &Transaction,
+        model: &ModelDbEntry,
+    ) -> Result<(), Error> {
+        let id = model
+            .id
+            .parse::<i64>()
+            .map_err(|_| Error::InvalidPackage(format!("invalid model id {:?}", model.id)))?;
+        match self {
+            Schema::V11 => update_col_json(transaction, "models", id, model),
+            Schema::V18 => {
+                let mut config = Message::new()
+                    .int(1, model.model_db_entry_type)
+                    .int(2, model.sortf)
+                    .string(3, &model.css)
+                    .string(5, &model.latex_pre)
+                    .string(6, &model.latex_post);
+                for (card_ord, any_or_all, field_ords) in &model.req {
+                    let kind = match any_or_all.as_str() {
+                        "any" => 1,
+                        "all" => 2,
+                        _ => 0,
+                    };
+                    config = config.message(
+                        8,
+                        Message::new()
+                            .uint(1, *card_ord as u64)
+                            .uint(2, kind)
+                            .packed_uints(3, field_ords.iter().map(|&ord| ord as u64)),
+                    );
+                }
+                // A model written again, e.g. by another deck, replaces its earlier version
+                transaction
+                    .execute("DELETE FROM notetypes WHERE id = ?", params![id])
+                    .map_err(database_error)?;
+                let name = unique_notetype_name(transaction, &model.name)?;
+                transaction
+                    .execute(
+                        "INSERT INTO notetypes VALUES(?,?,?,?,?)",
+                        params![
+                            id,
+                            name,
+                            model.model_db_entry_mod,
+                            model.usn,
+                            config.into_bytes(),
+                        ],
+                    )
+                    .map_err(database_error)?;
+                transaction
+                    .execute("DELETE FROM fields WHERE ntid = ?", params![id])
+                    .map_err(database_error)?;
+                for field in &model.flds {
+                    let config = Message::new()
+                        .bool(1, field.sticky)
+                        .bool(2, field.rtl)
+                        .string(3, &field.font)
+                        .int(4, field.size);
+                    transaction
+                        .execute(
+                            "INSERT INTO fields VALUES(?,?,?,?)",
+                            params![id, field.ord, field.name, config.into_bytes()],
+                        )
+                        .map_err(database_error)?;
+                }
+                transaction
+                    .execute("DELETE FROM templates WHERE ntid = ?", params![id])
+                    .map_err(database_error)?;
+                for template in &model.tmpls {
+                    let config = Message::new()
+                        .string(1, &template.qfmt)
+                        .string(2, &template.afmt)
+                        .string(3, &template.bqfmt)
+                        .string(4, &template.bafmt)
+                        .int(5, template.did.unwrap_or(0) as i64);
+                    transaction
+                        .execute(
+                            "INSERT INTO templates VALUES(?,?,?,?,?,?)",
+                            params![
+                                id,
+                                template.ord,
+                                template.name,
+                                model.model_db_entry_mod,
+                                model.usn,
+                                config.into_bytes(),
+                            ],
+                        )
+                        .map_err(database_error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers tags used by notes. Schema 11 collections rebuild their tag list on import, so
+    /// this only has an effect for schema 18.
+    pub(crate) fn add_tags(self, transaction: &Transaction, tags: &[String]) -> Result<(), Error> {
+        if self == Schema::V18 {
+            for tag in tags {
+                transaction
+                    .execute(
+                        "INSERT OR IGNORE INTO tags VALUES(?,?,?,?)",
+                        params![tag, -1, false, Option::<Vec<u8>>::None],
+                    )
+                    .map_err(database_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Inserts `entry` with `id` into the JSON object stored in the `column` of the `col` row
+/// Returns `name`, or if another notetype already has it, `name` with as many `+` appended as
+/// needed to make it unique, like Anki does when it imports a notetype
+fn unique_notetype_name(transaction: &Transaction, name: &str) -> Result<String, Error> {
+    let mut name = name.to_string();
+    loop {
+        let taken: bool = transaction
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM notetypes WHERE name = ?)",
+                params![name],
+                |row| row.get(0),
+            )
+            .map_err(database_error)?;
+        if !taken {
+            return Ok(name);
+        }
+        name.push('+');
+    }
+}
+
+fn update_col_json<T: serde::Serialize>(
+    transaction: &Transaction,
+    column: &str,
+    id: i64,
+    entry: &T,
+) -> Result<(), Error> {
+    let json_str: String = transaction
+        .query_row(&format!("SELECT {} FROM col", column), [], |row| row.get(0))
+        .map_err(database_error)?;
+    let mut entries: BTreeMap<i64, serde_json::Value> =
+        serde_json::from_str(&json_str).map_err(json_error)?;
+    entries.insert(id, serde_json::to_value(entry).map_err(json_error)?);
+    transaction
+        .execute(
+            &format!("UPDATE col SET {} = ?", column),
+            params![serde_json::to_string(&entries).map_err(json_error)?],
+        )
+        .map_err(database_error)?;
+    Ok(())
+}
+
+/// The "Default" deck of `APKG_COL`
+fn default_deck_db_entry() -> DeckDbEntry {
+    DeckDbEntry {
+        collapsed: false,
+        conf: 1,
+        desc: "".to_string(),
+        deck_db_entry_dyn: 0,
+        extend_new: 10,
+        extend_rev: 50,
+        id: 1,
+        lrn_today: vec![0, 0],
+        deck_db_entry_mod: 1425279151,
+        name: "Default".to_string(),
+        new_today: vec![0, 0],
+        rev_today: vec![0, 0],
+        time_today: vec![0, 0],
+        usn: 0,
+    }
+}
@@ -1,7 +1,8 @@
 use crate::deck::Deck;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct DeckDbEntry {
     pub collapsed: bool,
     pub conf: i64,
@@ -29,34 +30,43 @@ pub struct DeckDbEntry {
 
 impl From<Deck> for DeckDbEntry {
     fn from(deck: Deck) -> Self {
-        deck.into()
+        deck.to_deck_db_entry()
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ModelDbEntry {
+    #[serde(default)]
     pub vers: Vec<Option<serde_json::Value>>,
     pub name: String,
+    #[serde(default)]
     pub tags: Vec<Option<serde_json::Value>>,
-    pub did: i64,
+    #[serde(default)]
+    pub did: Option<i64>,
+    #[serde(default)]
     pub usn: i64,
+    #[serde(default)]
     pub req: Vec<(usize, String, Vec<usize>)>,
     pub flds: Vec<Fld>,
+    #[serde(default)]
     pub sortf: i64,
     pub tmpls: Vec<Tmpl>,
-    #[serde(rename = "mod")]
+    #[serde(rename = "mod", default)]
     pub model_db_entry_mod: i64,
-    #[serde(rename = "latexPost")]
+    #[serde(rename = "latexPost", default)]
     pub latex_post: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub model_db_entry_type: i64,
+    #[serde(deserialize_with = "string_or_number")]
     pub id: String,
+    #[serde(default)]
     pub css: String,
-    #[serde(rename = "latexPre")]
+    #[serde(rename = "latexPre", default)]
     pub latex_pre: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
 pub struct Fld {
     pub name: String,
     pub media: Vec<Option<serde_json::Value>>,
@@ -67,7 +77,8 @@ pub struct Fld {
     pub size: i64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
 pub struct Tmpl {
     pub name: String,
     pub qfmt: String,
@@ -77,3 +88,114 @@ pub struct Tmpl {
     pub ord: i64,
     pub bqfmt: String,
 }
+
+/// Anki itself writes model ids as JSON numbers, while genanki writes them as strings.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a string or a number, found {}",
+            other
+        ))),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DeckConfigDbEntry {
+    pub autoplay: bool,
+    pub id: i64,
+    pub lapse: LapseConfigDbEntry,
+    #[serde(rename = "maxTaken")]
+    pub max_taken: i64,
+    #[serde(rename = "mod")]
+    pub deck_config_db_entry_mod: i64,
+    pub name: String,
+    pub new: NewConfigDbEntry,
+    pub replayq: bool,
+    pub rev: RevConfigDbEntry,
+    pub timer: i64,
+    pub usn: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LapseConfigDbEntry {
+    pub delays: Vec<f64>,
+    #[serde(rename = "leechAction")]
+    pub leech_action: i64,
+    #[serde(rename = "leechFails")]
+    pub leech_fails: i64,
+    #[serde(rename = "minInt")]
+    pub min_int: i64,
+    pub mult: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewConfigDbEntry {
+    pub bury: bool,
+    pub delays: Vec<f64>,
+    #[serde(rename = "initialFactor")]
+    pub initial_factor: i64,
+    pub ints: Vec<i64>,
+    pub order: i64,
+    #[serde(rename = "perDay")]
+    pub per_day: i64,
+    pub separate: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevConfigDbEntry {
+    pub bury: bool,
+    pub ease4: f64,
+    pub fuzz: f64,
+    #[serde(rename = "ivlFct")]
+    pub ivl_fct: f64,
+    #[serde(rename = "maxIvl")]
+    pub max_ivl: i64,
+    #[serde(rename = "minSpace")]
+    pub min_space: i64,
+    #[serde(rename = "perDay")]
+    pub per_day: i64,
+}
+
+/// The "Default" deck options of `APKG_COL`
+impl Default for DeckConfigDbEntry {
+    fn default() -> Self {
+        Self {
+            autoplay: true,
+            id: 1,
+            lapse: LapseConfigDbEntry {
+                delays: vec![10.0],
+                leech_action: 0,
+                leech_fails: 8,
+                min_int: 1,
+                mult: 0.0,
+            },
+            max_taken: 60,
+            deck_config_db_entry_mod: 0,
+            name: "Default".to_string(),
+            new: NewConfigDbEntry {
+                bury: true,
+                delays: vec![1.0, 10.0],
+                initial_factor: 2500,
+                ints: vec![1, 4, 7],
+                order: 1,
+                per_day: 20,
+                separate: true,
+            },
+            replayq: true,
+            rev: RevConfigDbEntry {
+                bury: true,
+                ease4: 1.3,
+                fuzz: 0.05,
+                ivl_fct: 1.0,
+                max_ivl: 36500,
+                min_space: 1,
+                per_day: 100,
+            },
+            timer: 0,
+            usn: 0,
+        }
+    }
+}
@@ -1,21 +1,61 @@
 use super::Package;
-use crate::db_entries::{DeckDbEntry, ModelDbEntry};
-use crate::error::{database_error, json_error};
+use crate::card::Card;
+use crate::collection::Schema;
+use crate::db_entries::DeckDbEntry;
+use crate::deck_config::DeckConfig;
 use crate::model::Model;
-use crate::note::Note;
+use crate::note::{Note, NoteDefinition};
+use crate::validation::{ValidationReport, Validator};
+use crate::zero_cards::ZeroCardPolicy;
 use crate::Error;
-use rusqlite::{params, Transaction};
-use std::collections::HashMap;
+use rusqlite::Transaction;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::ops::RangeFrom;
 
+/// Id of the "Default" deck every collection has
+const DEFAULT_DECK_ID: i64 = 1;
+
+/// Separates the names of parent and child decks, e.g. `Textbook::Chapter 1::Section 1`
+const DECK_NAME_SEPARATOR: &str = "::";
+
 /// A flashcard deck which can be written into an .apkg file.
-#[derive(Clone)]
+///
+/// Decks can be serialized and deserialized with serde. The models of the notes are listed once
+/// in `models`, and each note refers to its model by id:
+///
+/// ```rust
+/// use genanki_rs::Deck;
+///
+/// let deck: Deck = serde_json::from_str(r#"{
+///     "id": 1234,
+///     "name": "Country Capitals",
+///     "models": [{
+///         "id": 1607392319,
+///         "name": "Simple Model",
+///         "fields": [{ "name": "Country" }, { "name": "Capital" }],
+///         "templates": [{ "name": "Card 1", "qfmt": "{{Country}}", "afmt": "{{Capital}}" }]
+///     }],
+///     "notes": [
+///         { "model": 1607392319, "fields": ["France", "Paris"], "tags": ["europe"] },
+///         { "model": 1607392319, "fields": { "Country": "Japan", "Capital": "Tokyo" } }
+///     ]
+/// }"#).unwrap();
+/// assert_eq!(deck.notes().len(), 2);
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "DeckDefinition", into = "DeckDefinition")]
 pub struct Deck {
     id: i64,
     name: String,
     description: String,
     notes: Vec<Note>,
-    models: HashMap<i64, Model>,
+    models: BTreeMap<i64, Model>,
+    config: Option<DeckConfig>,
+    zero_card_policy: Option<ZeroCardPolicy>,
+    subdecks: Vec<Deck>,
 }
 
 impl Deck {
@@ -28,7 +68,10 @@ impl Deck {
             name: name.to_string(),
             description: description.to_string(),
             notes: vec![],
-            models: HashMap::new(),
+            models: BTreeMap::new(),
+            config: None,
+            zero_card_policy: None,
+            subdecks: vec![],
         }
     }
 
@@ -37,28 +80,170 @@ impl Deck {
     /// Example:
     ///
     /// ```rust
-    /// use genanki_rs::{Deck, Note, basic_model};
+    /// use genanki_rs::{Deck, Error, Note, basic_model};
     ///
+    /// # fn main() -> Result<(), Error> {
     /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
     /// my_deck.add_note(Note::new(basic_model(), vec!["What is the capital of France?", "Paris"])?);
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn add_note(&mut self, note: Note) {
         self.notes.push(note);
     }
 
-    fn add_model(&mut self, model: Model) {
-        self.models.insert(model.id, model);
+    /// Adds `subdeck` as a child of this deck.
+    ///
+    /// The name of the subdeck is relative to its parent and is written into the package as
+    /// `Parent::Child`. It must not be empty or contain the `::` separator.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Deck, Error};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let mut chapter = Deck::new(2, "Chapter 1", "");
+    /// chapter.add_subdeck(Deck::new(3, "Section 1", ""))?;
+    /// let mut textbook = Deck::new(1234, "Textbook", "");
+    /// textbook.add_subdeck(chapter)?;
+    ///
+    /// assert_eq!(textbook.subdeck("Chapter 1::Section 1").unwrap().id(), 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Returns `Err` if the name of `subdeck` is invalid
+    pub fn add_subdeck(&mut self, subdeck: Deck) -> Result<(), Error> {
+        if subdeck.name.trim().is_empty() || subdeck.name.contains(DECK_NAME_SEPARATOR) {
+            return Err(Error::InvalidDeckName(subdeck.name));
+        }
+        self.subdecks.push(subdeck);
+        Ok(())
     }
 
-    fn to_deck_db_entry(&self) -> DeckDbEntry {
+    /// Returns the direct subdecks of the deck
+    pub fn subdecks(&self) -> &[Deck] {
+        &self.subdecks
+    }
+
+    /// Returns the subdeck at `path`, which is relative to this deck, e.g. `Chapter 1::Section 1`
+    pub fn subdeck(&self, path: &str) -> Option<&Deck> {
+        let (subdeck, rest) = self
+            .subdecks
+            .iter()
+            .find_map(|subdeck| subdeck.strip_name(path).map(|rest| (subdeck, rest)))?;
+        match rest {
+            Some(rest) => subdeck.subdeck(rest),
+            None => Some(subdeck),
+        }
+    }
+
+    /// Returns the subdeck at `path` for modification
+    pub fn subdeck_mut(&mut self, path: &str) -> Option<&mut Deck> {
+        let (subdeck, rest) = self
+            .subdecks
+            .iter_mut()
+            .find_map(|subdeck| subdeck.strip_name(path).map(|rest| (subdeck, rest)))?;
+        match rest {
+            Some(rest) => subdeck.subdeck_mut(rest),
+            None => Some(subdeck),
+        }
+    }
+
+    /// Matches the name of this deck against the beginning of `path`.
+    ///
+    /// Returns `None` if the path does not start with this deck, `Some(None)` if it is this deck
+    /// and `Some(Some(rest))` if it points to a subdeck.
+    pub(super) fn strip_name<'a>(&self, path: &'a str) -> Option<Option<&'a str>> {
+        if path == self.name {
+            return Some(None);
+        }
+        path.strip_prefix(self.name.as_str())
+            .and_then(|rest| rest.strip_prefix(DECK_NAME_SEPARATOR))
+            .map(Some)
+    }
+
+    /// Sets the deck options used to study the deck. Without options the deck uses the "Default"
+    /// preset.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{Deck, DeckConfig};
+    ///
+    /// let config = DeckConfig::new(1_600_000_000, "Intensive").new_per_day(100);
+    /// let deck = Deck::new(1234, "Example deck", "This is an example deck").with_config(config);
+    /// ```
+    pub fn with_config(self, config: DeckConfig) -> Self {
+        Self {
+            config: Some(config),
+            ..self
+        }
+    }
+
+    /// Sets what happens to notes of the deck and its subdecks which generate no cards, replacing
+    /// the policy of the package for them
+    pub fn with_zero_card_policy(self, zero_card_policy: ZeroCardPolicy) -> Self {
+        Self {
+            zero_card_policy: Some(zero_card_policy),
+            ..self
+        }
+    }
+
+    /// Returns the id of the deck
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Returns the name of the deck
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the description of the deck
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the deck options of the deck, if any were set
+    pub fn config(&self) -> Option<&DeckConfig> {
+        self.config.as_ref()
+    }
+
+    /// Returns the notes which were added to the deck
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Checks the notes of the deck and its subdecks with the default [`Validator`] and returns
+    /// the problems, see [`Package::validate`]
+    pub fn validate(&self) -> ValidationReport {
+        Validator::new().validate(std::slice::from_ref(self))
+    }
+
+    /// Removes the notes and subdecks from the deck and returns them
+    pub(super) fn take_contents(&mut self) -> (Vec<Note>, Vec<Deck>) {
+        self.models.clear();
+        (
+            std::mem::take(&mut self.notes),
+            std::mem::take(&mut self.subdecks),
+        )
+    }
+
+    pub(super) fn subdecks_mut(&mut self) -> &mut Vec<Deck> {
+        &mut self.subdecks
+    }
+
+    pub(super) fn to_deck_db_entry(&self) -> DeckDbEntry {
         DeckDbEntry {
             collapsed: false,
-            conf: 1,
+            conf: self.config.as_ref().map_or(1, DeckConfig::id),
             desc: self.description.clone(),
             deck_db_entry_dyn: 0,
             extend_new: 0,
             extend_rev: 50,
-            id: self.id.clone(),
+            id: self.id,
             lrn_today: vec![163, 2],
             deck_db_entry_mod: 1425278051,
             name: self.name.clone(),
@@ -75,44 +260,59 @@ impl Deck {
         serde_json::to_string(&db_entry).expect("Should always serialize")
     }
 
+    fn full_name(&self, parent: Option<&str>) -> String {
+        match parent {
+            Some(parent) => format!("{}{}{}", parent, DECK_NAME_SEPARATOR, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Writes the deck and all of its subdecks. `parent` is the full name of the parent deck, and
+    /// `zero_card_policy` the policy of the parent deck or package.
     pub(super) fn write_to_db(
         &mut self,
         transaction: &Transaction,
         timestamp: f64,
+        schema: Schema,
+        parent: Option<&str>,
+        zero_card_policy: ZeroCardPolicy,
         id_gen: &mut RangeFrom<usize>,
     ) -> Result<(), Error> {
-        let decks_json_str: String = transaction
-            .query_row("SELECT decks FROM col", [], |row| row.get(0))
-            .map_err(database_error)?;
-        let mut decks: HashMap<i64, DeckDbEntry> =
-            serde_json::from_str(&decks_json_str).map_err(json_error)?;
-        decks.insert(self.id, self.to_deck_db_entry());
-        transaction
-            .execute(
-                "UPDATE col SET decks = ?",
-                params![serde_json::to_string(&decks).map_err(json_error)?],
-            )
-            .map_err(database_error)?;
-
-        let models_json_str: String = transaction
-            .query_row("SELECT models FROM col", [], |row| row.get(0))
-            .map_err(database_error)?;
-        let mut models: HashMap<i64, ModelDbEntry> =
-            serde_json::from_str(&models_json_str).map_err(json_error)?;
-        for note in self.notes.clone().iter() {
-            self.add_model(note.model());
-        }
-        for (i, model) in &mut self.models {
-            models.insert(*i, model.to_model_db_entry(timestamp, self.id)?);
-        }
-        transaction
-            .execute(
-                "UPDATE col SET models = ?",
-                [serde_json::to_string(&models).map_err(json_error)?],
-            )
-            .map_err(database_error)?;
+        let zero_card_policy = self.zero_card_policy.unwrap_or(zero_card_policy);
+        if let Some(config) = &self.config {
+            schema.add_deck_config(transaction, &config.to_deck_config_db_entry(timestamp))?;
+        }
+        let name = self.full_name(parent);
+        schema.add_deck(
+            transaction,
+            &DeckDbEntry {
+                name: name.clone(),
+                ..self.to_deck_db_entry()
+            },
+        )?;
+        for note in &self.notes {
+            let model = note.model();
+            self.models.entry(model.id).or_insert_with(|| model.clone());
+        }
+        for model in self.models.values() {
+            schema.add_model(transaction, &model.to_model_db_entry(timestamp, self.id)?)?;
+        }
         for note in &mut self.notes {
-            note.write_to_db(&transaction, timestamp, self.id, id_gen)?;
+            if !zero_card_policy.admits(note)? {
+                continue;
+            }
+            schema.add_tags(transaction, note.get_tags())?;
+            note.write_to_db(transaction, timestamp, self.id, id_gen)?;
+        }
+        for subdeck in &mut self.subdecks {
+            subdeck.write_to_db(
+                transaction,
+                timestamp,
+                schema,
+                Some(&name),
+                zero_card_policy,
+                id_gen,
+            )?;
         }
         Ok(())
     }
@@ -122,26 +322,385 @@ impl Deck {
     /// Returns `Err` if the file can not be created.
     ///
     /// Example:
-    /// ```rust
-    /// use genanki_rs::{Deck, Note, basic_model};
+    /// ```rust,no_run
+    /// use genanki_rs::{Deck, Error, Note, basic_model};
     ///
+    /// # fn main() -> Result<(), Error> {
     /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
     /// my_deck.add_note(Note::new(basic_model(), vec!["What is the capital of France?", "Paris"])?);
     ///
     /// my_deck.write_to_file("output.apkg")?;
+    /// # Ok(())
+    /// # }
     /// ```
     ///
     /// This is equivalent to:
-    /// ```rust
-    /// use genanki_rs::{Deck, Note, basic_model, Package};
+    /// ```rust,no_run
+    /// use genanki_rs::{Deck, Error, Note, basic_model, Package};
     ///
+    /// # fn main() -> Result<(), Error> {
     /// let mut my_deck = Deck::new(1234, "Example deck", "This is an example deck");
     /// my_deck.add_note(Note::new(basic_model(), vec!["What is the capital of France?", "Paris"])?);
     ///
     /// Package::new(vec![my_deck], vec![])?.write_to_file("output.apkg")?;
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn write_to_file(&self, file: &str) -> Result<(), Error> {
         Package::new(vec![self.clone()], vec![])?.write_to_file(file)?;
         Ok(())
     }
 }
+
+/// Collects the full names and ids of `decks` and all of their subdecks
+pub(super) fn collect_deck_names(
+    decks: &[Deck],
+    parent: Option<&str>,
+    names: &mut Vec<(String, i64)>,
+) {
+    for deck in decks {
+        let name = deck.full_name(parent);
+        collect_deck_names(&deck.subdecks, Some(&name), names);
+        names.push((name, deck.id));
+    }
+}
+
+/// Checks the names of `decks` and their subdecks and returns the decks which have to be created
+/// so that every deck in the package has a parent.
+///
+/// Like Anki, deck names are compared case-insensitively. Missing parents get an id derived from
+/// their name, so they are the same for every package containing them.
+pub(super) fn missing_parent_decks(decks: &[Deck]) -> Result<Vec<Deck>, Error> {
+    let mut names = vec![];
+    collect_deck_names(decks, None, &mut names);
+    missing_parents(&names)
+}
+
+/// Like `missing_parent_decks`, for decks given by their full names and ids as returned by
+/// `collect_deck_names`
+pub(super) fn missing_parents(names: &[(String, i64)]) -> Result<Vec<Deck>, Error> {
+    let mut ids_by_name: BTreeMap<String, (String, i64)> = BTreeMap::new();
+    let mut names_by_id: HashMap<i64, String> = HashMap::new();
+    for (name, id) in names {
+        if name
+            .split(DECK_NAME_SEPARATOR)
+            .any(|component| component.trim().is_empty())
+        {
+            return Err(Error::InvalidDeckName(name.clone()));
+        }
+        let key = name.to_lowercase();
+        if let Some((other_name, other_id)) = ids_by_name.get(&key) {
+            if other_id != id {
+                return Err(Error::DeckConflict(format!(
+                    "decks {:?} ({}) and {:?} ({}) have the same name",
+                    other_name, other_id, name, id
+                )));
+            }
+        }
+        if let Some(other_key) = names_by_id.get(id) {
+            if *other_key != key {
+                return Err(Error::DeckConflict(format!(
+                    "decks {:?} and {:?} have the same id {}",
+                    ids_by_name[other_key].0, name, id
+                )));
+            }
+        }
+        ids_by_name.insert(key.clone(), (name.clone(), *id));
+        names_by_id.insert(*id, key);
+    }
+
+    let mut missing = BTreeMap::new();
+    for (name, _) in names {
+        let mut ancestor = String::new();
+        let components = name.split(DECK_NAME_SEPARATOR).collect::<Vec<_>>();
+        for component in &components[..components.len() - 1] {
+            if !ancestor.is_empty() {
+                ancestor.push_str(DECK_NAME_SEPARATOR);
+            }
+            ancestor.push_str(component);
+            let key = ancestor.to_lowercase();
+            if !ids_by_name.contains_key(&key) {
+                missing
+                    .entry(key)
+                    .or_insert_with(|| Deck::new(parent_deck_id(&ancestor), &ancestor, ""));
+            }
+        }
+    }
+    Ok(missing.into_values().collect())
+}
+
+/// Checks that every card which is put into a deck other than the one of its note is put into a
+/// deck of the package. `parents` are the decks returned by `missing_parent_decks`.
+pub(super) fn check_card_decks(decks: &[Deck], parents: &[Deck]) -> Result<(), Error> {
+    let mut names = vec![];
+    collect_deck_names(decks, None, &mut names);
+    let mut card_deck_ids = BTreeSet::new();
+    collect_card_deck_ids(decks, &mut card_deck_ids);
+    check_deck_ids(&card_deck_ids, &names, parents)
+}
+
+/// Collects the ids of the decks cards of `decks` and their subdecks are put into, if it is not
+/// the deck of their note
+pub(super) fn collect_card_deck_ids(decks: &[Deck], card_deck_ids: &mut BTreeSet<i64>) {
+    for deck in decks {
+        card_deck_ids.extend(
+            deck.notes
+                .iter()
+                .flat_map(Note::cards)
+                .filter_map(Card::deck_id),
+        );
+        collect_card_deck_ids(&deck.subdecks, card_deck_ids);
+    }
+}
+
+/// Checks that all `card_deck_ids` are either in `names`, in `parents` or the "Default" deck
+pub(super) fn check_deck_ids(
+    card_deck_ids: &BTreeSet<i64>,
+    names: &[(String, i64)],
+    parents: &[Deck],
+) -> Result<(), Error> {
+    let mut deck_ids = names.iter().map(|(_, id)| *id).collect::<HashSet<_>>();
+    deck_ids.extend(parents.iter().map(Deck::id));
+    deck_ids.insert(DEFAULT_DECK_ID);
+    match card_deck_ids.iter().find(|id| !deck_ids.contains(id)) {
+        Some(&deck_id) => Err(Error::CardDeckNotFound(deck_id)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the notes of `decks` and all their subdecks
+pub(super) fn all_notes(decks: &[Deck]) -> Vec<&Note> {
+    let mut notes = vec![];
+    for deck in decks {
+        notes.extend(&deck.notes);
+        notes.extend(all_notes(&deck.subdecks));
+    }
+    notes
+}
+
+/// Returns the notes of `decks` and all their subdecks for modification
+pub(super) fn all_notes_mut(decks: &mut [Deck]) -> Vec<&mut Note> {
+    let mut notes = vec![];
+    for deck in decks {
+        notes.extend(&mut deck.notes);
+        notes.extend(all_notes_mut(&mut deck.subdecks));
+    }
+    notes
+}
+
+/// Id of a deck that was created because one of its subdecks needs it as a parent.
+///
+/// It is taken from the SHA1 hash of the lowercased name and limited to 53 bits, so it can be
+/// represented exactly in Javascript.
+fn parent_deck_id(name: &str) -> i64 {
+    let hash = Sha1::digest(name.to_lowercase().as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    (u64::from_be_bytes(bytes) >> 11) as i64
+}
+
+/// Serialized form of a `Deck`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeckDefinition {
+    id: i64,
+    name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<DeckConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zero_card_policy: Option<ZeroCardPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    models: Vec<Model>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<NoteDefinition<i64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subdecks: Vec<Deck>,
+}
+
+impl From<Deck> for DeckDefinition {
+    fn from(deck: Deck) -> Self {
+        let mut models = BTreeMap::new();
+        for note in &deck.notes {
+            let model = note.model();
+            models.entry(model.id).or_insert_with(|| model.clone());
+        }
+        Self {
+            id: deck.id,
+            name: deck.name,
+            description: deck.description,
+            config: deck.config,
+            zero_card_policy: deck.zero_card_policy,
+            models: models.into_values().collect(),
+            notes: deck
+                .notes
+                .iter()
+                .map(|note| NoteDefinition::new(note, note.model().id))
+                .collect(),
+            subdecks: deck.subdecks,
+        }
+    }
+}
+
+impl TryFrom<DeckDefinition> for Deck {
+    type Error = Error;
+
+    fn try_from(definition: DeckDefinition) -> Result<Self, Error> {
+        let models: HashMap<i64, Model> = definition
+            .models
+            .into_iter()
+            .map(|model| (model.id, model))
+            .collect();
+        let mut deck = Deck::new(definition.id, &definition.name, &definition.description);
+        deck.config = definition.config;
+        deck.zero_card_policy = definition.zero_card_policy;
+        for note in definition.notes {
+            let model_id = *note.model();
+            let model = models.get(&model_id).ok_or(Error::UnknownModel(model_id))?;
+            deck.add_note(note.into_note(model.clone())?);
+        }
+        for subdeck in definition.subdecks {
+            deck.add_subdeck(subdeck)?;
+        }
+        Ok(deck)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn textbook() -> Deck {
+        let mut section = Deck::new(3, "Section 1", "");
+        section.add_subdeck(Deck::new(4, "Exercises", "")).unwrap();
+        let mut chapter = Deck::new(2, "Chapter 1", "");
+        chapter.add_subdeck(section).unwrap();
+        chapter.add_subdeck(Deck::new(5, "Section 2", "")).unwrap();
+        let mut textbook = Deck::new(1234, "Textbook", "");
+        textbook.add_subdeck(chapter).unwrap();
+        textbook
+    }
+
+    #[test]
+    fn subdeck_lookup() {
+        let mut textbook = textbook();
+        assert_eq!(textbook.subdeck("Chapter 1").unwrap().id(), 2);
+        assert_eq!(
+            textbook
+                .subdeck("Chapter 1::Section 1::Exercises")
+                .unwrap()
+                .id(),
+            4
+        );
+        assert!(textbook.subdeck("Chapter 1::Section 3").is_none());
+        assert!(textbook.subdeck("Chapter").is_none());
+        textbook
+            .subdeck_mut("Chapter 1::Section 2")
+            .unwrap()
+            .add_subdeck(Deck::new(6, "Exercises", ""))
+            .unwrap();
+        assert_eq!(
+            textbook
+                .subdeck("Chapter 1::Section 2::Exercises")
+                .unwrap()
+                .id(),
+            6
+        );
+    }
+
+    #[test]
+    fn invalid_subdeck_names() {
+        let mut deck = Deck::new(1, "Parent", "");
+        assert!(matches!(
+            deck.add_subdeck(Deck::new(2, "A::B", "")),
+            Err(Error::InvalidDeckName(_))
+        ));
+        assert!(matches!(
+            deck.add_subdeck(Deck::new(2, " ", "")),
+            Err(Error::InvalidDeckName(_))
+        ));
+        assert!(matches!(
+            missing_parent_decks(&[Deck::new(1, "A::::B", "")]),
+            Err(Error::InvalidDeckName(_))
+        ));
+    }
+
+    #[test]
+    fn missing_parents_are_created() {
+        let decks = vec![
+            textbook(),
+            Deck::new(7, "Languages::French::Vocabulary", ""),
+            Deck::new(8, "languages::Spanish", ""),
+        ];
+        let missing = missing_parent_decks(&decks).unwrap();
+        let names = missing.iter().map(Deck::name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Languages", "Languages::French"]);
+        assert_eq!(missing[0].id(), parent_deck_id("languages"));
+        assert_ne!(missing[0].id(), missing[1].id());
+        assert!(missing_parent_decks(&[textbook()]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn card_decks_must_exist() {
+        let note = Note::new(crate::basic_model(), vec!["Question", "Answer"]).unwrap();
+        let mut deck = Deck::new(10, "Languages::French", "");
+        deck.add_note(note.clone().card_deck(0, 11).unwrap());
+        let decks = vec![deck.clone()];
+        assert!(matches!(
+            check_card_decks(&decks, &missing_parent_decks(&decks).unwrap()),
+            Err(Error::CardDeckNotFound(11))
+        ));
+
+        deck.add_subdeck(Deck::new(11, "Vocabulary", "")).unwrap();
+        deck.add_note(note.card_deck(0, parent_deck_id("Languages")).unwrap());
+        let decks = vec![deck];
+        assert!(check_card_decks(&decks, &missing_parent_decks(&decks).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn conflicting_decks() {
+        assert!(matches!(
+            missing_parent_decks(&[Deck::new(1, "A", ""), Deck::new(2, "a", "")]),
+            Err(Error::DeckConflict(_))
+        ));
+        assert!(matches!(
+            missing_parent_decks(&[Deck::new(1, "A", ""), Deck::new(1, "B", "")]),
+            Err(Error::DeckConflict(_))
+        ));
+        assert!(missing_parent_decks(&[Deck::new(1, "A", ""), Deck::new(1, "A", "")]).is_ok());
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let model = crate::basic_model();
+        let mut deck = textbook().with_config(DeckConfig::new(7, "Course").new_per_day(5));
+        deck.add_note(Note::new(model.clone(), vec!["a", "b"]).unwrap());
+        deck.add_note(Note::new(model, vec!["c", "d"]).unwrap());
+        deck.subdeck_mut("Chapter 1")
+            .unwrap()
+            .add_note(Note::new(crate::cloze_model(), vec!["{{c1::e}}"]).unwrap());
+
+        let value = serde_json::to_value(&deck).unwrap();
+        assert_eq!(value["models"].as_array().unwrap().len(), 1);
+        assert_eq!(value["notes"][1]["model"], crate::basic_model().id);
+        assert_eq!(value["config"]["new_per_day"], 5);
+        let deserialized: Deck = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&deserialized).unwrap(), value);
+        assert_eq!(
+            deserialized
+                .subdeck("Chapter 1::Section 1::Exercises")
+                .unwrap()
+                .id(),
+            4
+        );
+
+        let value = serde_json::json!({
+            "id": 1,
+            "name": "Deck",
+            "notes": [{ "model": 42, "fields": [] }]
+        });
+        let error = serde_json::from_value::<Deck>(value).err().unwrap();
+        assert_eq!(error.to_string(), "The model 42 is not defined in the deck");
+    }
+}
@@ -0,0 +1,565 @@
+use crate::db_entries::{
+    DeckConfigDbEntry, LapseConfigDbEntry, NewConfigDbEntry, RevConfigDbEntry,
+};
+use serde::{Deserialize, Serialize};
+
+/// What Anki does with a card once it became a leech
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeechAction {
+    /// Suspend the card and tag its note with `leech`
+    Suspend,
+    /// Only tag the note with `leech`
+    TagOnly,
+}
+
+/// Deck options (a "preset" in Anki) which control how the cards of a `Deck` are studied.
+///
+/// A `DeckConfig` is attached to a deck with [`Deck::with_config`](crate::Deck::with_config) and
+/// can be shared by several decks. Steps are given in minutes, intervals in days.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Deck, DeckConfig};
+///
+/// let config = DeckConfig::new(1_600_000_000, "Course")
+///     .new_steps(vec![1.0, 10.0, 60.0])
+///     .new_per_day(50)
+///     .review_per_day(500)
+///     .max_interval(365);
+/// let deck = Deck::new(1234, "Example deck", "").with_config(config);
+/// ```
+///
+/// The builder has the same defaults as the "Default" preset of Anki:
+/// * `new_steps` - `[1.0, 10.0]`
+/// * `new_per_day` - `20`
+/// * `graduating_interval` - `1`
+/// * `easy_interval` - `4`
+/// * `starting_ease` - `2.5`
+/// * `bury_new` - `true`
+/// * `review_per_day` - `100`
+/// * `easy_bonus` - `1.3`
+/// * `interval_modifier` - `1.0`
+/// * `max_interval` - `36500`
+/// * `bury_reviews` - `true`
+/// * `lapse_steps` - `[10.0]`
+/// * `lapse_new_interval` - `0.0`
+/// * `lapse_min_interval` - `1`
+/// * `leech_threshold` - `8`
+/// * `leech_action` - `LeechAction::Suspend`
+/// * `autoplay` - `true`
+/// * `replay_question` - `true`
+/// * `show_timer` - `false`
+/// * `max_answer_seconds` - `60`
+///
+/// Deck options can be serialized and deserialized with serde. All options except `id` and
+/// `name` can be left out to use the defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "DeckConfigDefinition", into = "DeckConfigDefinition")]
+pub struct DeckConfig {
+    id: i64,
+    name: String,
+    new_steps: Vec<f64>,
+    new_per_day: i64,
+    graduating_interval: i64,
+    easy_interval: i64,
+    starting_ease: f64,
+    bury_new: bool,
+    review_per_day: i64,
+    easy_bonus: f64,
+    interval_modifier: f64,
+    max_interval: i64,
+    bury_reviews: bool,
+    lapse_steps: Vec<f64>,
+    lapse_new_interval: f64,
+    lapse_min_interval: i64,
+    leech_threshold: i64,
+    leech_action: LeechAction,
+    autoplay: bool,
+    replay_question: bool,
+    show_timer: bool,
+    max_answer_seconds: i64,
+}
+
+impl DeckConfig {
+    /// Creates new deck options with a unique `id` and a `name`.
+    ///
+    /// The id `1` belongs to the "Default" preset every collection has, using it replaces that
+    /// preset in the collection the package is imported into.
+    pub fn new(id: i64, name: &str) -> Self {
+        Self::from_deck_config_db_entry(DeckConfigDbEntry {
+            id,
+            name: name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the id of the deck options
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Returns the name of the deck options
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets the learning steps of new cards in minutes
+    pub fn new_steps(self, new_steps: Vec<f64>) -> Self {
+        Self { new_steps, ..self }
+    }
+
+    /// Sets the maximum number of new cards introduced per day
+    pub fn new_per_day(self, new_per_day: i64) -> Self {
+        Self {
+            new_per_day,
+            ..self
+        }
+    }
+
+    /// Sets the interval in days after a new card finished its learning steps
+    pub fn graduating_interval(self, graduating_interval: i64) -> Self {
+        Self {
+            graduating_interval,
+            ..self
+        }
+    }
+
+    /// Sets the interval in days after a new card was answered with "Easy"
+    pub fn easy_interval(self, easy_interval: i64) -> Self {
+        Self {
+            easy_interval,
+            ..self
+        }
+    }
+
+    /// Sets the ease new cards start with, e.g. `2.5` for 250%
+    pub fn starting_ease(self, starting_ease: f64) -> Self {
+        Self {
+            starting_ease,
+            ..self
+        }
+    }
+
+    /// Sets whether new sibling cards are buried until the next day
+    pub fn bury_new(self, bury_new: bool) -> Self {
+        Self { bury_new, ..self }
+    }
+
+    /// Sets the maximum number of reviews per day
+    pub fn review_per_day(self, review_per_day: i64) -> Self {
+        Self {
+            review_per_day,
+            ..self
+        }
+    }
+
+    /// Sets the extra multiplier applied to the interval when a review is answered with "Easy"
+    pub fn easy_bonus(self, easy_bonus: f64) -> Self {
+        Self { easy_bonus, ..self }
+    }
+
+    /// Sets the multiplier applied to all review intervals
+    pub fn interval_modifier(self, interval_modifier: f64) -> Self {
+        Self {
+            interval_modifier,
+            ..self
+        }
+    }
+
+    /// Sets the maximum review interval in days
+    pub fn max_interval(self, max_interval: i64) -> Self {
+        Self {
+            max_interval,
+            ..self
+        }
+    }
+
+    /// Sets whether review sibling cards are buried until the next day
+    pub fn bury_reviews(self, bury_reviews: bool) -> Self {
+        Self {
+            bury_reviews,
+            ..self
+        }
+    }
+
+    /// Sets the relearning steps of forgotten cards in minutes
+    pub fn lapse_steps(self, lapse_steps: Vec<f64>) -> Self {
+        Self {
+            lapse_steps,
+            ..self
+        }
+    }
+
+    /// Sets the multiplier applied to the interval of a forgotten card, `0.0` resets it
+    pub fn lapse_new_interval(self, lapse_new_interval: f64) -> Self {
+        Self {
+            lapse_new_interval,
+            ..self
+        }
+    }
+
+    /// Sets the minimum interval in days of a forgotten card
+    pub fn lapse_min_interval(self, lapse_min_interval: i64) -> Self {
+        Self {
+            lapse_min_interval,
+            ..self
+        }
+    }
+
+    /// Sets the number of lapses after which a card becomes a leech
+    pub fn leech_threshold(self, leech_threshold: i64) -> Self {
+        Self {
+            leech_threshold,
+            ..self
+        }
+    }
+
+    /// Sets what happens to leeches
+    pub fn leech_action(self, leech_action: LeechAction) -> Self {
+        Self {
+            leech_action,
+            ..self
+        }
+    }
+
+    /// Sets whether audio is played automatically
+    pub fn autoplay(self, autoplay: bool) -> Self {
+        Self { autoplay, ..self }
+    }
+
+    /// Sets whether the question audio is replayed when the answer is shown
+    pub fn replay_question(self, replay_question: bool) -> Self {
+        Self {
+            replay_question,
+            ..self
+        }
+    }
+
+    /// Sets whether an answer timer is shown
+    pub fn show_timer(self, show_timer: bool) -> Self {
+        Self { show_timer, ..self }
+    }
+
+    /// Sets the number of seconds after which the answer timer stops counting
+    pub fn max_answer_seconds(self, max_answer_seconds: i64) -> Self {
+        Self {
+            max_answer_seconds,
+            ..self
+        }
+    }
+
+    pub(crate) fn from_deck_config_db_entry(entry: DeckConfigDbEntry) -> Self {
+        Self {
+            id: entry.id,
+            name: entry.name,
+            new_steps: entry.new.delays,
+            new_per_day: entry.new.per_day,
+            graduating_interval: entry.new.ints.first().copied().unwrap_or(1),
+            easy_interval: entry.new.ints.get(1).copied().unwrap_or(4),
+            starting_ease: entry.new.initial_factor as f64 / 1000.0,
+            bury_new: entry.new.bury,
+            review_per_day: entry.rev.per_day,
+            easy_bonus: entry.rev.ease4,
+            interval_modifier: entry.rev.ivl_fct,
+            max_interval: entry.rev.max_ivl,
+            bury_reviews: entry.rev.bury,
+            lapse_steps: entry.lapse.delays,
+            lapse_new_interval: entry.lapse.mult,
+            lapse_min_interval: entry.lapse.min_int,
+            leech_threshold: entry.lapse.leech_fails,
+            leech_action: if entry.lapse.leech_action == 1 {
+                LeechAction::TagOnly
+            } else {
+                LeechAction::Suspend
+            },
+            autoplay: entry.autoplay,
+            replay_question: entry.replayq,
+            show_timer: entry.timer != 0,
+            max_answer_seconds: entry.max_taken,
+        }
+    }
+
+    pub(crate) fn to_deck_config_db_entry(&self, timestamp: f64) -> DeckConfigDbEntry {
+        let defaults = DeckConfigDbEntry::default();
+        DeckConfigDbEntry {
+            autoplay: self.autoplay,
+            id: self.id,
+            lapse: LapseConfigDbEntry {
+                delays: self.lapse_steps.clone(),
+                leech_action: match self.leech_action {
+                    LeechAction::Suspend => 0,
+                    LeechAction::TagOnly => 1,
+                },
+                leech_fails: self.leech_threshold,
+                min_int: self.lapse_min_interval,
+                mult: self.lapse_new_interval,
+            },
+            max_taken: self.max_answer_seconds,
+            deck_config_db_entry_mod: timestamp as i64,
+            name: self.name.clone(),
+            new: NewConfigDbEntry {
+                bury: self.bury_new,
+                delays: self.new_steps.clone(),
+                initial_factor: (self.starting_ease * 1000.0).round() as i64,
+                ints: vec![self.graduating_interval, self.easy_interval, 7],
+                per_day: self.new_per_day,
+                ..defaults.new
+            },
+            replayq: self.replay_question,
+            rev: RevConfigDbEntry {
+                bury: self.bury_reviews,
+                ease4: self.easy_bonus,
+                ivl_fct: self.interval_modifier,
+                max_ivl: self.max_interval,
+                per_day: self.review_per_day,
+                ..defaults.rev
+            },
+            timer: self.show_timer as i64,
+            usn: -1,
+        }
+    }
+}
+
+/// Serialized form of a `DeckConfig`, in which all options except `id` and `name` default to
+/// those of `DeckConfig::new`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeckConfigDefinition {
+    id: i64,
+    name: String,
+    #[serde(default = "default_new_steps")]
+    new_steps: Vec<f64>,
+    #[serde(default = "default_new_per_day")]
+    new_per_day: i64,
+    #[serde(default = "default_graduating_interval")]
+    graduating_interval: i64,
+    #[serde(default = "default_easy_interval")]
+    easy_interval: i64,
+    #[serde(default = "default_starting_ease")]
+    starting_ease: f64,
+    #[serde(default = "default_bury_new")]
+    bury_new: bool,
+    #[serde(default = "default_review_per_day")]
+    review_per_day: i64,
+    #[serde(default = "default_easy_bonus")]
+    easy_bonus: f64,
+    #[serde(default = "default_interval_modifier")]
+    interval_modifier: f64,
+    #[serde(default = "default_max_interval")]
+    max_interval: i64,
+    #[serde(default = "default_bury_reviews")]
+    bury_reviews: bool,
+    #[serde(default = "default_lapse_steps")]
+    lapse_steps: Vec<f64>,
+    #[serde(default = "default_lapse_new_interval")]
+    lapse_new_interval: f64,
+    #[serde(default = "default_lapse_min_interval")]
+    lapse_min_interval: i64,
+    #[serde(default = "default_leech_threshold")]
+    leech_threshold: i64,
+    #[serde(default = "default_leech_action")]
+    leech_action: LeechAction,
+    #[serde(default = "default_autoplay")]
+    autoplay: bool,
+    #[serde(default = "default_replay_question")]
+    replay_question: bool,
+    #[serde(default = "default_show_timer")]
+    show_timer: bool,
+    #[serde(default = "default_max_answer_seconds")]
+    max_answer_seconds: i64,
+}
+
+/// Options of an unnamed `DeckConfig`, whose defaults are used for missing fields
+fn defaults() -> DeckConfig {
+    DeckConfig::new(0, "")
+}
+
+fn default_new_steps() -> Vec<f64> {
+    defaults().new_steps
+}
+
+fn default_new_per_day() -> i64 {
+    defaults().new_per_day
+}
+
+fn default_graduating_interval() -> i64 {
+    defaults().graduating_interval
+}
+
+fn default_easy_interval() -> i64 {
+    defaults().easy_interval
+}
+
+fn default_starting_ease() -> f64 {
+    defaults().starting_ease
+}
+
+fn default_bury_new() -> bool {
+    defaults().bury_new
+}
+
+fn default_review_per_day() -> i64 {
+    defaults().review_per_day
+}
+
+fn default_easy_bonus() -> f64 {
+    defaults().easy_bonus
+}
+
+fn default_interval_modifier() -> f64 {
+    defaults().interval_modifier
+}
+
+fn default_max_interval() -> i64 {
+    defaults().max_interval
+}
+
+fn default_bury_reviews() -> bool {
+    defaults().bury_reviews
+}
+
+fn default_lapse_steps() -> Vec<f64> {
+    defaults().lapse_steps
+}
+
+fn default_lapse_new_interval() -> f64 {
+    defaults().lapse_new_interval
+}
+
+fn default_lapse_min_interval() -> i64 {
+    defaults().lapse_min_interval
+}
+
+fn default_leech_threshold() -> i64 {
+    defaults().leech_threshold
+}
+
+fn default_leech_action() -> LeechAction {
+    defaults().leech_action
+}
+
+fn default_autoplay() -> bool {
+    defaults().autoplay
+}
+
+fn default_replay_question() -> bool {
+    defaults().replay_question
+}
+
+fn default_show_timer() -> bool {
+    defaults().show_timer
+}
+
+fn default_max_answer_seconds() -> i64 {
+    defaults().max_answer_seconds
+}
+
+impl From<DeckConfig> for DeckConfigDefinition {
+    fn from(config: DeckConfig) -> Self {
+        Self {
+            id: config.id,
+            name: config.name,
+            new_steps: config.new_steps,
+            new_per_day: config.new_per_day,
+            graduating_interval: config.graduating_interval,
+            easy_interval: config.easy_interval,
+            starting_ease: config.starting_ease,
+            bury_new: config.bury_new,
+            review_per_day: config.review_per_day,
+            easy_bonus: config.easy_bonus,
+            interval_modifier: config.interval_modifier,
+            max_interval: config.max_interval,
+            bury_reviews: config.bury_reviews,
+            lapse_steps: config.lapse_steps,
+            lapse_new_interval: config.lapse_new_interval,
+            lapse_min_interval: config.lapse_min_interval,
+            leech_threshold: config.leech_threshold,
+            leech_action: config.leech_action,
+            autoplay: config.autoplay,
+            replay_question: config.replay_question,
+            show_timer: config.show_timer,
+            max_answer_seconds: config.max_answer_seconds,
+        }
+    }
+}
+
+impl From<DeckConfigDefinition> for DeckConfig {
+    fn from(definition: DeckConfigDefinition) -> Self {
+        Self {
+            id: definition.id,
+            name: definition.name,
+            new_steps: definition.new_steps,
+            new_per_day: definition.new_per_day,
+            graduating_interval: definition.graduating_interval,
+            easy_interval: definition.easy_interval,
+            starting_ease: definition.starting_ease,
+            bury_new: definition.bury_new,
+            review_per_day: definition.review_per_day,
+            easy_bonus: definition.easy_bonus,
+            interval_modifier: definition.interval_modifier,
+            max_interval: definition.max_interval,
+            bury_reviews: definition.bury_reviews,
+            lapse_steps: definition.lapse_steps,
+            lapse_new_interval: definition.lapse_new_interval,
+            lapse_min_interval: definition.lapse_min_interval,
+            leech_threshold: definition.leech_threshold,
+            leech_action: definition.leech_action,
+            autoplay: definition.autoplay,
+            replay_question: definition.replay_question,
+            show_timer: definition.show_timer,
+            max_answer_seconds: definition.max_answer_seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_anki() {
+        let entry = DeckConfig::new(1, "Default").to_deck_config_db_entry(0.0);
+        let expected = serde_json::to_value(DeckConfigDbEntry {
+            usn: -1,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(serde_json::to_value(entry).unwrap(), expected);
+    }
+
+    #[test]
+    fn db_entry_round_trip() {
+        let config = DeckConfig::new(42, "Course")
+            .new_steps(vec![5.0, 30.0])
+            .new_per_day(7)
+            .starting_ease(2.3)
+            .lapse_new_interval(0.5)
+            .leech_action(LeechAction::TagOnly)
+            .show_timer(true);
+        let entry = config.to_deck_config_db_entry(1_600_000_000.0);
+        assert_eq!(entry.new.initial_factor, 2300);
+        assert_eq!(entry.lapse.leech_action, 1);
+        assert_eq!(entry.timer, 1);
+        assert_eq!(DeckConfig::from_deck_config_db_entry(entry), config);
+    }
+
+    #[test]
+    fn serde_defaults() {
+        let config: DeckConfig =
+            serde_json::from_str(r#"{"id": 3, "name": "Course", "leech_action": "tag_only"}"#)
+                .unwrap();
+        assert_eq!(
+            config,
+            DeckConfig::new(3, "Course").leech_action(LeechAction::TagOnly)
+        );
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(serde_json::from_value::<DeckConfig>(value).unwrap(), config);
+
+        let error = serde_json::from_str::<DeckConfig>(r#"{"name": "Course"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "missing field `id` at line 1 column 18");
+    }
+}
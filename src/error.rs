@@ -3,6 +3,8 @@ use std::{convert::Infallible, time::SystemTimeError};
 use zip::result::ZipError;
 
 use crate::db_entries::Tmpl;
+use crate::merge::MergeConflict;
+use crate::zero_cards::EmptyNote;
 
 // Make sure `Error` is `Send` and `Sync`
 const fn _assert_send<T: Send>() {}
@@ -30,11 +32,70 @@ pub enum Error {
     #[error(transparent)]
     JsonParser(Box<dyn std::error::Error + Send + Sync>),
     #[error("Could not compute required fields for this template; please check the formatting of \"qfmt\": {0:?}")]
-    TemplateFormat(Tmpl),
+    TemplateFormat(Box<Tmpl>),
     #[error("number of model field ({0}) does not match number of fields ({1})")]
     ModelFieldCountMismatch(usize, usize),
     #[error("One of the tags contains whitespace, this is not allowed!")]
     TagContainsWhitespace,
+    /// Indicates that a deck name is empty, has an empty part between `::` separators or that a
+    /// subdeck name contains `::`
+    #[error("Invalid deck name {0:?}")]
+    InvalidDeckName(String),
+    /// Indicates that two different decks of a package have the same name or the same id
+    #[error("Conflicting decks: {0}")]
+    DeckConflict(String),
+    /// Indicates that the scheduling state of a card is not consistent with what Anki expects
+    #[error("Invalid card state: {0}")]
+    InvalidCardState(String),
+    /// Indicates that an entry of the review history of a card is invalid
+    #[error("Invalid review log entry: {0}")]
+    InvalidReviewLogEntry(String),
+    /// Indicates that a note has no card with the given template ordinal
+    #[error("The note has no card with ordinal {0}")]
+    CardNotFound(i64),
+    /// Indicates that a note is added to a deck which was not added to the `PackageWriter`
+    #[error("The deck {0} was not added to the package")]
+    DeckNotFound(i64),
+    /// Indicates that a card is put into a deck which is not part of the package
+    #[error("A card is put into the deck {0}, which is not part of the package")]
+    CardDeckNotFound(i64),
+    /// Indicates that a card template could not be rendered, e.g. because it refers to an unknown
+    /// field or has an unclosed conditional
+    #[error("Could not render template: {0}")]
+    TemplateRender(String),
+    /// Indicates that the name of a media file is empty or contains a path separator
+    #[error("Invalid media file name {0:?}")]
+    InvalidMediaName(String),
+    /// Indicates that several different media files of a package have the same name
+    #[error("Several different media files are called {0:?}")]
+    MediaNameCollision(String),
+    /// Indicates that a CSV/TSV file is malformed or that a column given to the `CsvImporter` is
+    /// not in it
+    #[error("Invalid CSV input: {0}")]
+    Csv(String),
+    /// Indicates that a template refers to a field its model does not have
+    #[error(
+        "The template {0:?} refers to the field {1:?}, but the model only has the fields {2:?}"
+    )]
+    TemplateUnknownField(String, String, Vec<String>),
+    /// Indicates that a note sets a field its model does not have
+    #[error("The model has no field called {0:?}")]
+    UnknownField(String),
+    /// Indicates that a note of a deserialized deck refers to a model the deck does not define
+    #[error("The model {0} is not defined in the deck")]
+    UnknownModel(i64),
+    /// Indicates that two notes have the same value in the key field of a `NoteManifest`
+    #[error("Two notes have the key {0:?}")]
+    DuplicateKey(String),
+    /// Indicates that merged packages conflict and the merge policy is to fail
+    #[error("{0}")]
+    MergeConflict(MergeConflict),
+    /// Indicates that a note generates no cards and the `ZeroCardPolicy` is to reject it
+    #[error("{0}")]
+    NoCards(EmptyNote),
+    /// Indicates that an `.apkg` file could not be read back because it is malformed
+    #[error("Invalid package: {0}")]
+    InvalidPackage(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     /// Indicates an error with the underlying template system
@@ -65,11 +126,11 @@ impl From<Infallible> for Error {
 }
 
 pub(crate) fn database_error(e: rusqlite::Error) -> Error {
-    Error::Template(Box::new(e))
+    Error::Database(Box::new(e))
 }
 
 pub(crate) fn json_error(e: serde_json::Error) -> Error {
-    Error::Template(Box::new(e))
+    Error::JsonParser(Box::new(e))
 }
 
 pub(crate) fn template_error(e: ramhorns::Error) -> Error {
@@ -0,0 +1,339 @@
+use crate::builtin_models::image_occlusion_model;
+use crate::{AnkiNote, Error, MediaFile, Model, Note};
+use std::fmt;
+
+/// A shape which hides part of the image of an [`ImageOcclusion`] note
+///
+/// Coordinates are fractions of the width and height of the image, so `0.5` is its middle, like
+/// Anki stores them since 23.10.1.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Shape {
+    /// A rectangle with its top left corner at `left`, `top`
+    Rect {
+        left: f64,
+        top: f64,
+        width: f64,
+        height: f64,
+    },
+    /// An ellipse with the radii `rx`, `ry` whose bounding box has its top left corner at `left`,
+    /// `top`
+    Ellipse {
+        left: f64,
+        top: f64,
+        rx: f64,
+        ry: f64,
+    },
+    /// A polygon through the `(x, y)` points
+    Polygon { points: Vec<(f64, f64)> },
+}
+
+impl Shape {
+    /// Creates a rectangle
+    pub fn rect(left: f64, top: f64, width: f64, height: f64) -> Self {
+        Shape::Rect {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    /// Creates an ellipse
+    pub fn ellipse(left: f64, top: f64, rx: f64, ry: f64) -> Self {
+        Shape::Ellipse { left, top, rx, ry }
+    }
+
+    /// Creates a polygon
+    pub fn polygon(points: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        Shape::Polygon {
+            points: points.into_iter().collect(),
+        }
+    }
+}
+
+/// Writes the shape the way it appears in the `Occlusion` field, e.g.
+/// `rect:left=.1:top=.2:width=.3:height=.4`
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Rect {
+                left,
+                top,
+                width,
+                height,
+            } => write!(
+                f,
+                "rect:left={}:top={}:width={}:height={}",
+                number(*left),
+                number(*top),
+                number(*width),
+                number(*height)
+            ),
+            Shape::Ellipse { left, top, rx, ry } => write!(
+                f,
+                "ellipse:left={}:top={}:rx={}:ry={}",
+                number(*left),
+                number(*top),
+                number(*rx),
+                number(*ry)
+            ),
+            Shape::Polygon { points } => {
+                let points = points
+                    .iter()
+                    .map(|(x, y)| format!("{},{}", number(*x), number(*y)))
+                    .collect::<Vec<_>>();
+                write!(f, "polygon:points={}", points.join(" "))
+            }
+        }
+    }
+}
+
+/// Formats a coordinate with four decimals and without leading and trailing zeros, like Anki's
+/// editor does with `toFixed(4).replace(/^0+|0+$/g, "")`, which keeps the zero after a minus sign
+fn number(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    let text = if text.starts_with('-') {
+        text.trim_end_matches('0')
+    } else {
+        text.trim_start_matches('0').trim_end_matches('0')
+    };
+    if text == "." {
+        ".0000".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// A note of [`image_occlusion_model`] which hides groups of shapes on an image, one group per
+/// card
+///
+/// Each call to [`ImageOcclusion::group`] adds a card, on which the shapes of that group are
+/// asked. By default, the other groups stay hidden as well ("Hide All, Guess One" in Anki). The
+/// image has to be added to the package as a media file.
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::{Deck, Error, ImageOcclusion, MediaFile, Package, Shape};
+///
+/// # fn main() -> Result<(), Error> {
+/// let image = MediaFile::from_bytes("heart.png", vec![0x89, b'P', b'N', b'G'])?;
+/// let note = ImageOcclusion::new(&image)
+///     .header("Chambers of the heart")
+///     .group(vec![Shape::rect(0.1, 0.2, 0.3, 0.15)])
+///     .group(vec![
+///         Shape::ellipse(0.6, 0.2, 0.05, 0.05),
+///         Shape::polygon(vec![(0.5, 0.6), (0.7, 0.6), (0.6, 0.8)]),
+///     ])
+///     .note()?;
+/// assert_eq!(note.cards().len(), 2);
+///
+/// let mut deck = Deck::new(1234, "Anatomy", "");
+/// deck.add_note(note);
+/// let package = Package::new_with_media(vec![deck], vec![image]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageOcclusion {
+    image: String,
+    groups: Vec<Vec<Shape>>,
+    hide_all: bool,
+    header: String,
+    back_extra: String,
+    comments: String,
+}
+
+impl ImageOcclusion {
+    /// Creates a note without shapes which occludes `image`
+    pub fn new(image: &MediaFile) -> Self {
+        Self {
+            image: image.name().to_string(),
+            groups: vec![],
+            hide_all: true,
+            header: String::new(),
+            back_extra: String::new(),
+            comments: String::new(),
+        }
+    }
+
+    /// Adds a group of shapes which are asked together on a new card
+    pub fn group(mut self, shapes: impl IntoIterator<Item = Shape>) -> Self {
+        self.groups.push(shapes.into_iter().collect());
+        self
+    }
+
+    /// Sets whether the shapes of the other groups are hidden too ("Hide All, Guess One"),
+    /// default is `true`. Otherwise only the asked group is hidden ("Hide One, Guess One").
+    pub fn hide_all(self, hide_all: bool) -> Self {
+        Self { hide_all, ..self }
+    }
+
+    /// Sets the text shown above the image
+    pub fn header(self, header: &str) -> Self {
+        Self {
+            header: header.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the text shown below the image on the answer side
+    pub fn back_extra(self, back_extra: &str) -> Self {
+        Self {
+            back_extra: back_extra.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the comments, which are not shown on the cards
+    pub fn comments(self, comments: &str) -> Self {
+        Self {
+            comments: comments.to_string(),
+            ..self
+        }
+    }
+
+    /// Returns the `Occlusion` field, with a cloze deletion per shape whose number is that of
+    /// its group
+    pub fn occlusion_field(&self) -> String {
+        let mut clozes = vec![];
+        for (idx, shapes) in self.groups.iter().enumerate() {
+            for shape in shapes {
+                let occlude_inactive = if self.hide_all { ":oi=1" } else { "" };
+                clozes.push(format!(
+                    "{{{{c{}::image-occlusion:{}{}}}}}",
+                    idx + 1,
+                    shape,
+                    occlude_inactive
+                ));
+            }
+        }
+        clozes.join("<br>")
+    }
+
+    /// Returns the `Image` field, an `<img>` tag of the image
+    pub fn image_field(&self) -> String {
+        format!("<img src=\"{}\">", self.image.replace('"', "&quot;"))
+    }
+
+    /// Creates the note, which has a card per group
+    pub fn note(&self) -> Result<Note, Error> {
+        Note::new(
+            image_occlusion_model(),
+            vec![
+                self.occlusion_field().as_str(),
+                &self.image_field(),
+                &self.header,
+                &self.back_extra,
+                &self.comments,
+            ],
+        )
+    }
+}
+
+impl AnkiNote for ImageOcclusion {
+    fn model() -> Model {
+        image_occlusion_model()
+    }
+
+    fn to_note(&self) -> Result<Note, Error> {
+        self.note()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_clozes, Deck, Package};
+    use std::io::Cursor;
+
+    fn image() -> MediaFile {
+        MediaFile::from_bytes("heart \"1\".png", vec![0x89, b'P', b'N', b'G']).unwrap()
+    }
+
+    #[test]
+    fn shapes_are_written_like_anki_does() {
+        assert_eq!(
+            Shape::rect(0.1, 0.25, 1.0, 0.0).to_string(),
+            "rect:left=.1:top=.25:width=1.:height=.0000"
+        );
+        assert_eq!(
+            Shape::ellipse(0.12345, 0.5, 0.03, 0.04).to_string(),
+            "ellipse:left=.1235:top=.5:rx=.03:ry=.04"
+        );
+        assert_eq!(
+            Shape::polygon(vec![(0.1, 0.2), (0.3, -0.05)]).to_string(),
+            "polygon:points=.1,.2 .3,-0.05"
+        );
+    }
+
+    #[test]
+    fn occlusion_field_has_a_cloze_per_shape() {
+        let occlusion = ImageOcclusion::new(&image())
+            .group(vec![Shape::rect(0.1, 0.2, 0.3, 0.4)])
+            .group(vec![
+                Shape::ellipse(0.5, 0.5, 0.1, 0.1),
+                Shape::rect(0.7, 0.7, 0.1, 0.1),
+            ]);
+        assert_eq!(
+            occlusion.occlusion_field(),
+            "{{c1::image-occlusion:rect:left=.1:top=.2:width=.3:height=.4:oi=1}}<br>\
+             {{c2::image-occlusion:ellipse:left=.5:top=.5:rx=.1:ry=.1:oi=1}}<br>\
+             {{c2::image-occlusion:rect:left=.7:top=.7:width=.1:height=.1:oi=1}}"
+        );
+        let clozes = parse_clozes(&occlusion.clone().hide_all(false).occlusion_field());
+        assert_eq!(
+            clozes[0].text(),
+            "image-occlusion:rect:left=.1:top=.2:width=.3:height=.4"
+        );
+        assert_eq!(
+            occlusion.image_field(),
+            "<img src=\"heart &quot;1&quot;.png\">"
+        );
+    }
+
+    #[test]
+    fn cards_show_masks_of_their_group() {
+        let note = ImageOcclusion::new(&image())
+            .header("Heart")
+            .back_extra("Left and right")
+            .group(vec![Shape::rect(0.1, 0.2, 0.3, 0.4)])
+            .group(vec![Shape::polygon(vec![
+                (0.1, 0.2),
+                (0.3, 0.4),
+                (0.5, 0.6),
+            ])])
+            .to_note()
+            .unwrap();
+        let cards = note.render("Anatomy").unwrap();
+        assert_eq!(cards.len(), 2);
+        assert!(cards[0].question().starts_with("<div>Heart</div>"));
+        assert!(cards[0].question().contains(
+            "<div class=\"cloze\" data-ordinal=\"1\" data-shape=\"rect\" data-left=\".1\" \
+             data-top=\".2\" data-width=\".3\" data-height=\".4\" data-oi=\"1\"></div>\
+             <br><div class=\"cloze-inactive\" data-ordinal=\"2\" data-shape=\"polygon\" \
+             data-points=\".1,.2 .3,.4 .5,.6\" data-oi=\"1\"></div>"
+        ));
+        assert!(cards[1]
+            .answer()
+            .contains("<div class=\"cloze-highlight\" data-ordinal=\"2\" data-shape=\"polygon\""));
+        assert!(cards[1].answer().contains("<div>Left and right</div>"));
+        assert!(!cards[1].question().contains("Left and right"));
+    }
+
+    #[test]
+    fn notes_are_written_with_the_image() {
+        let image = image();
+        let mut deck = Deck::new(1234, "Anatomy", "");
+        deck.add_note(
+            ImageOcclusion::new(&image)
+                .group(vec![Shape::rect(0.1, 0.2, 0.3, 0.4)])
+                .note()
+                .unwrap(),
+        );
+        let mut package = Package::new_with_media(vec![deck], vec![image]);
+        assert!(package.check_media().is_empty());
+        assert!(package.validate().is_empty());
+        package.write(Cursor::new(vec![])).unwrap();
+    }
+}
@@ -0,0 +1,566 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::deck::Deck;
+use crate::model::Model;
+use crate::note::Note;
+use crate::Error;
+
+/// Column of a CSV/TSV file, given by its name in the header row or by its index (starting at 0)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Index(usize),
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(name.to_string())
+    }
+}
+
+impl From<String> for Column {
+    fn from(name: String) -> Self {
+        Column::Name(name)
+    }
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Self {
+        Column::Index(index)
+    }
+}
+
+/// Imports the rows of a CSV or TSV file as notes of one `Model`
+///
+/// By default, the first row is a header and the model fields are read from the columns named
+/// like them. If the header does not name all fields, or if there is no header, the fields are
+/// read from the columns in order, skipping the tags, guid and deck columns. Use
+/// [`CsvImporter::fields`] to choose the columns explicitly.
+///
+/// Rows which cannot be imported are reported with their line number in
+/// [`CsvImport::errors`] and do not stop the import.
+///
+/// Example:
+/// ```rust,no_run
+/// use genanki_rs::{basic_model, CsvImporter, Deck, Error, Package};
+///
+/// # fn main() -> Result<(), Error> {
+/// let csv = "\
+/// Front,Back,Tags,Deck
+/// What is the capital of France?,Paris,geography europe,
+/// \"What is 2 + 2?\",4,math,Math
+/// ";
+/// let import = CsvImporter::new(basic_model(), Deck::new(1234, "Geography", ""))
+///     .tags_column("Tags")
+///     .deck_column("Deck")
+///     .import(csv.as_bytes())?;
+/// for error in import.errors() {
+///     eprintln!("{}", error);
+/// }
+/// let mut package = Package::new(import.into_decks(), vec![])?;
+/// package.write_to_file("output.apkg")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CsvImporter {
+    model: Model,
+    delimiter: char,
+    has_header: bool,
+    field_columns: Option<Vec<Column>>,
+    tags_column: Option<Column>,
+    guid_column: Option<Column>,
+    deck_column: Option<Column>,
+    decks: Vec<Deck>,
+}
+
+impl CsvImporter {
+    /// Creates an importer for comma separated files with a header row, which adds notes of
+    /// `model` to `deck`
+    pub fn new(model: Model, deck: Deck) -> Self {
+        Self {
+            model,
+            delimiter: ',',
+            has_header: true,
+            field_columns: None,
+            tags_column: None,
+            guid_column: None,
+            deck_column: None,
+            decks: vec![deck],
+        }
+    }
+
+    /// Sets the character separating the columns, e.g. `'\t'` for TSV files. Default is `','`
+    pub fn delimiter(self, delimiter: char) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    /// Sets whether the first row is a header naming the columns. Default is `true`
+    pub fn has_header(self, has_header: bool) -> Self {
+        Self { has_header, ..self }
+    }
+
+    /// Sets the columns the fields of the model are read from, one column per field in the order
+    /// of the model fields
+    pub fn fields(self, columns: impl IntoIterator<Item = impl Into<Column>>) -> Self {
+        Self {
+            field_columns: Some(columns.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    /// Sets the column holding the tags of a note, separated by whitespace
+    pub fn tags_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            tags_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Sets the column holding the GUID of a note. Notes with an empty GUID get the default
+    /// GUID computed from their fields
+    pub fn guid_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            guid_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Sets the column holding the name of the deck a note is added to
+    ///
+    /// Notes with an empty deck name are added to the deck given to [`CsvImporter::new`]. Names
+    /// of decks added with [`CsvImporter::with_deck`] select those decks, and for other names a
+    /// new deck is created, whose id is derived from its name.
+    pub fn deck_column(self, column: impl Into<Column>) -> Self {
+        Self {
+            deck_column: Some(column.into()),
+            ..self
+        }
+    }
+
+    /// Adds a deck rows can be routed into with the deck column
+    pub fn with_deck(mut self, deck: Deck) -> Self {
+        self.decks.push(deck);
+        self
+    }
+
+    /// Imports the file at `path`
+    ///
+    /// Returns `Err` if the file cannot be read or a configured column is not in its header
+    pub fn import_file(self, path: impl AsRef<Path>) -> Result<CsvImport, Error> {
+        self.import(File::open(path)?)
+    }
+
+    /// Imports all rows read from `reader`
+    ///
+    /// Returns `Err` if `reader` fails or a configured column is not in the header. Errors of
+    /// single rows are collected in the returned `CsvImport` instead.
+    pub fn import(self, mut reader: impl Read) -> Result<CsvImport, Error> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+        let mut records = parse_records(text, self.delimiter).into_iter();
+
+        let header = if self.has_header {
+            match records.next() {
+                Some((_, Ok(header))) => Some(header),
+                Some((line, Err(message))) => {
+                    return Err(Error::Csv(format!("line {}: {}", line, message)))
+                }
+                None => None,
+            }
+        } else {
+            None
+        };
+        let columns = self.resolve_columns(header.as_deref())?;
+
+        let mut import = CsvImport {
+            decks: self.decks.clone(),
+            errors: vec![],
+        };
+        for (line, record) in records {
+            let result = record
+                .map_err(Error::Csv)
+                .and_then(|row| self.import_row(&columns, &row, &mut import.decks));
+            if let Err(error) = result {
+                import.errors.push(RowError { line, error });
+            }
+        }
+        Ok(import)
+    }
+
+    fn resolve_columns(&self, header: Option<&[String]>) -> Result<ResolvedColumns, Error> {
+        let resolve = |column: &Column| match (column, header) {
+            (Column::Index(index), _) => Ok(*index),
+            (Column::Name(name), Some(header)) => header
+                .iter()
+                .position(|title| title.trim() == name)
+                .ok_or_else(|| Error::Csv(format!("there is no column named {:?}", name))),
+            (Column::Name(name), None) => Err(Error::Csv(format!(
+                "the column {:?} is given by name, but the file has no header",
+                name
+            ))),
+        };
+        let tags = self.tags_column.as_ref().map(resolve).transpose()?;
+        let guid = self.guid_column.as_ref().map(resolve).transpose()?;
+        let deck = self.deck_column.as_ref().map(resolve).transpose()?;
+
+        let field_names: Vec<String> = self
+            .model
+            .fields()
+            .into_iter()
+            .map(|field| field.name)
+            .collect();
+        let fields = match &self.field_columns {
+            Some(columns) => {
+                if columns.len() != field_names.len() {
+                    return Err(Error::ModelFieldCountMismatch(
+                        field_names.len(),
+                        columns.len(),
+                    ));
+                }
+                FieldColumns::Mapped(columns.iter().map(resolve).collect::<Result<_, _>>()?)
+            }
+            None => {
+                let by_name = header.and_then(|header| {
+                    field_names
+                        .iter()
+                        .map(|name| header.iter().position(|title| title.trim() == name))
+                        .collect::<Option<Vec<_>>>()
+                });
+                match by_name {
+                    Some(indices) => FieldColumns::Mapped(indices),
+                    None => FieldColumns::Remaining(
+                        [tags, guid, deck].iter().flatten().copied().collect(),
+                    ),
+                }
+            }
+        };
+        Ok(ResolvedColumns {
+            fields,
+            tags,
+            guid,
+            deck,
+        })
+    }
+
+    fn import_row(
+        &self,
+        columns: &ResolvedColumns,
+        row: &[String],
+        decks: &mut Vec<Deck>,
+    ) -> Result<(), Error> {
+        let field_count = self.model.fields().len();
+        let fields: Vec<&str> = match &columns.fields {
+            FieldColumns::Mapped(indices) => indices
+                .iter()
+                .map(|&index| row.get(index).map(String::as_str))
+                .collect::<Option<_>>()
+                .ok_or_else(|| Error::ModelFieldCountMismatch(field_count, row.len()))?,
+            FieldColumns::Remaining(skipped) => row
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !skipped.contains(index))
+                .map(|(_, value)| value.as_str())
+                .collect(),
+        };
+        if fields.len() != field_count {
+            return Err(Error::ModelFieldCountMismatch(field_count, fields.len()));
+        }
+        let value = |column: Option<usize>| {
+            column
+                .and_then(|index| row.get(index))
+                .map(|value| value.trim())
+                .unwrap_or_default()
+        };
+
+        let mut note =
+            Note::new(self.model.clone(), fields)?.tags(value(columns.tags).split_whitespace());
+        let guid = value(columns.guid);
+        if !guid.is_empty() {
+            note = note.guid(guid);
+        }
+        let deck_name = value(columns.deck);
+        let deck_index = if deck_name.is_empty() {
+            0
+        } else {
+            match decks.iter().position(|deck| deck.name() == deck_name) {
+                Some(index) => index,
+                None => {
+                    decks.push(Deck::new(deck_id_for(deck_name), deck_name, ""));
+                    decks.len() - 1
+                }
+            }
+        };
+        decks[deck_index].add_note(note);
+        Ok(())
+    }
+}
+
+/// Result of a [`CsvImporter`] run: the decks with the imported notes and the rows which could
+/// not be imported
+pub struct CsvImport {
+    decks: Vec<Deck>,
+    errors: Vec<RowError>,
+}
+
+impl CsvImport {
+    /// Returns the decks of the importer, followed by the decks created for unknown deck names
+    pub fn decks(&self) -> &[Deck] {
+        &self.decks
+    }
+
+    /// Returns the rows which could not be imported
+    pub fn errors(&self) -> &[RowError] {
+        &self.errors
+    }
+
+    /// Returns the decks, e.g. to pass them to [`Package::new`](crate::Package::new)
+    pub fn into_decks(self) -> Vec<Deck> {
+        self.decks
+    }
+}
+
+/// Error of a single row of a CSV/TSV file
+#[derive(Debug)]
+pub struct RowError {
+    line: usize,
+    error: Error,
+}
+
+impl RowError {
+    /// Returns the line the row starts at, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the reason the row could not be imported
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+struct ResolvedColumns {
+    fields: FieldColumns,
+    tags: Option<usize>,
+    guid: Option<usize>,
+    deck: Option<usize>,
+}
+
+enum FieldColumns {
+    /// Index of the column of each model field
+    Mapped(Vec<usize>),
+    /// All columns except these, in order
+    Remaining(HashSet<usize>),
+}
+
+/// Derives a deck id from the name of a deck, so importing the same file again gives the same ids
+fn deck_id_for(name: &str) -> i64 {
+    let hash = Sha256::digest(name.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    // Same range as the ids genanki suggests for decks
+    (1 << 30) + (u64::from_be_bytes(bytes) % (1 << 30)) as i64
+}
+
+/// Splits `text` into records of values separated by `delimiter`, together with the line each
+/// record starts at. Values may be quoted with `"`, which allows them to contain delimiters,
+/// line breaks and `""` for a quote. Empty lines are skipped.
+fn parse_records(text: &str, delimiter: char) -> Vec<(usize, Result<Vec<String>, String>)> {
+    let mut records = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start_line = line;
+        let mut record = vec![];
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut at_value_start = true;
+        loop {
+            let c = match chars.next() {
+                Some(c) => c,
+                None if quoted => {
+                    records.push((start_line, Err("unterminated quoted value".to_string())));
+                    return records;
+                }
+                None => break,
+            };
+            if c == '\n' {
+                line += 1;
+            }
+            if quoted {
+                if c == '"' {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        value.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                } else {
+                    value.push(c);
+                }
+            } else if c == '"' && at_value_start {
+                quoted = true;
+                at_value_start = false;
+            } else if c == delimiter {
+                record.push(std::mem::take(&mut value));
+                at_value_start = true;
+            } else if c == '\n' || (c == '\r' && chars.peek() == Some(&'\n')) {
+                if c == '\r' {
+                    chars.next();
+                    line += 1;
+                }
+                break;
+            } else {
+                value.push(c);
+                at_value_start = false;
+            }
+        }
+        record.push(value);
+        if record.len() > 1 || !record[0].is_empty() {
+            records.push((start_line, Ok(record)));
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_model;
+
+    fn importer() -> CsvImporter {
+        CsvImporter::new(basic_model(), Deck::new(1234, "Default", ""))
+    }
+
+    fn fields(deck: &Deck) -> Vec<Vec<String>> {
+        deck.notes()
+            .iter()
+            .map(|note| note.fields().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let records = parse_records("a,\"b,c\",\"say \"\"hi\"\"\"\r\n\n\"multi\nline\",x\n", ',');
+        assert_eq!(
+            records,
+            vec![
+                (
+                    1,
+                    Ok(vec![
+                        "a".to_string(),
+                        "b,c".to_string(),
+                        "say \"hi\"".to_string()
+                    ])
+                ),
+                (3, Ok(vec!["multi\nline".to_string(), "x".to_string()])),
+            ]
+        );
+        assert_eq!(
+            parse_records("a\n\"open", ','),
+            vec![
+                (1, Ok(vec!["a".to_string()])),
+                (2, Err("unterminated quoted value".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_fields_by_header_name() -> Result<(), Error> {
+        let csv = "Tags,Back,Front\nt1 t2,Paris,France\n";
+        let import = importer().tags_column("Tags").import(csv.as_bytes())?;
+        assert!(import.errors().is_empty());
+        let deck = &import.decks()[0];
+        assert_eq!(fields(deck), vec![vec!["France", "Paris"]]);
+        assert_eq!(deck.notes()[0].get_tags(), ["t1", "t2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn maps_fields_by_position() -> Result<(), Error> {
+        let tsv = "q1\tguid1\ta1\nq2\t\ta2\n";
+        let import = importer()
+            .delimiter('\t')
+            .has_header(false)
+            .guid_column(1)
+            .import(tsv.as_bytes())?;
+        assert!(import.errors().is_empty());
+        let notes = import.decks()[0].notes();
+        assert_eq!(fields(&import.decks()[0]), vec![["q1", "a1"], ["q2", "a2"]]);
+        assert_eq!(notes[0].get_guid(), "guid1");
+        assert_eq!(notes[1].get_guid(), crate::guid_for(&["q2", "a2"]));
+
+        let import = importer()
+            .fields(vec![2, 0])
+            .has_header(false)
+            .import("a,b,c\n".as_bytes())?;
+        assert_eq!(fields(&import.decks()[0]), vec![["c", "a"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn routes_rows_into_decks() -> Result<(), Error> {
+        let csv = "Front,Back,Deck\n1,a,\n2,b,Other\n3,c,New\n4,d,New\n";
+        let import = importer()
+            .deck_column("Deck")
+            .with_deck(Deck::new(5678, "Other", ""))
+            .import(csv.as_bytes())?;
+        let decks = import.decks();
+        assert_eq!(decks.len(), 3);
+        assert_eq!(fields(&decks[0]), vec![["1", "a"]]);
+        assert_eq!(decks[1].id(), 5678);
+        assert_eq!(fields(&decks[1]), vec![["2", "b"]]);
+        assert_eq!(decks[2].name(), "New");
+        assert_eq!(decks[2].id(), deck_id_for("New"));
+        assert_eq!(fields(&decks[2]), vec![["3", "c"], ["4", "d"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_row_errors_with_line_numbers() -> Result<(), Error> {
+        let csv = "Front,Back\n\"<b>ok</b>\",\nonly one value\n\n\"x\ny\"\n";
+        let import = importer().import(csv.as_bytes())?;
+        assert_eq!(import.decks()[0].notes().len(), 1);
+        let errors: Vec<_> = import.errors().iter().map(RowError::line).collect();
+        assert_eq!(errors, vec![3, 5]);
+        assert!(matches!(
+            import.errors()[0].error(),
+            Error::ModelFieldCountMismatch(2, 1)
+        ));
+        assert!(import.errors()[1]
+            .to_string()
+            .starts_with("line 5: number of model field"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        let result = importer()
+            .tags_column("Tags")
+            .import("Front,Back\n".as_bytes());
+        assert!(matches!(result, Err(Error::Csv(_))));
+        let result = importer()
+            .has_header(false)
+            .deck_column("Deck")
+            .import("a,b\n".as_bytes());
+        assert!(matches!(result, Err(Error::Csv(_))));
+    }
+}
@@ -39,6 +39,19 @@
 //!
 //! You pass in a `Model`, discussed below, and a set of `fields` (encoded as HTML).
 //!
+//! To avoid having to remember the order of the fields, a note can also be
+//! built by field name with [`Note::builder`]. Fields which are not set stay
+//! empty, and naming a field the model does not have is an error:
+//!
+//! ```rust,ignore
+//! let my_note = Note::builder(my_model)
+//!     .field("Answer", "Buenos Aires")
+//!     .field("Question", "Capital of Argentina")
+//!     .with_tag("geography")
+//!     .build()?;
+//! assert_eq!(my_note.field("Answer"), Some("Buenos Aires"));
+//! ```
+//!
 //! ### Models
 //! A `Model` defines the fields and cards for a type of `Note`. For example:
 //!
@@ -80,6 +93,11 @@
 //! You need to pass a model `id` and a model `name` so that Anki can keep track of your model. It's important that you use a unique model `id`
 //! for each `Model` you define.
 //!
+//! Notes of a cloze model get one card per cloze number in the fields the
+//! templates use with a `cloze` filter, like `{{cloze:Text}}`. Deletions are
+//! parsed like Anki does, including nested deletions and hints, and
+//! [`parse_clozes`] returns them with their number, text, hint and position.
+//!
 //! ### Generating a Deck/Package
 //! To import your notes into Anki, you need to add them to a `Deck`:
 //!
@@ -171,38 +189,178 @@
 //!
 //! You should only put the filename (aka basename) and not the full path in the field; `<img src="images/image.jpg">` will *not* work. Media files should have unique filenames.
 //!
+//! Media created in memory, e.g. generated images or synthesized audio, does not have to be written to disk first. Wrap it in a `MediaFile`, which takes bytes, a `Read` source or a path together with the filename used in the notes:
+//!
+//! ```rust,ignore
+//! use genanki_rs::{Error, MediaFile, Package};
+//!
+//! fn main() -> Result<(), Error> {
+//!     // ...
+//!     let media = vec![
+//!         MediaFile::from_bytes("sound.mp3", synthesized_audio)?,
+//!         MediaFile::from_path_with_name("images/image-1.jpg", "image.jpg")?,
+//!     ];
+//!     let mut my_package = Package::new_with_media(vec![my_deck], media);
+//!     my_package.write_to_file("output.apkg")?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! ### Image occlusion
+//! [`image_occlusion_model`] is a model like the Image Occlusion note type of
+//! Anki 23.10 and later. [`ImageOcclusion`] builds its notes from an image
+//! [`MediaFile`] and groups of [`Shape`]s, each of which becomes a card:
+//!
+//! ```rust
+//! use genanki_rs::{Error, ImageOcclusion, MediaFile, Shape};
+//!
+//! # fn main() -> Result<(), Error> {
+//! let image = MediaFile::from_bytes("heart.png", vec![0x89, b'P', b'N', b'G'])?;
+//! let note = ImageOcclusion::new(&image)
+//!     .group(vec![Shape::rect(0.1, 0.2, 0.3, 0.15)])
+//!     .group(vec![Shape::ellipse(0.6, 0.2, 0.05, 0.05)])
+//!     .note()?;
+//! assert_eq!(note.cards().len(), 2);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Remember to add the image to the `Package` as well.
+//!
 //! ### sort_field
 //! Anki has a value for each `Note` called the `sort_field`. Anki uses this
 //! value to sort the cards in the Browse interface. Anki also is happier if
 //! you avoid having two notes with the same `sort_field`, although this isn't
-//! strictly necessary. By default, the `sort_field` is the first field, but
-//! you can change it by calling [`Note::sort_field`].
+//! strictly necessary. By default, the `sort_field` is the first field with
+//! HTML removed, but you can set it to any text by calling
+//! [`Note::sort_field`].
 //!
 //! You can also call [`Model::sort_field_index`], passing the
 //! `sort_field_index` to change the sort field. `0` means the first field in
 //! the Note, `1` means the second, etc.
 //!
+//! ### Validating notes
+//! By default, writing a package does not check the contents of the notes,
+//! and it never prints anything. [`Package::validate`] and
+//! [`Deck::validate`] return a [`ValidationReport`] of invalid HTML,
+//! unbalanced cloze deletions, notes without cards, duplicate sort fields and
+//! oversized fields, with the note GUID, field name and byte range of each
+//! problem. A [`Validator`] can also
+//! pass the problems to a callback as they are found, e.g. to log them.
+//!
+//! Notes which generate no cards are written anyway unless a
+//! [`ZeroCardPolicy`] on the package or deck skips them or rejects them with
+//! [`Error::NoCards`], naming the template requirements they do not meet.
+//!
+//! ### Updating decks
+//! Notes whose content was edited get a new GUID by default and arrive in
+//! Anki as new notes. A [`NoteManifest`] of the previous release gives the
+//! notes of a regenerated deck the GUIDs and ids of the previous notes with
+//! the same value in a key field, and [`NoteManifest::update`] reports which
+//! notes were added, changed and removed.
+//!
+//! ### Merging packages
+//! A [`Merger`] combines packages or decks built separately into one. Decks
+//! with the same id are merged, identical notes, models and media files are
+//! kept once, and conflicting ones are resolved by a [`MergePolicy`] for each
+//! kind: keep the first, keep the newest, rename the later one, or fail. The
+//! resolved conflicts are listed in the resulting [`Merge`].
+//!
+//! ### Serde
+//! [`Model`], [`Field`], [`Template`], [`Deck`], [`Note`] and [`DeckConfig`]
+//! implement `Serialize` and `Deserialize`, so note types and decks can be
+//! kept in TOML, YAML or JSON files. Options with default values can be left
+//! out, and deserializing a model fails with a helpful error if a template
+//! refers to a field the model does not have. See [`Model`] and [`Deck`] for
+//! the format.
+//!
+//! ### Typed notes
+//! Note types can also be Rust structs implementing [`AnkiNote`]. With the
+//! `derive` feature, `#[derive(AnkiNote)]` generates the model from the struct
+//! and checks at compile time that the templates only refer to its fields, so
+//! `Note`s are created from values instead of positional strings. See
+//! [`AnkiNote`] for an example.
+//!
+//! ### Importing spreadsheets
+//! Notes can be imported from CSV and TSV files with a [`CsvImporter`]. It
+//! maps the columns to the fields of a model by their header names, or by
+//! position if there is no header, and can read tags, guids and the deck of
+//! each note from further columns. Rows which cannot be imported are reported
+//! with their line number instead of failing the whole import:
+//!
+//! ```rust,no_run
+//! use genanki_rs::{basic_model, CsvImporter, Deck, Error, Package};
+//!
+//! fn main() -> Result<(), Error> {
+//!     let import = CsvImporter::new(basic_model(), Deck::new(2059400110, "Country Capitals", ""))
+//!         .delimiter('\t')
+//!         .tags_column("Tags")
+//!         .deck_column("Deck")
+//!         .import_file("capitals.tsv")?;
+//!     for error in import.errors() {
+//!         eprintln!("Skipped {}", error);
+//!     }
+//!     let mut my_package = Package::new(import.into_decks(), vec![])?;
+//!     my_package.write_to_file("output.apkg")?;
+//!     Ok(())
+//! }
+//! ```
+//!
 
+mod anki_note;
 mod apkg_col;
 mod apkg_schema;
 mod builders;
 mod builtin_models;
 mod card;
+mod cloze;
+mod collection;
 mod db_entries;
 mod deck;
+mod deck_config;
 mod error;
+mod image_occlusion;
+mod import;
+mod media;
+mod merge;
 mod model;
 mod note;
 mod package;
+mod package_writer;
+mod protobuf;
+mod reader;
+mod render;
+mod revlog;
+mod update;
 mod util;
+mod validation;
+mod zero_cards;
 
-pub use builders::{Field, Template};
+pub use anki_note::AnkiNote;
+pub use builders::{Field, NoteBuilder, Template};
 pub use builtin_models::*;
+pub use card::{Card, CardState, CardType, Flag};
+pub use cloze::{parse_clozes, ClozeDeletion};
 pub use deck::Deck;
+pub use deck_config::{DeckConfig, LeechAction};
 pub use error::Error;
+pub use image_occlusion::{ImageOcclusion, Shape};
+pub use import::{Column, CsvImport, CsvImporter, RowError};
+pub use media::{MediaFile, MediaIssue, MediaLocation};
+pub use merge::{Merge, MergeConflict, MergePolicy, Merger};
 pub use model::{Model, ModelType};
 pub use note::Note;
-pub use package::Package;
+pub use package::{Package, PackageFormat};
+pub use package_writer::PackageWriter;
+pub use render::RenderedCard;
+pub use revlog::{Ease, ReviewKind, ReviewLogEntry};
+pub use update::{DeckUpdate, NoteManifest};
+pub use util::guid_for;
+pub use validation::{IssueKind, ValidationIssue, ValidationReport, Validator};
+pub use zero_cards::{CardRequirement, EmptyNote, RequirementKind, ZeroCardPolicy};
+
+#[cfg(feature = "derive")]
+pub use genanki_rs_derive::AnkiNote;
 
 #[cfg(test)]
 mod tests {
@@ -524,6 +682,59 @@ def check_media(col):
         });
     }
 
+    #[test]
+    #[serial]
+    fn anki21_package_can_be_imported() {
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            let mut deck = Deck::new(123456, "foodeck", "");
+            deck.add_note(Note::new(cn_model(), vec!["a", "b", "c"]).unwrap());
+            let package = Package::new(vec![deck], vec![])
+                .unwrap()
+                .format(PackageFormat::Anki21);
+            setup.import_package(package, None);
+            assert!(setup.check_col(
+                "{i['name'] for i in col.decks.all()} == {'Default', 'foodeck'} and len(col.find_cards('')) == 2"
+            ));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn deck_with_config() {
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            let config = DeckConfig::new(1_500_000_000, "Course")
+                .new_per_day(42)
+                .max_interval(365);
+            let mut deck = Deck::new(123456, "foodeck", "").with_config(config);
+            deck.add_note(Note::new(basic_model(), vec!["a", "b"]).unwrap());
+            setup.import_package(Package::new(vec![deck], vec![]).unwrap(), None);
+            assert!(setup.check_col(
+                "[col.decks.config_dict_for_deck_id(d['id']) for d in col.decks.all() if d['name'] == 'foodeck'][0]['new']['perDay'] == 42"
+            ));
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn nested_decks() {
+        Python::with_gil(|py| {
+            let mut setup = TestSetup::new(&py);
+            let mut chapter = Deck::new(2, "Chapter 1", "");
+            chapter.add_note(Note::new(basic_model(), vec!["a", "b"]).unwrap());
+            let mut textbook = Deck::new(10, "Library::Textbook", "");
+            textbook.add_subdeck(chapter).unwrap();
+            setup.import_package(Package::new(vec![textbook], vec![]).unwrap(), None);
+            assert!(setup.check_col(
+                "{'Library', 'Library::Textbook', 'Library::Textbook::Chapter 1'} <= {d['name'] for d in col.decks.all()}"
+            ));
+            assert!(setup.check_col(
+                "col.decks.name(col.get_card(col.find_cards('')[0]).did) == 'Library::Textbook::Chapter 1'"
+            ));
+        });
+    }
+
     #[test]
     fn model_req() {
         let req = model().req().unwrap();
@@ -598,11 +809,11 @@ def check_media(col):
         deck.add_note(note);
         std::fs::File::create("present.mp3")
             .unwrap()
-            .write(VALID_MP3)
+            .write_all(VALID_MP3)
             .unwrap();
         std::fs::File::create("present.jpg")
             .unwrap()
-            .write(VALID_JPG)
+            .write_all(VALID_JPG)
             .unwrap();
         Python::with_gil(|py| {
             let mut setup = TestSetup::new(&py);
@@ -641,11 +852,11 @@ def check_media(col):
         let present_jpg_path = tmp_dir.path().join("present.jpg");
         std::fs::File::create(present_mp3_path.clone())
             .unwrap()
-            .write(VALID_MP3)
+            .write_all(VALID_MP3)
             .unwrap();
         std::fs::File::create(present_jpg_path.clone())
             .unwrap()
-            .write(VALID_JPG)
+            .write_all(VALID_JPG)
             .unwrap();
         Python::with_gil(|py| {
             let mut setup = TestSetup::new(&py);
@@ -0,0 +1,579 @@
+use crate::deck::Deck;
+use crate::Error;
+use fancy_regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A media file (image, sound, ...) to be packed into a `Package`
+///
+/// The content can be given as bytes, read from any `Read` source or be loaded from a path when
+/// the package is written. Notes refer to media files by their `name`, e.g. `[sound:hello.mp3]`
+/// or `<img src="cat.jpg">`.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Error, MediaFile};
+///
+/// # fn main() -> Result<(), Error> {
+/// let synthesized: Vec<u8> = vec![0xff, 0xfb, 0x90, 0x64];
+/// let audio = MediaFile::from_bytes("hello.mp3", synthesized)?;
+/// let image = MediaFile::from_reader("cat.jpg", &b"not really a jpg"[..])?;
+/// let on_disk = MediaFile::from_path_with_name("images/photo-1.jpg", "photo.jpg")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaFile {
+    name: String,
+    source: MediaSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum MediaSource {
+    Bytes(Vec<u8>),
+    Path(PathBuf),
+}
+
+impl MediaFile {
+    /// Creates a media file called `name` with the content `data`
+    ///
+    /// Returns `Err` if `name` is not a plain file name
+    pub fn from_bytes(name: &str, data: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        Ok(Self {
+            name: check_name(name)?,
+            source: MediaSource::Bytes(data.into()),
+        })
+    }
+
+    /// Creates a media file called `name` with the content of `reader`, which is read to the end
+    /// immediately
+    ///
+    /// Returns `Err` if `name` is not a plain file name or if `reader` fails
+    pub fn from_reader(name: &str, mut reader: impl Read) -> Result<Self, Error> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(name, data)
+    }
+
+    /// Creates a media file which is read from `path` when the package is written. It is named
+    /// like the last component of `path`.
+    ///
+    /// Returns `Err` if `path` does not end with a valid file name
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::InvalidMediaName(path.display().to_string()))?;
+        Self::from_path_with_name(path, name)
+    }
+
+    /// Creates a media file called `name` which is read from `path` when the package is written
+    ///
+    /// Returns `Err` if `name` is not a plain file name
+    pub fn from_path_with_name(path: impl AsRef<Path>, name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            name: check_name(name)?,
+            source: MediaSource::Path(path.as_ref().to_path_buf()),
+        })
+    }
+
+    /// Returns the name under which the file is stored in the package
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a copy of the file stored under `name`, which has to be a valid name
+    pub(crate) fn with_name(&self, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            source: self.source.clone(),
+        }
+    }
+
+    /// Returns the path the file is read from, if it was not given as bytes
+    pub fn path(&self) -> Option<&Path> {
+        match &self.source {
+            MediaSource::Path(path) => Some(path),
+            MediaSource::Bytes(_) => None,
+        }
+    }
+
+    /// Returns the content of the file
+    ///
+    /// Returns `Err` if the file has a path which cannot be read
+    pub fn data(&self) -> Result<Vec<u8>, Error> {
+        match &self.source {
+            MediaSource::Bytes(data) => Ok(data.clone()),
+            MediaSource::Path(path) => Ok(std::fs::read(path)?),
+        }
+    }
+
+    /// Opens the content of the file for reading, without loading files from disk into memory
+    pub(crate) fn open(&self) -> Result<Box<dyn Read + '_>, Error> {
+        match &self.source {
+            MediaSource::Bytes(data) => Ok(Box::new(&data[..])),
+            MediaSource::Path(path) => Ok(Box::new(File::open(path)?)),
+        }
+    }
+}
+
+/// Anki stores all media files in one flat folder, so names cannot contain path separators
+fn check_name(name: &str) -> Result<String, Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(&['/', '\\'][..]) {
+        return Err(Error::InvalidMediaName(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+/// Where a media file is referred to
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MediaLocation {
+    /// A field of the note with `note_guid`
+    Field { note_guid: String, field: String },
+    /// A template or the CSS of the model with `model_id`
+    Model {
+        model_id: i64,
+        template: Option<String>,
+    },
+}
+
+impl fmt::Display for MediaLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaLocation::Field { note_guid, field } => {
+                write!(f, "field {:?} of note {:?}", field, note_guid)
+            }
+            MediaLocation::Model {
+                model_id,
+                template: Some(template),
+            } => write!(f, "template {:?} of model {}", template, model_id),
+            MediaLocation::Model {
+                model_id,
+                template: None,
+            } => write!(f, "CSS of model {}", model_id),
+        }
+    }
+}
+
+/// A problem with the media files of a `Package`, found by
+/// [`Package::check_media`](crate::Package::check_media)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MediaIssue {
+    /// A note or model refers to a file which is not part of the package
+    Missing {
+        name: String,
+        location: MediaLocation,
+    },
+    /// A file of the package is not referred to by any note or model. Files starting with `_`
+    /// are never reported, as Anki keeps them for use in templates.
+    Unused { name: String },
+    /// Several files of the package with different content have the same name, so only one of
+    /// them would end up in the collection. Files which cannot be read count as different.
+    Collision { name: String, count: usize },
+}
+
+impl MediaIssue {
+    /// Returns whether the issue makes the package incomplete. Unused files are only a warning.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, MediaIssue::Unused { .. })
+    }
+}
+
+impl fmt::Display for MediaIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaIssue::Missing { name, location } => {
+                write!(
+                    f,
+                    "missing file {:?}, referred to in the {}",
+                    name, location
+                )
+            }
+            MediaIssue::Unused { name } => write!(f, "unused file {:?}", name),
+            MediaIssue::Collision { name, count } => {
+                write!(f, "{} different files are called {:?}", count, name)
+            }
+        }
+    }
+}
+
+/// Scans the notes of `decks` and their models for media references and compares them with
+/// `media_files`
+pub(crate) fn check_media(decks: &[Deck], media_files: &[MediaFile]) -> Vec<MediaIssue> {
+    let mut references = vec![];
+    let mut seen_models = HashSet::new();
+    collect_references(decks, &mut seen_models, &mut references);
+
+    let mut issues = vec![];
+    let available = media_files
+        .iter()
+        .map(MediaFile::name)
+        .collect::<HashSet<_>>();
+    let mut reported = HashSet::new();
+    for (name, location) in &references {
+        if !available.contains(name.as_str()) && reported.insert((name, location)) {
+            issues.push(MediaIssue::Missing {
+                name: name.clone(),
+                location: location.clone(),
+            });
+        }
+    }
+
+    let referenced = references
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<HashSet<_>>();
+    let mut by_name: BTreeMap<&str, Vec<&MediaFile>> = BTreeMap::new();
+    for media_file in media_files {
+        by_name
+            .entry(media_file.name())
+            .or_default()
+            .push(media_file);
+    }
+    for (name, files) in by_name {
+        let mut distinct: Vec<&MediaFile> = vec![];
+        for file in files {
+            if !distinct.iter().any(|other| same_content(other, file)) {
+                distinct.push(file);
+            }
+        }
+        if distinct.len() > 1 {
+            issues.push(MediaIssue::Collision {
+                name: name.to_string(),
+                count: distinct.len(),
+            });
+        }
+        if !referenced.contains(name) && !name.starts_with('_') {
+            issues.push(MediaIssue::Unused {
+                name: name.to_string(),
+            });
+        }
+    }
+    issues
+}
+
+/// Returns whether both files have the same content, wherever it comes from. A file which cannot
+/// be read only has the same content as itself.
+fn same_content(file: &MediaFile, other: &MediaFile) -> bool {
+    file == other || matches!((file.data(), other.data()), (Ok(data), Ok(other)) if data == other)
+}
+
+fn collect_references(
+    decks: &[Deck],
+    seen_models: &mut HashSet<i64>,
+    references: &mut Vec<(String, MediaLocation)>,
+) {
+    for deck in decks {
+        for note in deck.notes() {
+            let model = note.model();
+            for (field, value) in model.fields().iter().zip(note.fields()) {
+                for name in media_references(value) {
+                    references.push((
+                        name,
+                        MediaLocation::Field {
+                            note_guid: note.get_guid().to_string(),
+                            field: field.name.clone(),
+                        },
+                    ));
+                }
+            }
+            if seen_models.insert(model.id) {
+                for template in model.templates() {
+                    let text = format!("{}{}", template.qfmt, template.afmt);
+                    for name in media_references(&text) {
+                        references.push((
+                            name,
+                            MediaLocation::Model {
+                                model_id: model.id,
+                                template: Some(template.name.clone()),
+                            },
+                        ));
+                    }
+                }
+                for name in media_references(model.get_css()) {
+                    references.push((
+                        name,
+                        MediaLocation::Model {
+                            model_id: model.id,
+                            template: None,
+                        },
+                    ));
+                }
+            }
+        }
+        collect_references(deck.subdecks(), seen_models, references);
+    }
+}
+
+/// Patterns of media references: `[sound:...]`, the `src` of HTML tags and CSS `url(...)`. The
+/// name is in one of the capture groups.
+const MEDIA_REFERENCE_REGEXES: [&str; 3] = [
+    r"\[sound:(.+?)\]",
+    r#"(?si)<\b(?:img|audio|video|object|source)\b[^>]+\b(?:src|data)\b=(?:"([^"]+)"|'([^']+)'|([^ >]+))"#,
+    r#"(?i)\burl\(\s*(?:"([^"]+)"|'([^']+)'|([^)'"\s]+))\s*\)"#,
+];
+
+/// Returns the names of the files `text` refers to with `[sound:...]`, the `src` of HTML tags or
+/// CSS `url(...)`, together with their position in `text`. Links to other websites and field
+/// replacements are skipped.
+fn media_reference_ranges(text: &str) -> Vec<(String, Range<usize>)> {
+    // Compiled once, as this runs for every field of every note
+    static REGEXES: OnceLock<Vec<Regex>> = OnceLock::new();
+    let regexes = REGEXES.get_or_init(|| {
+        MEDIA_REFERENCE_REGEXES
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("static regex"))
+            .collect()
+    });
+    let mut names = vec![];
+    for regex in regexes {
+        for caps in regex.captures_iter(text).filter_map(Result::ok) {
+            let group = match (1..caps.len()).find_map(|idx| caps.get(idx)) {
+                Some(group) => group,
+                None => continue,
+            };
+            let name = decode_entities(group.as_str().trim());
+            if name.is_empty()
+                || name.contains("://")
+                || name.starts_with("data:")
+                || name.contains("{{")
+            {
+                continue;
+            }
+            names.push((name, group.range()));
+        }
+    }
+    names
+}
+
+/// Returns the names of the files `text` refers to, see `media_reference_ranges`
+fn media_references(text: &str) -> Vec<String> {
+    media_reference_ranges(text)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Replaces the references to the media file `old` in `text` by references to `new`
+pub(crate) fn rename_media_references(text: &str, old: &str, new: &str) -> String {
+    let mut ranges: Vec<Range<usize>> = media_reference_ranges(text)
+        .into_iter()
+        .filter(|(name, _)| name == old)
+        .map(|(_, range)| range)
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    let mut renamed = String::with_capacity(text.len());
+    let mut end = 0;
+    for range in ranges {
+        if range.start < end {
+            continue;
+        }
+        renamed.push_str(&text[end..range.start]);
+        renamed.push_str(new);
+        end = range.end;
+    }
+    renamed.push_str(&text[end..]);
+    renamed
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn sources() {
+        let bytes = MediaFile::from_bytes("a.mp3", b"bytes".to_vec()).unwrap();
+        assert_eq!(bytes.name(), "a.mp3");
+        assert_eq!(bytes.path(), None);
+        assert_eq!(bytes.data().unwrap(), b"bytes");
+
+        let read = MediaFile::from_reader("b.jpg", &b"read"[..]).unwrap();
+        assert_eq!(read.data().unwrap(), b"read");
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"file").unwrap();
+        let from_path = MediaFile::from_path(file.path()).unwrap();
+        assert_eq!(
+            Some(from_path.name()),
+            file.path().file_name().and_then(|name| name.to_str())
+        );
+        assert_eq!(from_path.path(), Some(file.path()));
+        let mut content = vec![];
+        from_path.open().unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"file");
+
+        let renamed = MediaFile::from_path_with_name(file.path(), "c.png").unwrap();
+        assert_eq!(renamed.name(), "c.png");
+        assert_eq!(renamed.data().unwrap(), b"file");
+    }
+
+    #[test]
+    fn invalid_names() {
+        for name in ["", "..", "images/a.png", "images\\a.png"] {
+            assert!(matches!(
+                MediaFile::from_bytes(name, vec![]),
+                Err(Error::InvalidMediaName(_))
+            ));
+        }
+        assert!(MediaFile::from_path("images/..").is_err());
+        assert!(MediaFile::from_path("images/a.png").is_ok());
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            media_references(
+                r#"[sound:a b.mp3] <img class="x" src="c&amp;d.jpg"> <IMG SRC='e.png'> <img src=f.gif>"#
+            ),
+            vec!["a b.mp3", "c&d.jpg", "e.png", "f.gif"]
+        );
+        assert_eq!(
+            media_references(
+                r#"@font-face { src: url("_font.ttf"); } .x { background: url(bg.png) }"#
+            ),
+            vec!["_font.ttf", "bg.png"]
+        );
+        assert!(media_references(
+            r#"<img src="https://example.com/a.png"> <img src="{{Image}}"> <img src="data:image/png;base64,AA">"#
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn renamed_references() {
+        assert_eq!(
+            rename_media_references(
+                r#"[sound:a.mp3] <img src="a.mp3"> <img src=ba.mp3> url('a.mp3') a.mp3"#,
+                "a.mp3",
+                "a_2.mp3"
+            ),
+            r#"[sound:a_2.mp3] <img src="a_2.mp3"> <img src=ba.mp3> url('a_2.mp3') a.mp3"#
+        );
+    }
+
+    #[test]
+    fn issues() {
+        use crate::{basic_model, Field, Model, Note, Template};
+
+        let model = Model::new(
+            1,
+            "With logo",
+            vec![Field::new("Front"), Field::new("Back")],
+            vec![Template::new("Card 1")
+                .qfmt(r#"<img src="logo.png">{{Front}}"#)
+                .afmt("{{Back}}")],
+        );
+        let mut deck = Deck::new(10, "Deck", "");
+        deck.add_note(Note::new(model, vec!["[sound:a.mp3]", "[sound:missing.mp3]"]).unwrap());
+        let mut subdeck = Deck::new(11, "Subdeck", "");
+        subdeck.add_note(
+            Note::new(basic_model(), vec![r#"<img src="b.png">"#, ""])
+                .unwrap()
+                .guid("sub"),
+        );
+        deck.add_subdeck(subdeck).unwrap();
+
+        let media_files = vec![
+            MediaFile::from_bytes("a.mp3", b"a".to_vec()).unwrap(),
+            MediaFile::from_bytes("logo.png", b"logo".to_vec()).unwrap(),
+            MediaFile::from_path_with_name("images/b.png", "b.png").unwrap(),
+            MediaFile::from_path_with_name("audio/b.png", "b.png").unwrap(),
+            MediaFile::from_bytes("unused.jpg", b"unused".to_vec()).unwrap(),
+            MediaFile::from_bytes("_font.ttf", b"font".to_vec()).unwrap(),
+            MediaFile::from_bytes("_font.ttf", b"font".to_vec()).unwrap(),
+        ];
+        let issues = check_media(&[deck], &media_files);
+        let note_guid = Note::new(basic_model(), vec!["[sound:a.mp3]", "[sound:missing.mp3]"])
+            .unwrap()
+            .get_guid()
+            .to_string();
+        assert_eq!(
+            issues,
+            vec![
+                MediaIssue::Missing {
+                    name: "missing.mp3".to_string(),
+                    location: MediaLocation::Field {
+                        note_guid,
+                        field: "Back".to_string()
+                    }
+                },
+                MediaIssue::Collision {
+                    name: "b.png".to_string(),
+                    count: 2
+                },
+                MediaIssue::Unused {
+                    name: "unused.jpg".to_string()
+                },
+            ]
+        );
+        assert!(issues[0].is_error());
+        assert!(issues[1].is_error());
+        assert!(!issues[2].is_error());
+
+        let media_files = vec![
+            MediaFile::from_bytes("b.png", b"one".to_vec()).unwrap(),
+            MediaFile::from_bytes("b.png", b"two".to_vec()).unwrap(),
+        ];
+        let result =
+            crate::Package::new_with_media(vec![], media_files).write(std::io::Cursor::new(vec![]));
+        assert!(matches!(result, Err(Error::MediaNameCollision(name)) if name == "b.png"));
+    }
+
+    #[test]
+    fn same_content_is_no_collision() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"logo").unwrap();
+        let media_files = vec![
+            MediaFile::from_bytes("_logo.png", b"logo".to_vec()).unwrap(),
+            MediaFile::from_path_with_name(file.path(), "_logo.png").unwrap(),
+        ];
+        assert!(check_media(&[], &media_files).is_empty());
+
+        std::fs::write(file.path(), b"other logo").unwrap();
+        assert_eq!(
+            check_media(&[], &media_files),
+            [MediaIssue::Collision {
+                name: "_logo.png".to_string(),
+                count: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn issue_messages() {
+        let issue = MediaIssue::Missing {
+            name: "a.mp3".to_string(),
+            location: MediaLocation::Model {
+                model_id: 1,
+                template: Some("Card 1".to_string()),
+            },
+        };
+        assert_eq!(
+            issue.to_string(),
+            r#"missing file "a.mp3", referred to in the template "Card 1" of model 1"#
+        );
+        let issue = MediaIssue::Collision {
+            name: "b.png".to_string(),
+            count: 2,
+        };
+        assert_eq!(issue.to_string(), r#"2 different files are called "b.png""#);
+    }
+}
@@ -0,0 +1,919 @@
+use crate::deck::{all_notes, Deck};
+use crate::media::MediaFile;
+use crate::note::Note;
+use crate::package::Package;
+use crate::util::guid_for;
+use crate::Error;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tempfile::TempDir;
+
+/// How a `Merger` resolves two items which share an id, guid or name but differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// Keeps the item which was merged first and leaves out the later one. Notes of a model which
+    /// is left out are left out as well.
+    KeepFirst,
+    /// Keeps the newest item. Notes are compared by their modification time, a note without one
+    /// counts as older than any note with one. Models and media files carry no modification time,
+    /// so the one merged last is kept.
+    KeepNewest,
+    /// Keeps both items by giving the later one a new model id and name, guid or file name.
+    /// Model names get a `+` appended until they are unique, like in Anki. References
+    /// to a renamed media file in the notes and models of its package are updated. A model whose
+    /// templates or CSS refer to a renamed file and whose id is also used by another package
+    /// gets a new id and name in the package of the file, so the other package keeps its
+    /// references.
+    Rename,
+    /// Fails the merge with [`Error::MergeConflict`]
+    #[default]
+    Error,
+}
+
+/// A conflict found while merging, see [`Merge::conflicts`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// Two models with the same id have different fields
+    Model {
+        /// Id of the models
+        id: i64,
+        /// Index of the package the later model is in
+        package: usize,
+        /// New id of the later model, with `MergePolicy::Rename`
+        renamed_to: Option<i64>,
+    },
+    /// Two notes with the same guid have different fields, tags or models
+    Note {
+        /// Guid of the notes
+        guid: String,
+        /// Index of the package the later note is in
+        package: usize,
+        /// New guid of the later note, with `MergePolicy::Rename`
+        renamed_to: Option<String>,
+    },
+    /// Two media files with the same name have different content
+    MediaFile {
+        /// Name of the files
+        name: String,
+        /// Index of the package the later file is in
+        package: usize,
+        /// New name of the later file, with `MergePolicy::Rename`
+        renamed_to: Option<String>,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::Model {
+                id,
+                package,
+                renamed_to,
+            } => {
+                write!(
+                    f,
+                    "model {} of package {} has other fields than an earlier model with the same id",
+                    id, package
+                )?;
+                if let Some(renamed_to) = renamed_to {
+                    write!(f, ", renamed to {}", renamed_to)?;
+                }
+            }
+            MergeConflict::Note {
+                guid,
+                package,
+                renamed_to,
+            } => {
+                write!(
+                    f,
+                    "note {} of package {} differs from an earlier note with the same guid",
+                    guid, package
+                )?;
+                if let Some(renamed_to) = renamed_to {
+                    write!(f, ", renamed to {}", renamed_to)?;
+                }
+            }
+            MergeConflict::MediaFile {
+                name,
+                package,
+                renamed_to,
+            } => {
+                write!(
+                    f,
+                    "media file {:?} of package {} differs from an earlier file with the same name",
+                    name, package
+                )?;
+                if let Some(renamed_to) = renamed_to {
+                    write!(f, ", renamed to {:?}", renamed_to)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges several packages or decks into one
+///
+/// Decks with the same id are merged into one deck, which keeps the name, description and
+/// options of the first. Identical models, notes and media files are only kept once. Models with
+/// the same id but different fields, notes with the same guid but different content and media
+/// files with the same name but different content are conflicts, which are resolved by a
+/// [`MergePolicy`] for each kind. The default policy is `MergePolicy::Error`.
+///
+/// Example:
+///
+/// ```rust,no_run
+/// use genanki_rs::{Error, MergePolicy, Merger, Package};
+///
+/// # fn main() -> Result<(), Error> {
+/// let merge = Merger::new()
+///     .notes(MergePolicy::KeepNewest)
+///     .media(MergePolicy::Rename)
+///     .merge(vec![
+///         Package::read_from_file("vocabulary.apkg")?,
+///         Package::read_from_file("grammar.apkg")?,
+///     ])?;
+/// for conflict in merge.conflicts() {
+///     eprintln!("{}", conflict);
+/// }
+/// merge.into_package().write_to_file("release.apkg")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Merger {
+    models: MergePolicy,
+    notes: MergePolicy,
+    media: MergePolicy,
+}
+
+/// The result of a merge: the merged decks and media files, and the conflicts which were resolved
+pub struct Merge {
+    decks: Vec<Deck>,
+    media_files: Vec<MediaFile>,
+    media_dirs: Vec<TempDir>,
+    conflicts: Vec<MergeConflict>,
+}
+
+impl Merge {
+    /// Returns the merged decks
+    pub fn decks(&self) -> &[Deck] {
+        &self.decks
+    }
+
+    /// Returns the merged media files
+    pub fn media_files(&self) -> &[MediaFile] {
+        &self.media_files
+    }
+
+    /// Returns the conflicts which were resolved by the merge policies
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.conflicts
+    }
+
+    /// Returns the merged decks and media files as a package
+    pub fn into_package(self) -> Package {
+        Package::from_parts(self.decks, self.media_files, self.media_dirs)
+    }
+
+    /// Returns the merged decks
+    pub fn into_decks(self) -> Vec<Deck> {
+        self.decks
+    }
+}
+
+/// What happens to the notes of a model of one package
+enum ModelAction {
+    Drop,
+    /// The model gets a new id and name
+    Rename(i64, String),
+}
+
+impl Merger {
+    /// Creates a merger which fails on every conflict
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the policy for all kinds of conflicts
+    pub fn policy(self, policy: MergePolicy) -> Self {
+        Self {
+            models: policy,
+            notes: policy,
+            media: policy,
+        }
+    }
+
+    /// Sets the policy for models with the same id but different fields
+    pub fn models(self, policy: MergePolicy) -> Self {
+        Self {
+            models: policy,
+            ..self
+        }
+    }
+
+    /// Sets the policy for notes with the same guid but different fields, tags or models
+    pub fn notes(self, policy: MergePolicy) -> Self {
+        Self {
+            notes: policy,
+            ..self
+        }
+    }
+
+    /// Sets the policy for media files with the same name but different content
+    pub fn media(self, policy: MergePolicy) -> Self {
+        Self {
+            media: policy,
+            ..self
+        }
+    }
+
+    /// Merges `decks`, each of which counts as a package of its own
+    ///
+    /// Returns `Err` on a conflict whose policy is `MergePolicy::Error`, or if a media file
+    /// cannot be read
+    pub fn merge_decks(&self, decks: impl IntoIterator<Item = Deck>) -> Result<Merge, Error> {
+        self.merge(
+            decks
+                .into_iter()
+                .map(|deck| Package::new_with_media(vec![deck], vec![])),
+        )
+    }
+
+    /// Merges `packages` in the given order
+    ///
+    /// Returns `Err` on a conflict whose policy is `MergePolicy::Error`, or if a media file
+    /// cannot be read to compare it with another file of the same name
+    pub fn merge(&self, packages: impl IntoIterator<Item = Package>) -> Result<Merge, Error> {
+        let mut inputs = vec![];
+        let mut media_dirs = vec![];
+        for package in packages {
+            let (decks, media_files, dirs) = package.into_parts();
+            inputs.push((decks, media_files));
+            media_dirs.extend(dirs);
+        }
+        let mut conflicts = vec![];
+        let mut used_model_ids: HashSet<i64> = HashSet::new();
+        let mut used_model_names: HashSet<String> = HashSet::new();
+        for note in inputs.iter().flat_map(|(decks, _)| all_notes(decks)) {
+            used_model_ids.insert(note.model().id);
+            used_model_names.insert(note.model().get_name().to_string());
+        }
+        let model_actions = self.merge_models(
+            &inputs,
+            &mut used_model_ids,
+            &mut used_model_names,
+            &mut conflicts,
+        )?;
+        let (media_files, renamed_media) = self.merge_media(&mut inputs, &mut conflicts)?;
+        // The packages which use each model id that is kept
+        let mut model_packages: HashMap<i64, HashSet<usize>> = HashMap::new();
+        for (package, (decks, _)) in inputs.iter().enumerate() {
+            for note in all_notes(decks) {
+                let id = match model_actions.get(&(package, note.model().id)) {
+                    Some(ModelAction::Drop) => continue,
+                    Some(ModelAction::Rename(id, _)) => *id,
+                    None => note.model().id,
+                };
+                model_packages.entry(id).or_default().insert(package);
+            }
+        }
+        // New ids and names of the models whose references to renamed media files changed
+        let mut forked_models: HashMap<(usize, i64), (i64, String)> = HashMap::new();
+
+        let mut used_guids: HashSet<String> = inputs
+            .iter()
+            .flat_map(|(decks, _)| all_notes(decks))
+            .map(|note| note.get_guid().to_string())
+            .collect();
+        let mut decks = vec![];
+        // Every merged note with the position of its deck in `decks`
+        let mut notes: Vec<(Vec<usize>, Note)> = vec![];
+        let mut guids: HashMap<String, usize> = HashMap::new();
+        for (package, (package_decks, _)) in inputs.into_iter().enumerate() {
+            let mut package_notes = vec![];
+            for deck in package_decks {
+                merge_deck(&mut decks, &mut vec![], deck, &mut package_notes);
+            }
+            for (path, mut note) in package_notes {
+                match model_actions.get(&(package, note.model().id)) {
+                    Some(ModelAction::Drop) => continue,
+                    Some(ModelAction::Rename(id, name)) => {
+                        note.model_mut().id = *id;
+                        note.model_mut().set_name(name);
+                    }
+                    None => {}
+                }
+                let mut model_changed = false;
+                for (old, new) in renamed_media.get(&package).into_iter().flatten() {
+                    model_changed |= note.rename_media(old, new);
+                }
+                let id = note.model().id;
+                if model_changed && model_packages.get(&id).is_some_and(|used| used.len() > 1) {
+                    let (new_id, new_name) = forked_models
+                        .entry((package, id))
+                        .or_insert_with(|| {
+                            let new_id = unused_model_id(id, &used_model_ids);
+                            let new_name =
+                                unused_model_name(note.model().get_name(), &used_model_names);
+                            used_model_ids.insert(new_id);
+                            used_model_names.insert(new_name.clone());
+                            (new_id, new_name)
+                        })
+                        .clone();
+                    note.model_mut().id = new_id;
+                    note.model_mut().set_name(&new_name);
+                }
+                let index = match guids.get(note.get_guid()) {
+                    Some(&index) => index,
+                    None => {
+                        guids.insert(note.get_guid().to_string(), notes.len());
+                        notes.push((path, note));
+                        continue;
+                    }
+                };
+                if same_content(&notes[index].1, &note) {
+                    continue;
+                }
+                let guid = note.get_guid().to_string();
+                let mut renamed_to = None;
+                match self.notes {
+                    MergePolicy::Error => {
+                        return Err(Error::MergeConflict(MergeConflict::Note {
+                            guid,
+                            package,
+                            renamed_to,
+                        }))
+                    }
+                    MergePolicy::KeepFirst => {}
+                    MergePolicy::KeepNewest => {
+                        if notes[index].1.get_modified() <= note.get_modified() {
+                            notes[index].1 = note;
+                        }
+                    }
+                    MergePolicy::Rename => {
+                        let new_guid = (1..)
+                            .map(|n| guid_for(&[guid.clone(), n.to_string()]))
+                            .find(|new_guid| !used_guids.contains(new_guid))
+                            .expect("unused guid");
+                        used_guids.insert(new_guid.clone());
+                        guids.insert(new_guid.clone(), notes.len());
+                        notes.push((path, note.guid(&new_guid)));
+                        renamed_to = Some(new_guid);
+                    }
+                }
+                conflicts.push(MergeConflict::Note {
+                    guid,
+                    package,
+                    renamed_to,
+                });
+            }
+        }
+        // Notes from different packages may have the same id, only the first one keeps it
+        let mut note_ids = HashSet::new();
+        for (path, mut note) in notes {
+            if let Some(id) = note.get_id() {
+                if !note_ids.insert(id) {
+                    note.set_id(None);
+                }
+            }
+            deck_at(&mut decks, &path).add_note(note);
+        }
+
+        Ok(Merge {
+            decks,
+            media_files,
+            media_dirs,
+            conflicts,
+        })
+    }
+
+    /// Compares the models of all packages and decides what happens to the notes of models whose
+    /// id was used before for other fields
+    fn merge_models(
+        &self,
+        inputs: &[(Vec<Deck>, Vec<MediaFile>)],
+        used_ids: &mut HashSet<i64>,
+        used_names: &mut HashSet<String>,
+        conflicts: &mut Vec<MergeConflict>,
+    ) -> Result<HashMap<(usize, i64), ModelAction>, Error> {
+        let mut actions = HashMap::new();
+        // Field names of the models kept so far, with the package they come from
+        let mut kept: HashMap<i64, (usize, Vec<String>)> = HashMap::new();
+        for (package, (decks, _)) in inputs.iter().enumerate() {
+            let mut models = vec![];
+            for note in all_notes(decks) {
+                let model = note.model();
+                if !models.iter().any(|(id, _, _)| *id == model.id) {
+                    let fields: Vec<String> =
+                        model.field_names().into_iter().map(String::from).collect();
+                    models.push((model.id, model.get_name(), fields));
+                }
+            }
+            for (id, name, fields) in models {
+                let kept_package = match kept.get(&id) {
+                    None => {
+                        kept.insert(id, (package, fields));
+                        continue;
+                    }
+                    Some((_, kept_fields)) if *kept_fields == fields => continue,
+                    Some((kept_package, _)) => *kept_package,
+                };
+                let mut renamed_to = None;
+                match self.models {
+                    MergePolicy::Error => {
+                        return Err(Error::MergeConflict(MergeConflict::Model {
+                            id,
+                            package,
+                            renamed_to,
+                        }))
+                    }
+                    MergePolicy::KeepFirst => {
+                        actions.insert((package, id), ModelAction::Drop);
+                    }
+                    MergePolicy::KeepNewest => {
+                        actions.insert((kept_package, id), ModelAction::Drop);
+                        kept.insert(id, (package, fields));
+                    }
+                    MergePolicy::Rename => {
+                        let new_id = unused_model_id(id, used_ids);
+                        let new_name = unused_model_name(name, used_names);
+                        used_ids.insert(new_id);
+                        used_names.insert(new_name.clone());
+                        actions.insert((package, id), ModelAction::Rename(new_id, new_name));
+                        kept.insert(new_id, (package, fields));
+                        renamed_to = Some(new_id);
+                    }
+                }
+                conflicts.push(MergeConflict::Model {
+                    id,
+                    package,
+                    renamed_to,
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Merges the media files of all packages. Returns them together with the files which were
+    /// renamed in each package, as pairs of the old and new name.
+    #[allow(clippy::type_complexity)]
+    fn merge_media(
+        &self,
+        inputs: &mut [(Vec<Deck>, Vec<MediaFile>)],
+        conflicts: &mut Vec<MergeConflict>,
+    ) -> Result<(Vec<MediaFile>, HashMap<usize, Vec<(String, String)>>), Error> {
+        let mut used_names: HashSet<String> = inputs
+            .iter()
+            .flat_map(|(_, files)| files.iter().map(|file| file.name().to_string()))
+            .collect();
+        let mut media_files: Vec<MediaFile> = vec![];
+        let mut names: HashMap<String, usize> = HashMap::new();
+        let mut renamed: HashMap<usize, Vec<(String, String)>> = HashMap::new();
+        for (package, (_, files)) in inputs.iter_mut().enumerate() {
+            for file in std::mem::take(files) {
+                let index = match names.get(file.name()) {
+                    Some(&index) => index,
+                    None => {
+                        names.insert(file.name().to_string(), media_files.len());
+                        media_files.push(file);
+                        continue;
+                    }
+                };
+                if media_files[index] == file || media_files[index].data()? == file.data()? {
+                    continue;
+                }
+                let name = file.name().to_string();
+                let mut renamed_to = None;
+                match self.media {
+                    MergePolicy::Error => {
+                        return Err(Error::MergeConflict(MergeConflict::MediaFile {
+                            name,
+                            package,
+                            renamed_to,
+                        }))
+                    }
+                    MergePolicy::KeepFirst => {}
+                    MergePolicy::KeepNewest => media_files[index] = file,
+                    MergePolicy::Rename => {
+                        let new_name = unused_name(&name, &used_names);
+                        used_names.insert(new_name.clone());
+                        names.insert(new_name.clone(), media_files.len());
+                        media_files.push(file.with_name(&new_name));
+                        renamed
+                            .entry(package)
+                            .or_default()
+                            .push((name.clone(), new_name.clone()));
+                        renamed_to = Some(new_name);
+                    }
+                }
+                conflicts.push(MergeConflict::MediaFile {
+                    name,
+                    package,
+                    renamed_to,
+                });
+            }
+        }
+        Ok((media_files, renamed))
+    }
+}
+
+/// Merges `deck` into the deck with the same id in `decks`, or appends it. Its notes are moved to
+/// `notes` together with the position of the deck they belong to.
+fn merge_deck(
+    decks: &mut Vec<Deck>,
+    path: &mut Vec<usize>,
+    mut deck: Deck,
+    notes: &mut Vec<(Vec<usize>, Note)>,
+) {
+    let (deck_notes, subdecks) = deck.take_contents();
+    let index = match decks.iter().position(|other| other.id() == deck.id()) {
+        Some(index) => index,
+        None => {
+            decks.push(deck);
+            decks.len() - 1
+        }
+    };
+    path.push(index);
+    notes.extend(deck_notes.into_iter().map(|note| (path.clone(), note)));
+    for subdeck in subdecks {
+        merge_deck(decks[index].subdecks_mut(), path, subdeck, notes);
+    }
+    path.pop();
+}
+
+fn deck_at<'a>(decks: &'a mut [Deck], path: &[usize]) -> &'a mut Deck {
+    let deck = &mut decks[path[0]];
+    match path.len() {
+        1 => deck,
+        _ => deck_at(deck.subdecks_mut(), &path[1..]),
+    }
+}
+
+fn same_content(note: &Note, other: &Note) -> bool {
+    note.model().id == other.model().id
+        && note.fields() == other.fields()
+        && note.get_tags() == other.get_tags()
+}
+
+/// Returns the first id after `id` which is unused
+fn unused_model_id(id: i64, used: &HashSet<i64>) -> i64 {
+    (id + 1..)
+        .find(|new_id| !used.contains(new_id))
+        .expect("unused model id")
+}
+
+/// Returns `name`, or if it is used, `name` with as many `+` appended as needed to make it
+/// unused, like Anki names a notetype whose name is taken
+fn unused_model_name(name: &str, used: &HashSet<String>) -> String {
+    let mut name = name.to_string();
+    while used.contains(&name) {
+        name.push('+');
+    }
+    name
+}
+
+/// Returns `name` with the first suffix `_2`, `_3`, ... before the extension which makes it
+/// unused
+fn unused_name(name: &str, used: &HashSet<String>) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    (2..)
+        .map(|n| format!("{}_{}{}", stem, n, extension))
+        .find(|candidate| !used.contains(candidate))
+        .expect("unused name")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, Field, Model, PackageFormat, Template};
+    use std::io::Cursor;
+
+    fn note(fields: Vec<&str>) -> Note {
+        Note::new(basic_model(), fields).unwrap()
+    }
+
+    fn package(deck: Deck, media_files: Vec<MediaFile>) -> Package {
+        Package::new_with_media(vec![deck], media_files)
+    }
+
+    fn other_layout() -> Model {
+        Model::new(
+            basic_model().id,
+            basic_model().get_name(),
+            vec![
+                Field::new("Question"),
+                Field::new("Answer"),
+                Field::new("Extra"),
+            ],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt("{{Answer}}")],
+        )
+    }
+
+    #[test]
+    fn decks_and_identical_items_are_merged() {
+        let mut first = Deck::new(1, "Capitals", "first");
+        first.add_note(note(vec!["France", "Paris"]));
+        let mut subdeck = Deck::new(3, "Europe", "");
+        subdeck.add_note(note(vec!["Italy", "Rome"]));
+        first.add_subdeck(subdeck).unwrap();
+        let mut second = Deck::new(1, "Capitals", "second");
+        second.add_note(note(vec!["France", "Paris"]));
+        second.add_note(note(vec!["Japan", "Tokyo"]));
+        let mut subdeck = Deck::new(3, "Europe", "");
+        subdeck.add_note(note(vec!["Spain", "Madrid"]));
+        second.add_subdeck(subdeck).unwrap();
+        let third = Deck::new(2, "Other", "");
+        let logo = || MediaFile::from_bytes("logo.png", b"logo".to_vec()).unwrap();
+
+        let merge = Merger::new()
+            .merge(vec![
+                package(first, vec![logo()]),
+                package(second, vec![logo()]),
+                package(third, vec![]),
+            ])
+            .unwrap();
+        assert!(merge.conflicts().is_empty());
+        assert_eq!(merge.media_files().len(), 1);
+        let decks = merge.decks();
+        assert_eq!(decks.len(), 2);
+        assert_eq!(decks[0].description(), "first");
+        let fields = |deck: &Deck| -> Vec<String> {
+            deck.notes()
+                .iter()
+                .map(|note| note.fields()[0].clone())
+                .collect()
+        };
+        assert_eq!(fields(&decks[0]), ["France", "Japan"]);
+        assert_eq!(fields(&decks[0].subdecks()[0]), ["Italy", "Spain"]);
+    }
+
+    #[test]
+    fn note_policies() {
+        let inputs = || {
+            let mut first = Deck::new(1, "Capitals", "");
+            first.add_note(note(vec!["France", "Paris"]).guid("fr").id(7).modified(200));
+            let mut second = Deck::new(1, "Capitals", "");
+            second.add_note(
+                note(vec!["France", "Paris (city)"])
+                    .guid("fr")
+                    .id(7)
+                    .modified(100),
+            );
+            second.add_note(note(vec!["Germany", "Berlin"]).guid("de"));
+            vec![first, second]
+        };
+        let backs = |merge: &Merge| -> Vec<String> {
+            merge.decks()[0]
+                .notes()
+                .iter()
+                .map(|note| note.fields()[1].clone())
+                .collect()
+        };
+
+        let error = Merger::new().merge_decks(inputs()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "note fr of package 1 differs from an earlier note with the same guid"
+        );
+
+        let merge = Merger::new()
+            .notes(MergePolicy::KeepFirst)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(backs(&merge), ["Paris", "Berlin"]);
+        assert_eq!(
+            merge.conflicts(),
+            [MergeConflict::Note {
+                guid: "fr".to_string(),
+                package: 1,
+                renamed_to: None
+            }]
+        );
+
+        let merge = Merger::new()
+            .notes(MergePolicy::KeepNewest)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(backs(&merge), ["Paris", "Berlin"]);
+        let mut decks = inputs();
+        decks.reverse();
+        let merge = Merger::new()
+            .notes(MergePolicy::KeepNewest)
+            .merge_decks(decks)
+            .unwrap();
+        assert_eq!(backs(&merge), ["Paris", "Berlin"]);
+
+        let merge = Merger::new()
+            .notes(MergePolicy::Rename)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(backs(&merge), ["Paris", "Paris (city)", "Berlin"]);
+        let new_guid = guid_for(&["fr", "1"]);
+        assert_eq!(merge.decks()[0].notes()[1].get_guid(), new_guid);
+        assert_eq!(merge.decks()[0].notes()[0].get_id(), Some(7));
+        assert_eq!(merge.decks()[0].notes()[1].get_id(), None);
+        assert!(matches!(
+            &merge.conflicts()[0],
+            MergeConflict::Note { renamed_to: Some(guid), .. } if *guid == new_guid
+        ));
+    }
+
+    #[test]
+    fn model_policies() {
+        let inputs = || {
+            let mut first = Deck::new(1, "Capitals", "");
+            first.add_note(note(vec!["France", "Paris"]));
+            let mut second = Deck::new(1, "Capitals", "");
+            second.add_note(Note::new(other_layout(), vec!["Spain", "Madrid", ""]).unwrap());
+            vec![first, second]
+        };
+        let model_ids = |merge: &Merge| -> Vec<i64> {
+            merge.decks()[0]
+                .notes()
+                .iter()
+                .map(|note| note.model().id)
+                .collect()
+        };
+        let id = basic_model().id;
+
+        let error = Merger::new().merge_decks(inputs()).err().unwrap();
+        assert!(matches!(
+            error,
+            Error::MergeConflict(MergeConflict::Model { package: 1, .. })
+        ));
+
+        let merge = Merger::new()
+            .models(MergePolicy::KeepFirst)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(model_ids(&merge), [id]);
+        assert_eq!(merge.decks()[0].notes()[0].fields()[0], "France");
+
+        let merge = Merger::new()
+            .models(MergePolicy::KeepNewest)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(model_ids(&merge), [id]);
+        assert_eq!(merge.decks()[0].notes()[0].fields()[0], "Spain");
+
+        let merge = Merger::new()
+            .models(MergePolicy::Rename)
+            .merge_decks(inputs())
+            .unwrap();
+        assert_eq!(model_ids(&merge), [id, id + 1]);
+        assert_eq!(
+            merge.decks()[0].notes()[1].model().get_name(),
+            format!("{}+", basic_model().get_name())
+        );
+        assert_eq!(
+            merge.conflicts()[0].to_string(),
+            format!(
+                "model {} of package 1 has other fields than an earlier model with the same id, renamed to {}",
+                id,
+                id + 1
+            )
+        );
+        for format in [
+            PackageFormat::Legacy,
+            PackageFormat::Anki21,
+            PackageFormat::Anki21b,
+        ] {
+            let mut buffer = Cursor::new(vec![]);
+            Merger::new()
+                .models(MergePolicy::Rename)
+                .merge_decks(inputs())
+                .unwrap()
+                .into_package()
+                .format(format)
+                .write_timestamp(&mut buffer, 1_600_000_000.0)
+                .unwrap();
+            buffer.set_position(0);
+            let package = Package::read(buffer).unwrap();
+            let mut ids: Vec<i64> = all_notes(package.decks())
+                .iter()
+                .map(|note| note.model().id)
+                .collect();
+            ids.sort();
+            assert_eq!(ids, [id, id + 1], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn media_policies() {
+        let inputs = || {
+            let mut first = Deck::new(1, "Capitals", "");
+            first.add_note(note(vec!["France", r#"<img src="flag.png">"#]));
+            let mut second = Deck::new(1, "Capitals", "");
+            second.add_note(note(vec!["Italy", "[sound:flag.png]"]));
+            vec![
+                package(
+                    first,
+                    vec![MediaFile::from_bytes("flag.png", b"blue white red".to_vec()).unwrap()],
+                ),
+                package(
+                    second,
+                    vec![MediaFile::from_bytes("flag.png", b"green white red".to_vec()).unwrap()],
+                ),
+            ]
+        };
+
+        assert!(Merger::new().merge(inputs()).is_err());
+
+        let merge = Merger::new()
+            .media(MergePolicy::KeepFirst)
+            .merge(inputs())
+            .unwrap();
+        assert_eq!(merge.media_files().len(), 1);
+        assert_eq!(merge.media_files()[0].data().unwrap(), b"blue white red");
+
+        let merge = Merger::new()
+            .media(MergePolicy::KeepNewest)
+            .merge(inputs())
+            .unwrap();
+        assert_eq!(merge.media_files()[0].data().unwrap(), b"green white red");
+
+        let merge = Merger::new()
+            .media(MergePolicy::Rename)
+            .merge(inputs())
+            .unwrap();
+        let names: Vec<&str> = merge.media_files().iter().map(MediaFile::name).collect();
+        assert_eq!(names, ["flag.png", "flag_2.png"]);
+        let notes = merge.decks()[0].notes();
+        assert_eq!(notes[0].fields()[1], r#"<img src="flag.png">"#);
+        assert_eq!(notes[1].fields()[1], "[sound:flag_2.png]");
+        assert!(merge.into_package().check_media().is_empty());
+    }
+
+    #[test]
+    fn models_referring_to_renamed_media() {
+        let logo_model = || {
+            Model::new(
+                42,
+                "Logo",
+                vec![Field::new("Front"), Field::new("Back")],
+                vec![Template::new("Card 1")
+                    .qfmt(r#"<img src="_logo.png">{{Front}}"#)
+                    .afmt("{{Back}}")],
+            )
+        };
+        let input = |front: &str, logo: &[u8]| {
+            let mut deck = Deck::new(1, "Capitals", "");
+            deck.add_note(Note::new(logo_model(), vec![front, "Capital"]).unwrap());
+            package(
+                deck,
+                vec![MediaFile::from_bytes("_logo.png", logo.to_vec()).unwrap()],
+            )
+        };
+        let merge = Merger::new()
+            .media(MergePolicy::Rename)
+            .merge(vec![input("France", b"blue"), input("Italy", b"green")])
+            .unwrap();
+        let models: Vec<(i64, &str, String)> = merge.decks()[0]
+            .notes()
+            .iter()
+            .map(|note| {
+                let model = note.model();
+                (
+                    model.id,
+                    model.get_name(),
+                    model.templates()[0].qfmt.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            models,
+            [
+                (42, "Logo", r#"<img src="_logo.png">{{Front}}"#.to_string()),
+                (
+                    43,
+                    "Logo+",
+                    r#"<img src="_logo_2.png">{{Front}}"#.to_string()
+                ),
+            ]
+        );
+        assert!(merge.into_package().check_media().is_empty());
+    }
+
+    #[test]
+    fn unused_names() {
+        let used: HashSet<String> = ["a.png", "a_2.png", "b"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(unused_name("a.png", &used), "a_3.png");
+        assert_eq!(unused_name("b", &used), "b_2");
+        assert_eq!(unused_name(".hidden", &used), ".hidden_2");
+        let used: HashSet<String> = ["Basic", "Basic+"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(unused_model_name("Basic", &used), "Basic++");
+        assert_eq!(unused_model_name("Cloze", &used), "Cloze");
+    }
+}
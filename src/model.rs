@@ -1,10 +1,14 @@
 use crate::builders::Template;
 use crate::db_entries::{Fld, ModelDbEntry, Tmpl};
 use crate::error::{json_error, template_error};
+use crate::media::rename_media_references;
+use crate::render::referenced_fields;
 use crate::{Error, Field};
 use fancy_regex::Regex;
 use ramhorns::Template as RamTemplate;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 const DEFAULT_LATEX_PRE: &str = r#"
 \documentclass[12pt]{article}
@@ -21,14 +25,39 @@ const DEFAULT_LATEX_POST: &str = r"\end{document}";
 /// `FrontBack` or `Cloze` to determine the type of a Model.
 ///
 /// When creating a Model, the default is `FrontBack`
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ModelType {
+    #[default]
     FrontBack,
     Cloze,
 }
 
 /// `Model` to determine the structure of a `Note`
-#[derive(Clone)]
+///
+/// Models can be serialized and deserialized with serde, so they can be kept in TOML, YAML or JSON
+/// files. Only `id`, `name`, `fields` and `templates` are required. Deserializing a model fails if
+/// one of its templates refers to a field it does not have.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::Model;
+///
+/// let model: Model = serde_json::from_str(r#"{
+///     "id": 1607392319,
+///     "name": "Simple Model",
+///     "fields": [{ "name": "Question" }, { "name": "Answer", "font": "Arial" }],
+///     "templates": [{
+///         "name": "Card 1",
+///         "qfmt": "{{Question}}",
+///         "afmt": "{{FrontSide}}<hr id=answer>{{Answer}}"
+///     }],
+///     "css": ".card { font-size: 20px; }"
+/// }"#).unwrap();
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "ModelDefinition", into = "ModelDefinition")]
 pub struct Model {
     pub id: i64,
     name: String,
@@ -151,6 +180,70 @@ impl Model {
         }
     }
 
+    /// Returns the names of the fields, in the order notes of this model give their values
+    pub fn field_names(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect()
+    }
+
+    /// Replaces the references to the media file `old` in the templates and CSS by `new`.
+    /// Returns whether there were any.
+    pub(super) fn rename_media(&mut self, old: &str, new: &str) -> bool {
+        let templates = self.templates.clone();
+        let css = self.css.clone();
+        for template in &mut self.templates {
+            for format in [
+                &mut template.qfmt,
+                &mut template.afmt,
+                &mut template.bqfmt,
+                &mut template.bafmt,
+            ] {
+                *format = rename_media_references(format, old, new);
+            }
+        }
+        self.css = rename_media_references(&self.css, old, new);
+        self.css != css
+            || self
+                .templates
+                .iter()
+                .zip(&templates)
+                .any(|(template, old)| {
+                    (
+                        &template.qfmt,
+                        &template.afmt,
+                        &template.bqfmt,
+                        &template.bafmt,
+                    ) != (&old.qfmt, &old.afmt, &old.bqfmt, &old.bafmt)
+                })
+    }
+
+    /// Checks that the templates only refer to fields of the model
+    pub(super) fn check_field_references(&self) -> Result<(), Error> {
+        let field_names = self.field_names();
+        for template in &self.templates {
+            let formats = [
+                &template.qfmt,
+                &template.afmt,
+                &template.bqfmt,
+                &template.bafmt,
+            ];
+            for format in formats {
+                for field in referenced_fields(format)? {
+                    if !field_names.contains(&field) {
+                        return Err(Error::TemplateUnknownField(
+                            template.name.clone(),
+                            field.to_string(),
+                            field_names.iter().map(|name| name.to_string()).collect(),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub(super) fn req(&self) -> Result<Vec<(usize, String, Vec<usize>)>, Error> {
         let sentinel = "SeNtInEl".to_string();
         let field_names: Vec<String> = self.fields.iter().map(|field| field.name.clone()).collect();
@@ -179,13 +272,35 @@ impl Model {
                 .map(|(field_ord, _)| field_ord)
                 .collect::<Vec<_>>();
             if required_fields.is_empty() {
-                return Err(Error::TemplateFormat(template.clone()));
+                return Err(Error::TemplateFormat(Box::new(template.clone())));
             }
             req.push((template_ord, "any".to_string(), required_fields))
         }
         Ok(req)
     }
 
+    pub(super) fn from_model_db_entry(entry: ModelDbEntry) -> Result<Self, Error> {
+        let id = entry
+            .id
+            .parse()
+            .map_err(|_| Error::InvalidPackage(format!("invalid model id {:?}", entry.id)))?;
+        let model_type = match entry.model_db_entry_type {
+            1 => ModelType::Cloze,
+            _ => ModelType::FrontBack,
+        };
+        Ok(Self {
+            id,
+            name: entry.name,
+            fields: entry.flds,
+            templates: entry.tmpls,
+            css: entry.css,
+            model_type,
+            latex_pre: entry.latex_pre,
+            latex_post: entry.latex_post,
+            sort_field_index: entry.sortf,
+        })
+    }
+
     pub(super) fn fields(&self) -> Vec<Fld> {
         self.fields.clone()
     }
@@ -195,18 +310,29 @@ impl Model {
     pub(super) fn get_model_type(&self) -> ModelType {
         self.model_type.clone()
     }
+    pub(super) fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub(super) fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+    pub(super) fn get_css(&self) -> &str {
+        &self.css
+    }
+    pub(super) fn get_sort_field_index(&self) -> usize {
+        self.sort_field_index.max(0) as usize
+    }
     pub(super) fn to_model_db_entry(
-        &mut self,
+        &self,
         timestamp: f64,
         deck_id: i64,
     ) -> Result<ModelDbEntry, Error> {
-        self.templates
-            .iter_mut()
-            .enumerate()
-            .for_each(|(i, template)| {
-                template.ord = i as i64;
-            });
-        self.fields.iter_mut().enumerate().for_each(|(i, field)| {
+        let mut templates = self.templates.clone();
+        templates.iter_mut().enumerate().for_each(|(i, template)| {
+            template.ord = i as i64;
+        });
+        let mut fields = self.fields.clone();
+        fields.iter_mut().enumerate().for_each(|(i, field)| {
             field.ord = i as i64;
         });
         let model_type = match self.model_type {
@@ -217,12 +343,12 @@ impl Model {
             vers: vec![],
             name: self.name.clone(),
             tags: vec![],
-            did: deck_id,
+            did: Some(deck_id),
             usn: -1,
-            req: self.req()?.clone(),
-            flds: self.fields.clone(),
-            sortf: self.sort_field_index.clone(),
-            tmpls: self.templates.clone(),
+            req: self.req()?,
+            flds: fields,
+            sortf: self.sort_field_index,
+            tmpls: templates,
             model_db_entry_mod: timestamp as i64,
             latex_post: self.latex_post.clone(),
             model_db_entry_type: model_type,
@@ -233,11 +359,8 @@ impl Model {
     }
 
     #[allow(dead_code)]
-    pub(super) fn to_json(&mut self, timestamp: f64, deck_id: i64) -> Result<String, Error> {
-        Ok(
-            serde_json::to_string(&self.to_model_db_entry(timestamp, deck_id)?)
-                .map_err(json_error)?,
-        )
+    pub(super) fn to_json(&self, timestamp: f64, deck_id: i64) -> Result<String, Error> {
+        serde_json::to_string(&self.to_model_db_entry(timestamp, deck_id)?).map_err(json_error)
     }
 }
 
@@ -252,10 +375,92 @@ fn contains_other_fields(rendered: &str, current_field: &str, sentinel: &str) ->
     .unwrap()
 }
 
+/// Serialized form of a `Model`, leaving out options which have their default value
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelDefinition {
+    id: i64,
+    name: String,
+    fields: Vec<Field>,
+    templates: Vec<Template>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    css: String,
+    #[serde(default, rename = "type")]
+    model_type: ModelType,
+    #[serde(
+        default = "default_latex_pre",
+        skip_serializing_if = "is_default_latex_pre"
+    )]
+    latex_pre: String,
+    #[serde(
+        default = "default_latex_post",
+        skip_serializing_if = "is_default_latex_post"
+    )]
+    latex_post: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    sort_field_index: i64,
+}
+
+fn is_zero(value: &i64) -> bool {
+    *value == 0
+}
+
+fn default_latex_pre() -> String {
+    DEFAULT_LATEX_PRE.to_string()
+}
+
+fn is_default_latex_pre(latex_pre: &str) -> bool {
+    latex_pre == DEFAULT_LATEX_PRE
+}
+
+fn default_latex_post() -> String {
+    DEFAULT_LATEX_POST.to_string()
+}
+
+fn is_default_latex_post(latex_post: &str) -> bool {
+    latex_post == DEFAULT_LATEX_POST
+}
+
+impl From<Model> for ModelDefinition {
+    fn from(model: Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            fields: model.fields.into_iter().map(Field::from).collect(),
+            templates: model.templates.into_iter().map(Template::from).collect(),
+            css: model.css,
+            model_type: model.model_type,
+            latex_pre: model.latex_pre,
+            latex_post: model.latex_post,
+            sort_field_index: model.sort_field_index,
+        }
+    }
+}
+
+impl TryFrom<ModelDefinition> for Model {
+    type Error = Error;
+
+    fn try_from(definition: ModelDefinition) -> Result<Self, Error> {
+        let model = Model::new_with_options(
+            definition.id,
+            &definition.name,
+            definition.fields,
+            definition.templates,
+            Some(&definition.css),
+            Some(definition.model_type),
+            Some(&definition.latex_pre),
+            Some(&definition.latex_post),
+            Some(definition.sort_field_index),
+        );
+        model.check_field_references()?;
+        Ok(model)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Deck, Note};
+    use crate::{Card, Deck, Note};
     use std::collections::HashSet;
     use tempfile::NamedTempFile;
 
@@ -419,6 +624,46 @@ mod tests {
         assert_eq!(sorted, vec![1, 2]);
     }
 
+    fn cloze_ords(model: &Model, text: &str) -> Vec<i64> {
+        let note = Note::new(model.clone(), vec![text, ""]).unwrap();
+        let mut ords = note.cards().iter().map(Card::ord).collect::<Vec<_>>();
+        ords.sort_unstable();
+        ords
+    }
+
+    #[test]
+    fn cloze_nested_and_hints() {
+        let model = cloze_model();
+        assert_eq!(cloze_ords(&model, "{{c1::a {{c2::b}} c}}"), [0, 1]);
+        assert_eq!(cloze_ords(&model, "{{c1::a::x}}}} {{c2::b}}"), [0, 1]);
+        assert_eq!(cloze_ords(&model, "{{c0::a}} {{c3::b"), [0]);
+    }
+
+    #[test]
+    fn cloze_fields_of_all_templates() {
+        let model = Model::new_with_options(
+            1,
+            "Typed Cloze",
+            vec![Field::new("Text"), Field::new("Extra")],
+            vec![
+                Template::new("Cloze")
+                    .qfmt("{{#Text}}{{type:cloze:Text}}{{/Text}}")
+                    .afmt("{{type:cloze:Text}}"),
+                Template::new("Extra")
+                    .qfmt("{{cloze:Extra}} {{cloze-only:Text}}")
+                    .afmt("{{cloze:Extra}}"),
+            ],
+            None,
+            Some(ModelType::Cloze),
+            None,
+            None,
+            None,
+        );
+        let note = Note::new(model, vec!["{{c1::a}}", "{{c2::b}}"]).unwrap();
+        let ords = note.cards().iter().map(Card::ord).collect::<Vec<_>>();
+        assert_eq!(ords, [0, 1]);
+    }
+
     #[test]
     fn cloze_newlines_in_deletion() {
         let fields = vec![
@@ -448,4 +693,47 @@ mod tests {
             .sort_field_index(1)
             .model_type(ModelType::FrontBack);
     }
+
+    #[test]
+    fn serde_round_trip() {
+        let model = cloze_model()
+            .with_field(Field::new("Extra").font("Arial").rtl(true))
+            .sort_field_index(1);
+        let value = serde_json::to_value(&model).unwrap();
+        assert_eq!(value["type"], "cloze");
+        assert_eq!(
+            value["fields"][2],
+            serde_json::json!({"name": "Extra", "rtl": true, "font": "Arial"})
+        );
+        assert!(value.get("latex_pre").is_none());
+        assert_eq!(value["sort_field_index"], 1);
+        let deserialized: Model = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&deserialized).unwrap(), value);
+
+        let value = serde_json::to_value(cloze_model()).unwrap();
+        assert!(value.get("sort_field_index").is_none());
+    }
+
+    #[test]
+    fn deserializing_checks_field_references() {
+        let model = serde_json::json!({
+            "id": 1,
+            "name": "Typo",
+            "fields": [{"name": "Front"}, {"name": "Back"}],
+            "templates": [{
+                "name": "Card 1",
+                "qfmt": "{{Front}}{{#Tags}}{{Tags}}{{/Tags}}",
+                "afmt": "{{FrontSide}}<hr id=answer>{{text:Bakc}}"
+            }]
+        });
+        let error = serde_json::from_value::<Model>(model).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            r#"The template "Card 1" refers to the field "Bakc", but the model only has the fields ["Front", "Back"]"#
+        );
+
+        let model = serde_json::json!({"id": 1, "name": "Unknown", "fields": [], "templates": [], "colour": 3});
+        let error = serde_json::from_value::<Model>(model).err().unwrap();
+        assert!(error.to_string().contains("unknown field `colour`"));
+    }
 }
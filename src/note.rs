@@ -1,22 +1,49 @@
-use crate::card::Card;
+use crate::builders::NoteBuilder;
+use crate::card::{Card, CardState, Flag};
+use crate::cloze::cloze_numbers;
 use crate::error::database_error;
+use crate::media::rename_media_references;
 use crate::model::{Model, ModelType};
+use crate::render::{
+    cloze_fields, render_note, strip_html_preserving_media_filenames, RenderedCard,
+};
+use crate::revlog::ReviewLogEntry;
 use crate::util::guid_for;
 use crate::Error;
 use fancy_regex::Regex;
 use rusqlite::{params, Transaction};
-use std::collections::HashSet;
-use std::ops::RangeFrom;
-use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::ops::{Range, RangeFrom};
 
 /// Note (Flashcard) to be added to a `Deck`
-#[derive(Clone)]
+///
+/// Notes can be serialized and deserialized with serde, together with their model. The fields
+/// are given as a list in the order of the model fields or as a map from field names to values,
+/// in which missing fields are empty. Cards only have to be listed if they are not new, e.g.
+/// `{ "ord": 0, "suspended": true }`.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, Note};
+///
+/// let note = Note::new(basic_model(), vec!["Capital of France", "Paris"]).unwrap();
+/// let json = serde_json::to_string(&note).unwrap();
+/// let note: Note = serde_json::from_str(&json).unwrap();
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "NoteDefinition<Model>", into = "NoteDefinition<Model>")]
 pub struct Note {
     model: Model,
     fields: Vec<String>,
-    sort_field: bool,
+    sort_field: Option<String>,
     tags: Vec<String>,
     guid: String,
+    id: Option<i64>,
+    modified: Option<i64>,
     cards: Vec<Card>,
 }
 
@@ -32,7 +59,7 @@ impl Note {
     /// let note = Note::new(basic_model(), vec!["What is the capital of France?", "Paris"]);
     /// ```
     pub fn new(model: Model, fields: Vec<&str>) -> Result<Self, Error> {
-        let fields = fields.iter().map(|&s| s.to_string()).collect();
+        let fields: Vec<String> = fields.iter().map(|&s| s.to_string()).collect();
         let cards = match model.get_model_type() {
             ModelType::FrontBack => front_back_cards(&model, &fields)?,
             ModelType::Cloze => cloze_cards(&model, &fields),
@@ -41,15 +68,23 @@ impl Note {
         Ok(Self {
             model,
             fields,
-            sort_field: false,
+            sort_field: None,
             tags: vec![],
             guid,
+            id: None,
+            modified: None,
             cards,
         })
     }
 
+    /// Returns a builder for a note of `model` which sets the fields by name, see [`NoteBuilder`]
+    pub fn builder(model: Model) -> NoteBuilder {
+        NoteBuilder::new(model)
+    }
+
     /// Creates a new Note with a new `model`, `fields` and custom parameters:
-    /// * `sort_field` - whether to sort field, default is `false`
+    /// * `sort_field` - Value the note is sorted by in the browser, default is the field at the
+    ///   `sort_field_index` of the model
     /// * `tags` - List of tags
     /// * `guid` - Custom unique note id, default is hash of all fields
     ///
@@ -57,17 +92,17 @@ impl Note {
     pub fn new_with_options(
         model: Model,
         fields: Vec<&str>,
-        sort_field: Option<bool>,
+        sort_field: Option<&str>,
         tags: Option<Vec<&str>>,
         guid: Option<&str>,
     ) -> Result<Self, Error> {
-        let tags = tags
+        let tags: Vec<String> = tags
             .unwrap_or_default()
             .iter()
             .map(|s| s.to_string())
             .collect();
         validate_tags(&tags)?;
-        let fields = fields.iter().map(|s| s.to_string()).collect();
+        let fields: Vec<String> = fields.iter().map(|s| s.to_string()).collect();
         let cards = match model.get_model_type() {
             ModelType::FrontBack => front_back_cards(&model, &fields)?,
             ModelType::Cloze => cloze_cards(&model, &fields),
@@ -76,16 +111,24 @@ impl Note {
         Ok(Self {
             model,
             fields,
-            sort_field: sort_field.unwrap_or(false),
+            sort_field: sort_field.map(str::to_string),
             tags,
             guid,
+            id: None,
+            modified: None,
             cards,
         })
     }
 
-    /// Returns a new Note with the sort field replace with the new one
-    pub fn sort_field(self, sort_field: bool) -> Self {
-        Self { sort_field, ..self }
+    /// Sets the value the note is sorted by in the Anki browser
+    ///
+    /// By default this is the field at the `sort_field_index` of the model with HTML removed.
+    /// The value is used as given, so it should not contain HTML.
+    pub fn sort_field(self, sort_field: impl ToString) -> Self {
+        Self {
+            sort_field: Some(sort_field.to_string()),
+            ..self
+        }
     }
 
     /// Sets or replaces tags with the provided ones
@@ -104,7 +147,8 @@ impl Note {
 
     /// Sets the GUID for this note
     ///
-    /// The GUID is auto-generated if this option is not provided.
+    /// The GUID is auto-generated from all fields with [`guid_for`](crate::guid_for) if this
+    /// option is not provided, which gives the same GUID as Python genanki.
     pub fn guid(self, guid: impl ToString) -> Self {
         Self {
             guid: guid.to_string(),
@@ -112,17 +156,247 @@ impl Note {
         }
     }
 
-    pub(super) fn model(&self) -> Model {
-        self.model.clone()
+    /// Sets the id the note is written with. Notes read from a package keep their id.
+    ///
+    /// By default a new id is generated when the package is written. Anki matches notes by
+    /// their GUID on import, so the id only has to be kept to reproduce a collection exactly.
+    pub fn id(self, id: i64) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    /// Sets the time the note was last modified, as a unix timestamp in seconds. Notes read from a
+    /// package keep their modification time.
+    ///
+    /// By default the time the package is written is used.
+    pub fn modified(self, timestamp: i64) -> Self {
+        Self {
+            modified: Some(timestamp),
+            ..self
+        }
+    }
+
+    /// Sets the scheduling state of the card generated from the template with ordinal `ord`
+    /// (for cloze notes, `c1` has the ordinal 0).
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_and_reversed_card_model, CardState, Error, Note};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let note = Note::new(basic_and_reversed_card_model(), vec!["Capital of France", "Paris"])?
+    ///     .with_card_state(0, CardState::review(1609459200, 21, 2.5).reps(6))?
+    ///     .with_card_state(1, CardState::learning(1609459200, 1))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Returns `Err` if the note has no such card or if the state is inconsistent, e.g. a
+    /// review card without an interval
+    pub fn with_card_state(mut self, ord: i64, state: CardState) -> Result<Self, Error> {
+        state.validate()?;
+        self.card_mut(ord)?.state = state;
+        Ok(self)
+    }
+
+    /// Adds an entry to the review history of the card generated from the template with ordinal
+    /// `ord`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_model, CardState, Ease, Error, Note, ReviewKind, ReviewLogEntry};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let note = Note::new(basic_model(), vec!["Capital of France", "Paris"])?
+    ///     .with_review(0, ReviewLogEntry::new(1609459200.0, Ease::Good, ReviewKind::Learning))?
+    ///     .with_review(0, ReviewLogEntry::new(1609545600.0, Ease::Good, ReviewKind::Review).interval(3))?
+    ///     .with_card_state(0, CardState::review(1609804800, 3, 2.5).reps(2))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Returns `Err` if the note has no such card or if the entry is invalid
+    pub fn with_review(mut self, ord: i64, entry: ReviewLogEntry) -> Result<Self, Error> {
+        entry.validate()?;
+        self.card_mut(ord)?.reviews.push(entry);
+        Ok(self)
+    }
+
+    /// Suspends the card generated from the template with ordinal `ord`, so it is not shown
+    /// until it is unsuspended in Anki.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_and_reversed_card_model, Error, Flag, Note};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let note = Note::new(basic_and_reversed_card_model(), vec!["Capital of France", "Paris"])?
+    ///     .suspend_card(1)?
+    ///     .flag_card(0, Flag::Green)?;
+    /// assert!(note.cards()[1].is_suspended());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Returns `Err` if the note has no such card
+    pub fn suspend_card(mut self, ord: i64) -> Result<Self, Error> {
+        self.card_mut(ord)?.suspend = true;
+        Ok(self)
+    }
+
+    /// Buries the card generated from the template with ordinal `ord` until the next day
+    ///
+    /// Returns `Err` if the note has no such card
+    pub fn bury_card(mut self, ord: i64) -> Result<Self, Error> {
+        self.card_mut(ord)?.bury = true;
+        Ok(self)
+    }
+
+    /// Sets a colored flag on the card generated from the template with ordinal `ord`
+    ///
+    /// Returns `Err` if the note has no such card
+    pub fn flag_card(mut self, ord: i64, flag: Flag) -> Result<Self, Error> {
+        self.card_mut(ord)?.flag = Some(flag);
+        Ok(self)
+    }
+
+    /// Puts the card generated from the template with ordinal `ord` into the deck with `deck_id`
+    /// instead of the deck the note is added to. The deck has to be part of the same `Package`.
+    ///
+    /// Returns `Err` if the note has no such card
+    pub fn card_deck(mut self, ord: i64, deck_id: i64) -> Result<Self, Error> {
+        self.card_mut(ord)?.deck_id = Some(deck_id);
+        Ok(self)
+    }
+
+    fn card_mut(&mut self, ord: i64) -> Result<&mut Card, Error> {
+        self.cards
+            .iter_mut()
+            .find(|card| card.ord == ord)
+            .ok_or(Error::CardNotFound(ord))
+    }
+
+    /// Rebuilds a note that was read back from an existing collection, keeping its cards as they
+    /// were stored instead of generating them again.
+    pub(super) fn from_db(
+        model: Model,
+        fields: Vec<String>,
+        tags: Vec<String>,
+        id: i64,
+        guid: String,
+        modified: i64,
+        cards: Vec<Card>,
+    ) -> Self {
+        Self {
+            model,
+            fields,
+            sort_field: None,
+            tags,
+            guid,
+            id: Some(id),
+            modified: Some(modified),
+            cards,
+        }
+    }
+
+    pub(super) fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub(super) fn set_guid(&mut self, guid: String) {
+        self.guid = guid;
+    }
+
+    pub(super) fn set_id(&mut self, id: Option<i64>) {
+        self.id = id;
+    }
+
+    pub(super) fn model_mut(&mut self) -> &mut Model {
+        &mut self.model
     }
 
-    #[allow(dead_code)]
-    pub(super) fn cards(&self) -> Vec<Card> {
-        self.cards.clone()
+    /// Replaces the references to the media file `old` in the fields and the model by `new`.
+    /// Returns whether the templates or CSS of the model referred to it.
+    pub(super) fn rename_media(&mut self, old: &str, new: &str) -> bool {
+        for field in &mut self.fields {
+            *field = rename_media_references(field, old, new);
+        }
+        self.model.rename_media(old, new)
     }
 
-    fn get_guid(&self) -> String {
-        self.guid.clone()
+    /// Returns the cards generated for this note
+    pub fn cards(&self) -> &[Card] {
+        &self.cards
+    }
+
+    /// Renders the question and answer side of every card of this note into HTML, as Anki would
+    /// show them when the note is in the deck called `deck_name`
+    ///
+    /// Returns `Err` if a template refers to a field the model does not have or if a conditional
+    /// is not closed
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{Error, Note, basic_model};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let note = Note::new(basic_model(), vec!["What is the capital of France?", "Paris"])?;
+    /// let cards = note.render("Geography")?;
+    /// assert_eq!(cards[0].question(), "What is the capital of France?");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn render(&self, deck_name: &str) -> Result<Vec<RenderedCard>, Error> {
+        render_note(self, deck_name)
+    }
+
+    /// Returns the field values, in the order of the fields of the model
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Returns the value of the field called `name`, or `None` if the model has no such field
+    ///
+    /// Example:
+    /// ```
+    /// use genanki_rs::{basic_model, Error, Note};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let note = Note::new(basic_model(), vec!["Capital of France", "Paris"])?;
+    /// assert_eq!(note.field("Back"), Some("Paris"));
+    /// assert_eq!(note.field("Answer"), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn field(&self, name: &str) -> Option<&str> {
+        let index = self.model.field_names().iter().position(|&n| n == name)?;
+        self.fields.get(index).map(String::as_str)
+    }
+
+    /// Returns the tags of the note, as set with [`Note::tags`] and [`Note::with_tag`]
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Returns the GUID of the note, as set with [`Note::guid`] or computed from the fields
+    pub fn get_guid(&self) -> &str {
+        &self.guid
+    }
+
+    /// Returns the id of the note, if it was set with [`Note::id`] or read from a package
+    pub fn get_id(&self) -> Option<i64> {
+        self.id
+    }
+
+    /// Returns the modification time of the note, if it was set with [`Note::modified`] or read
+    /// from a package
+    pub fn get_modified(&self) -> Option<i64> {
+        self.modified
     }
 
     fn check_number_model_fields_matches_num_fields(&self) -> Result<(), Error> {
@@ -136,21 +410,45 @@ impl Note {
         }
     }
 
-    fn check_invalid_html_tags_in_fields(&self) -> Result<(), Error> {
-        for field in &self.fields {
-            let invalid_tags = find_invalid_html_tags_in_field(field);
-            if !invalid_tags.is_empty() {
-                println!(
-                    "Warning: The field {} contains the invalid html tags {:?}",
-                    field, invalid_tags
-                );
-            }
+    fn format_fields(&self) -> String {
+        self.fields.clone().join("\x1f")
+    }
+
+    /// Returns the name of the field the note is sorted by, or `None` if a custom sort field was
+    /// set
+    pub(super) fn sort_field_name(&self) -> Option<&str> {
+        match self.sort_field {
+            Some(_) => None,
+            None => self
+                .model
+                .field_names()
+                .get(self.model.get_sort_field_index())
+                .copied(),
         }
-        Ok(())
     }
 
-    fn format_fields(&self) -> String {
-        self.fields.clone().join("\x1f")
+    /// Returns the value of the `sfld` column, by which Anki sorts notes in the browser
+    pub(super) fn get_sort_field(&self) -> String {
+        match &self.sort_field {
+            Some(sort_field) => sort_field.clone(),
+            None => self
+                .fields
+                .get(self.model.get_sort_field_index())
+                .map(|field| strip_html_preserving_media_filenames(field))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the value of the `csum` column, which Anki uses to find duplicates: the first 4
+    /// bytes of the SHA1 hash of the first field without HTML
+    fn get_checksum(&self) -> i64 {
+        let first_field = self
+            .fields
+            .first()
+            .map(|field| strip_html_preserving_media_filenames(field))
+            .unwrap_or_default();
+        let hash = Sha1::digest(first_field.as_bytes());
+        u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as i64
     }
 
     fn format_tags(&self) -> String {
@@ -161,63 +459,211 @@ impl Note {
         transaction: &Transaction,
         timestamp: f64,
         deck_id: i64,
-        mut id_gen: &mut RangeFrom<usize>,
+        id_gen: &mut RangeFrom<usize>,
     ) -> Result<(), Error> {
         self.check_number_model_fields_matches_num_fields()?;
-        self.check_invalid_html_tags_in_fields()?;
+        let note_id = match self.id {
+            Some(id) => id,
+            None => id_gen.next().expect("Should always have a next id") as i64,
+        };
         transaction
             .execute(
                 "INSERT INTO notes VALUES(?,?,?,?,?,?,?,?,?,?,?);",
                 params![
-                    id_gen.next(),        // id
-                    self.get_guid(),      // guid
-                    self.model.id,        // mid
-                    timestamp as i64,     // mod
-                    -1,                   // usn
-                    self.format_tags(),   // TODO tags
-                    self.format_fields(), // flds
-                    self.sort_field,      // sfld
-                    0,                    // csum, can be ignored
-                    0,                    // flags
-                    "",                   // data
+                    note_id,                                   // id
+                    self.get_guid(),                           // guid
+                    self.model.id,                             // mid
+                    self.modified.unwrap_or(timestamp as i64), // mod
+                    -1,                                        // usn
+                    self.format_tags(),                        // TODO tags
+                    self.format_fields(),                      // flds
+                    self.get_sort_field(),                     // sfld
+                    self.get_checksum(),                       // csum
+                    0,                                         // flags
+                    "",                                        // data
                 ],
             )
             .map_err(database_error)?;
-        let note_id = transaction.last_insert_rowid() as usize;
+        let note_id = note_id as usize;
         for card in &self.cards {
-            card.write_to_db(transaction, timestamp, deck_id, note_id, &mut id_gen)?
+            card.write_to_db(transaction, timestamp, deck_id, note_id, id_gen)?
         }
         Ok(())
     }
 }
 
-fn cloze_cards(model: &Model, self_fields: &Vec<String>) -> Vec<Card> {
-    let mut card_ords: HashSet<i64> = HashSet::new();
-    let mut cloze_replacements: HashSet<String> = HashSet::new();
-    cloze_replacements.extend(re_findall(
-        r"{{[^}]*?cloze:(?:[^}]?:)*(.+?)}}",
-        &model.templates()[0].qfmt,
-    ));
-    cloze_replacements.extend(re_findall("<%cloze:(.+?)%>", &model.templates()[0].qfmt));
-    for field_name in cloze_replacements {
-        let fields = model.fields();
-        let mut field_index_iter = fields
+/// Serialized form of a `Note`. A single note contains its whole model, while the notes of a
+/// deck only refer to a model of the deck by its id.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NoteDefinition<M> {
+    model: M,
+    fields: FieldValues,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort_field: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cards: Vec<CardDefinition>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum FieldValues {
+    List(Vec<String>),
+    Map(BTreeMap<String, String>),
+}
+
+/// Serialized form of a card which is not a plain new card
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CardDefinition {
+    ord: i64,
+    #[serde(default, skip_serializing_if = "is_false")]
+    suspended: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    buried: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flag: Option<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deck_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<CardState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    reviews: Vec<ReviewLogEntry>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl<M> NoteDefinition<M> {
+    pub(crate) fn new(note: &Note, model: M) -> Self {
+        let cards = note
+            .cards
             .iter()
-            .enumerate()
-            .filter(|(_, field)| field.name == field_name)
-            .map(|(i, _)| i);
-        let field_value = if let Some(field_index) = field_index_iter.next() {
-            self_fields[field_index].clone()
-        } else {
-            "".to_string()
+            .filter(|card| {
+                card.suspend
+                    || card.bury
+                    || card.flag.is_some()
+                    || card.deck_id.is_some()
+                    || card.state != CardState::new()
+                    || !card.reviews.is_empty()
+            })
+            .map(|card| CardDefinition {
+                ord: card.ord,
+                suspended: card.suspend,
+                buried: card.bury,
+                flag: card.flag,
+                deck_id: card.deck_id,
+                state: Some(card.state.clone()).filter(|state| *state != CardState::new()),
+                reviews: card.reviews.clone(),
+            })
+            .collect();
+        Self {
+            model,
+            fields: FieldValues::List(note.fields.clone()),
+            tags: note.tags.clone(),
+            guid: Some(note.guid.clone()),
+            sort_field: note.sort_field.clone(),
+            id: note.id,
+            modified: note.modified,
+            cards,
+        }
+    }
+
+    pub(crate) fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Creates the note with `model`, which is the model the definition refers to
+    pub(crate) fn into_note(self, model: Model) -> Result<Note, Error> {
+        let field_names = model.field_names();
+        let fields: Vec<String> = match self.fields {
+            FieldValues::List(values) => {
+                if values.len() != field_names.len() {
+                    return Err(Error::ModelFieldCountMismatch(
+                        field_names.len(),
+                        values.len(),
+                    ));
+                }
+                values
+            }
+            FieldValues::Map(mut values) => {
+                let fields = field_names
+                    .iter()
+                    .map(|name| values.remove(*name).unwrap_or_default())
+                    .collect();
+                if let Some(name) = values.into_keys().next() {
+                    return Err(Error::UnknownField(name));
+                }
+                fields
+            }
         };
-        let updates_str = re_findall(r"(?s){{c(\d+)::.+?}}", &field_value);
-        let updates = updates_str
-            .iter()
-            .map(|m| i64::from_str(m).expect("parsed from regex") - 1)
-            .filter(|&m| m >= 0);
-        card_ords.extend(updates);
+        validate_tags(&self.tags)?;
+        let mut note =
+            Note::new(model, fields.iter().map(String::as_str).collect())?.tags(self.tags);
+        if let Some(guid) = self.guid {
+            note = note.guid(guid);
+        }
+        if let Some(sort_field) = self.sort_field {
+            note = note.sort_field(sort_field);
+        }
+        if let Some(id) = self.id {
+            note = note.id(id);
+        }
+        if let Some(modified) = self.modified {
+            note = note.modified(modified);
+        }
+        for card in self.cards {
+            let ord = card.ord;
+            note.card_mut(ord)?;
+            if let Some(state) = card.state {
+                note = note.with_card_state(ord, state)?;
+            }
+            for review in card.reviews {
+                note = note.with_review(ord, review)?;
+            }
+            if card.suspended {
+                note = note.suspend_card(ord)?;
+            }
+            if card.buried {
+                note = note.bury_card(ord)?;
+            }
+            if let Some(flag) = card.flag {
+                note = note.flag_card(ord, flag)?;
+            }
+            if let Some(deck_id) = card.deck_id {
+                note = note.card_deck(ord, deck_id)?;
+            }
+        }
+        Ok(note)
+    }
+}
+
+impl From<Note> for NoteDefinition<Model> {
+    fn from(note: Note) -> Self {
+        Self::new(&note, note.model.clone())
+    }
+}
+
+impl TryFrom<NoteDefinition<Model>> for Note {
+    type Error = Error;
+
+    fn try_from(definition: NoteDefinition<Model>) -> Result<Self, Error> {
+        let model = definition.model.clone();
+        definition.into_note(model)
     }
+}
+
+fn cloze_cards(model: &Model, self_fields: &[String]) -> Vec<Card> {
+    let mut card_ords = cloze_ords(model, self_fields);
     if card_ords.is_empty() {
         card_ords.insert(0);
     }
@@ -227,13 +673,38 @@ fn cloze_cards(model: &Model, self_fields: &Vec<String>) -> Vec<Card> {
         .collect()
 }
 
-fn front_back_cards(model: &Model, self_fields: &Vec<String>) -> Result<Vec<Card>, Error> {
+/// Returns the names of the fields the templates of a cloze `model` take cloze deletions from
+pub(super) fn cloze_field_names(model: &Model) -> Vec<String> {
+    let mut cloze_replacements: BTreeSet<String> = BTreeSet::new();
+    for template in model.templates() {
+        if let Ok(fields) = cloze_fields(&template.qfmt) {
+            cloze_replacements.extend(fields.into_iter().map(str::to_string));
+        }
+        cloze_replacements.extend(re_findall("<%cloze:(.+?)%>", &template.qfmt));
+    }
+    cloze_replacements.into_iter().collect()
+}
+
+/// Returns the ordinals of the cards generated by the cloze deletions in `self_fields`. Like in
+/// Anki, `c0` generates the first card just like `c1`.
+pub(super) fn cloze_ords(model: &Model, self_fields: &[String]) -> BTreeSet<i64> {
+    let field_names = model.field_names();
+    cloze_field_names(model)
+        .iter()
+        .filter_map(|name| field_names.iter().position(|field| field == name))
+        .filter_map(|index| self_fields.get(index))
+        .flat_map(|value| cloze_numbers(value))
+        .map(|number| i64::from(number.max(1)) - 1)
+        .collect()
+}
+
+fn front_back_cards(model: &Model, self_fields: &[String]) -> Result<Vec<Card>, Error> {
     let mut rv = vec![];
     for (card_ord, any_or_all, required_field_ords) in model.req()?.drain(..) {
         let mut iter = required_field_ords.iter().map(|&ord| &self_fields[ord]);
         let condition = match any_or_all.as_str() {
-            "any" => iter.any(|field| field.len() > 0),
-            "all" => iter.all(|field| field.len() > 0),
+            "any" => iter.any(|field| !field.is_empty()),
+            "all" => iter.all(|field| !field.is_empty()),
             _ => panic!("only any or all"),
         };
         if condition {
@@ -248,18 +719,17 @@ fn re_findall(regex_str: &'static str, to_match: &str) -> Vec<String> {
     regex
         .captures_iter(to_match)
         .filter_map(|m| m.ok())
-        .map(|cap| {
+        .flat_map(|cap| {
             cap.iter()
                 .skip(1)
-                .filter_map(|m| m)
+                .flatten()
                 .map(|m| m.as_str().to_string())
                 .collect::<Vec<String>>()
         })
-        .flatten()
         .collect()
 }
 
-fn validate_tags(tags: &Vec<String>) -> Result<(), Error> {
+fn validate_tags(tags: &[String]) -> Result<(), Error> {
     if tags.iter().any(|tag| tag.contains(' ')) {
         Err(Error::TagContainsWhitespace)
     } else {
@@ -267,11 +737,13 @@ fn validate_tags(tags: &Vec<String>) -> Result<(), Error> {
     }
 }
 
-fn find_invalid_html_tags_in_field(field: &str) -> Vec<String> {
+/// Returns the byte ranges of the tags in `field` which are not valid HTML tags
+pub(super) fn invalid_html_tags(field: &str) -> Vec<Range<usize>> {
     let regex = Regex::new(r"<(?!/?[a-z0-9]+(?: .*|/?)>)(?:.|\n)*?>").unwrap();
     regex
         .find_iter(field)
-        .map(|m| m.unwrap().as_str().to_string())
+        .filter_map(|m| m.ok())
+        .map(|m| m.range())
         .collect()
 }
 
@@ -285,8 +757,15 @@ mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
     use tempfile::{NamedTempFile, TempPath};
 
+    fn find_invalid_html_tags_in_field(field: &str) -> Vec<String> {
+        invalid_html_tags(field)
+            .into_iter()
+            .map(|range| field[range].to_string())
+            .collect()
+    }
+
     fn write_to_db_setup(db_file: &TempPath) -> (Connection, f64, i64, RangeFrom<usize>) {
-        let conn = Connection::open(&db_file).unwrap();
+        let conn = Connection::open(db_file).unwrap();
         conn.execute_batch(APKG_SCHEMA).unwrap();
         conn.execute_batch(APKG_COL).unwrap();
         let timestamp = SystemTime::now()
@@ -316,6 +795,44 @@ mod tests {
         transaction.commit().unwrap();
     }
 
+    #[test]
+    fn sort_field_and_checksum() {
+        let model = Model::new(
+            1,
+            "Sorted by answer",
+            vec![Field::new("Question"), Field::new("Answer")],
+            vec![Template::new("Card 1")
+                .qfmt("{{Question}}")
+                .afmt("{{Answer}}")],
+        )
+        .sort_field_index(1);
+        let sorted_by = |note: &Note| {
+            let db_file = NamedTempFile::new().unwrap().into_temp_path();
+            let (mut conn, timestamp, deck_id, mut id_gen) = write_to_db_setup(&db_file);
+            let transaction = conn.transaction().unwrap();
+            note.write_to_db(&transaction, timestamp, deck_id, &mut id_gen)
+                .unwrap();
+            transaction
+                .query_row("SELECT sfld, csum FROM notes", [], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                })
+                .unwrap()
+        };
+
+        let note = Note::new(
+            model,
+            vec![
+                "<b>Capital</b> of&nbsp;France<!-- comment -->",
+                r#"Paris<br><img class="map" src="x.jpg">"#,
+            ],
+        )
+        .unwrap();
+        // Checksums are the first 8 hex digits of the SHA1 of "Capital of France"
+        assert_eq!(sorted_by(&note), ("Paris x.jpg ".to_string(), 56535495));
+        let note = note.sort_field("Paris, France");
+        assert_eq!(sorted_by(&note), ("Paris, France".to_string(), 56535495));
+    }
+
     #[test]
     fn tags_new() {
         let _ = Note::new_with_options(
@@ -509,6 +1026,18 @@ mod tests {
         assert_eq!(find_invalid_html_tags_in_field("<h1@>"), vec!["<h1@>"]);
     }
 
+    #[test]
+    fn card_not_found() {
+        let note = Note::new(crate::basic_model(), vec!["Question", "Answer"]).unwrap();
+        assert!(matches!(
+            note.clone().suspend_card(1),
+            Err(Error::CardNotFound(1))
+        ));
+        let note = note.bury_card(0).unwrap().flag_card(0, Flag::Red).unwrap();
+        assert!(note.cards()[0].is_buried());
+        assert_eq!(note.cards()[0].flag(), Some(Flag::Red));
+    }
+
     #[test]
     fn option_builder() -> anyhow::Result<()> {
         // Make sure we can call the different builder-style methods on Note.
@@ -523,8 +1052,86 @@ mod tests {
             .guid("1234")
             .tags(["tag_a"])
             .with_tag("tag_b")
-            .sort_field(true);
+            .sort_field("a");
 
         Ok(())
     }
+
+    #[test]
+    fn serde_round_trip() {
+        let note = Note::new(
+            crate::basic_and_reversed_card_model(),
+            vec!["Capital of France", "Paris"],
+        )
+        .unwrap()
+        .tags(["geography"])
+        .sort_field("France")
+        .with_card_state(0, CardState::review(1609459200, 21, 2.5))
+        .unwrap()
+        .with_review(
+            0,
+            ReviewLogEntry::new(1609459200.0, crate::Ease::Good, crate::ReviewKind::Learning)
+                .time_taken(std::time::Duration::from_millis(1500)),
+        )
+        .unwrap()
+        .suspend_card(1)
+        .unwrap();
+        let value = serde_json::to_value(&note).unwrap();
+        assert_eq!(
+            value["cards"],
+            serde_json::json!([
+                {
+                    "ord": 0,
+                    "state": {
+                        "card_type": "review",
+                        "due": 1609459200,
+                        "interval": 21,
+                        "ease": 2.5,
+                        "remaining_steps": 0,
+                        "reps": 1,
+                        "lapses": 0
+                    },
+                    "reviews": [{
+                        "timestamp": 1609459200.0,
+                        "ease": "good",
+                        "kind": "learning",
+                        "interval": 0,
+                        "last_interval": 0,
+                        "factor": 0.0,
+                        "time_taken": 1.5
+                    }]
+                },
+                { "ord": 1, "suspended": true }
+            ])
+        );
+        let deserialized: Note = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&deserialized).unwrap(), value);
+        assert_eq!(deserialized.get_guid(), note.get_guid());
+        assert_eq!(deserialized.cards()[0].reviews(), note.cards()[0].reviews());
+    }
+
+    #[test]
+    fn deserialize_fields_by_name() {
+        let mut value = serde_json::to_value(crate::basic_model()).unwrap();
+        value = serde_json::json!({ "model": value, "fields": { "Back": "Paris" } });
+        let note: Note = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(note.fields(), ["", "Paris"]);
+        assert_eq!(note.get_guid(), guid_for(&["", "Paris"]));
+
+        value["fields"] = serde_json::json!({ "Front": "France", "Capital": "Paris" });
+        let error = serde_json::from_value::<Note>(value.clone()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            r#"The model has no field called "Capital""#
+        );
+
+        value["fields"] = serde_json::json!(["France"]);
+        let error = serde_json::from_value::<Note>(value.clone()).err().unwrap();
+        assert!(error.to_string().starts_with("number of model field (2)"));
+
+        value["fields"] = serde_json::json!(["France", "Paris"]);
+        value["cards"] = serde_json::json!([{ "ord": 5, "buried": true }]);
+        let error = serde_json::from_value::<Note>(value).err().unwrap();
+        assert_eq!(error.to_string(), "The note has no card with ordinal 5");
+    }
 }
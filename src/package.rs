@@ -1,26 +1,62 @@
-use rusqlite::{Connection, Transaction};
 use std::time::{SystemTime, UNIX_EPOCH};
-use tempfile::NamedTempFile;
-use zip::{write::FileOptions, ZipWriter};
+use tempfile::{NamedTempFile, TempDir};
+use zip::ZipArchive;
 
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Seek, Write};
-use std::path::{Path, PathBuf};
 
-use crate::apkg_col::APKG_COL;
-use crate::apkg_schema::APKG_SCHEMA;
-use crate::deck::Deck;
-use crate::error::{database_error, json_error, zip_error};
+use crate::collection::Schema;
+use crate::deck::{self, Deck};
+use crate::error::zip_error;
+use crate::media::{self, MediaFile, MediaIssue};
+use crate::package_writer::PackageWriter;
+use crate::reader;
+use crate::validation::{ValidationReport, Validator};
+use crate::zero_cards::ZeroCardPolicy;
 use crate::Error;
-use std::str::FromStr;
+
+/// Layout of the collection inside a written `.apkg` file
+///
+/// The default is `PackageFormat::Legacy`, which can be imported by every Anki version. Schema 18
+/// requires unique notetype names, so in `Anki21` and `Anki21b` packages a model whose name is
+/// already taken by another model gets a `+` appended, like Anki does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PackageFormat {
+    /// A schema 11 `collection.anki2`, where decks and models are stored as JSON in the `col` table
+    #[default]
+    Legacy,
+    /// A schema 18 `collection.anki21`, with separate tables for notetypes, fields, templates,
+    /// decks, deck options and tags
+    Anki21,
+    /// A zstd-compressed schema 18 `collection.anki21b` together with a `meta` file, which is the
+    /// format exported by current Anki versions (2.1.50+). Media files are compressed as well.
+    Anki21b,
+}
+
+impl PackageFormat {
+    pub(crate) fn schema(self) -> Schema {
+        match self {
+            PackageFormat::Legacy => Schema::V11,
+            PackageFormat::Anki21 | PackageFormat::Anki21b => Schema::V18,
+        }
+    }
+
+    pub(crate) fn collection_name(self) -> &'static str {
+        match self {
+            PackageFormat::Legacy => "collection.anki2",
+            PackageFormat::Anki21 => "collection.anki21",
+            PackageFormat::Anki21b => "collection.anki21b",
+        }
+    }
+}
 
 /// `Package` to pack `Deck`s and `media_files` and write them to a `.apkg` file
 ///
 /// Example:
-/// ```rust
-/// use genanki_rs::{Package, Deck, Note, Model, Field, Template};
+/// ```rust,no_run
+/// use genanki_rs::{Package, Deck, Error, Note, Model, Field, Template};
 ///
+/// # fn main() -> Result<(), Error> {
 /// let model = Model::new(
 ///     1607392319,
 ///     "Simple Model",
@@ -38,12 +74,18 @@ use std::str::FromStr;
 /// deck.add_note(Note::new(model.clone(), vec!["What is the capital of France?", "Paris", "[sound:sound.mp3]"])?);
 /// deck.add_note(Note::new(model.clone(), vec!["What is the capital of France?", "Paris", r#"<img src="image.jpg">"#])?);
 ///
-/// let mut package = Package::new(vec![my_deck], vec!["sound.mp3", "images/image.jpg"])?;
+/// let mut package = Package::new(vec![deck], vec!["sound.mp3", "images/image.jpg"])?;
 /// package.write_to_file("output.apkg")?;
+/// # Ok(())
+/// # }
 /// ```
 pub struct Package {
     decks: Vec<Deck>,
-    media_files: Vec<PathBuf>,
+    media_files: Vec<MediaFile>,
+    format: PackageFormat,
+    zero_card_policy: ZeroCardPolicy,
+    // Keeps media files extracted by `Package::read` alive as long as the package
+    media_dirs: Vec<TempDir>,
 }
 
 impl Package {
@@ -53,9 +95,196 @@ impl Package {
     pub fn new(decks: Vec<Deck>, media_files: Vec<&str>) -> Result<Self, Error> {
         let media_files = media_files
             .iter()
-            .map(|&s| PathBuf::from_str(s))
+            .map(|&s| MediaFile::from_path(s))
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self { decks, media_files })
+        Ok(Self::new_with_media(decks, media_files))
+    }
+
+    /// Create a new package with `decks` and `media_files` given as `MediaFile`s, which can hold
+    /// content created in memory
+    ///
+    /// Example:
+    /// ```rust,no_run
+    /// use genanki_rs::{Deck, Error, MediaFile, Package};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// # let deck = Deck::new(1234, "Example deck", "This is an example deck");
+    /// # let synthesized_audio = vec![];
+    /// let media = vec![
+    ///     MediaFile::from_bytes("hello.mp3", synthesized_audio)?,
+    ///     MediaFile::from_path("images/image.jpg")?,
+    /// ];
+    /// Package::new_with_media(vec![deck], media).write_to_file("output.apkg")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_media(decks: Vec<Deck>, media_files: Vec<MediaFile>) -> Self {
+        Self {
+            decks,
+            media_files,
+            format: PackageFormat::default(),
+            zero_card_policy: ZeroCardPolicy::default(),
+            media_dirs: vec![],
+        }
+    }
+
+    /// Reads a package from any reader that implements Read and Seek, e.g. an `.apkg` exported by
+    /// Anki or written by this crate.
+    ///
+    /// Media files are extracted into a temporary directory which lives as long as the package.
+    ///
+    /// Returns `Err` if the package is malformed
+    pub fn read<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+        let mut archive = ZipArchive::new(reader).map_err(zip_error)?;
+        let db_file = NamedTempFile::new()?.into_temp_path();
+        let compressed = reader::extract_collection(&mut archive, &db_file)?;
+        let decks = reader::read_decks(&db_file)?;
+        let media_dir = TempDir::new()?;
+        let media_files = reader::extract_media(&mut archive, media_dir.path(), compressed)?;
+        Ok(Self {
+            decks,
+            media_files,
+            format: PackageFormat::default(),
+            zero_card_policy: ZeroCardPolicy::default(),
+            media_dirs: vec![media_dir],
+        })
+    }
+
+    /// Reads a package from an `.apkg` file
+    ///
+    /// Example:
+    /// ```rust,no_run
+    /// use genanki_rs::{Error, Package};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let package = Package::read_from_file("shared_deck.apkg")?;
+    /// for deck in package.decks() {
+    ///     println!("{} contains {} notes", deck.name(), deck.notes().len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Returns `Err` if the `file` cannot be opened or is not a valid package
+    pub fn read_from_file(file: &str) -> Result<Self, Error> {
+        Self::read(File::open(file)?)
+    }
+
+    /// Returns the decks of the package
+    pub fn decks(&self) -> &[Deck] {
+        &self.decks
+    }
+
+    /// Returns the decks of the package for modification
+    pub fn decks_mut(&mut self) -> &mut Vec<Deck> {
+        &mut self.decks
+    }
+
+    /// Returns the deck with the full name `path`, e.g. `Textbook::Chapter 1`, which may be a
+    /// subdeck of one of the decks of the package
+    pub fn deck(&self, path: &str) -> Option<&Deck> {
+        self.decks
+            .iter()
+            .find_map(|deck| match deck.strip_name(path)? {
+                Some(rest) => deck.subdeck(rest),
+                None => Some(deck),
+            })
+    }
+
+    /// Returns the deck with the full name `path` for modification
+    pub fn deck_mut(&mut self, path: &str) -> Option<&mut Deck> {
+        self.decks
+            .iter_mut()
+            .find_map(|deck| match deck.strip_name(path)? {
+                Some(rest) => deck.subdeck_mut(rest),
+                None => Some(deck),
+            })
+    }
+
+    /// Returns the media files of the package
+    pub fn media_files(&self) -> &[MediaFile] {
+        &self.media_files
+    }
+
+    /// Adds a media file to the package
+    pub fn add_media_file(&mut self, media_file: MediaFile) {
+        self.media_files.push(media_file);
+    }
+
+    /// Checks the media files against the `[sound:...]`, `<img src="...">` and CSS `url(...)`
+    /// references in the notes and models of the package, and returns all files which are
+    /// referred to but missing, all files which are never referred to and all names used by
+    /// more than one file
+    ///
+    /// Writing a package with missing or unused files still works, but files with the same name
+    /// are rejected.
+    ///
+    /// Example:
+    /// ```rust
+    /// use genanki_rs::{basic_model, Deck, Error, MediaFile, MediaIssue, Note, Package};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let mut deck = Deck::new(1234, "Example deck", "");
+    /// deck.add_note(Note::new(basic_model(), vec!["[sound:hello.mp3]", "Hello"])?);
+    /// let media = vec![MediaFile::from_bytes("hello.mp3", vec![])?];
+    /// let package = Package::new_with_media(vec![deck], media);
+    /// assert!(package.check_media().iter().all(|issue| !issue.is_error()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check_media(&self) -> Vec<MediaIssue> {
+        media::check_media(&self.decks, &self.media_files)
+    }
+
+    /// Checks the notes of the package for problems like invalid HTML or unbalanced cloze
+    /// deletions with the default [`Validator`] and returns them. Nothing is printed.
+    pub fn validate(&self) -> ValidationReport {
+        Validator::new().validate(&self.decks)
+    }
+
+    /// Splits the package into its decks and media files, together with the temporary directories
+    /// holding media files extracted by `Package::read`
+    pub(crate) fn into_parts(self) -> (Vec<Deck>, Vec<MediaFile>, Vec<TempDir>) {
+        (self.decks, self.media_files, self.media_dirs)
+    }
+
+    /// Creates a package from the parts returned by `Package::into_parts`
+    pub(crate) fn from_parts(
+        decks: Vec<Deck>,
+        media_files: Vec<MediaFile>,
+        media_dirs: Vec<TempDir>,
+    ) -> Self {
+        Self {
+            media_dirs,
+            ..Self::new_with_media(decks, media_files)
+        }
+    }
+
+    /// Sets the format of the collection written into the package
+    ///
+    /// Example:
+    /// ```rust,no_run
+    /// use genanki_rs::{Deck, Error, Package, PackageFormat};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let deck = Deck::new(1234, "Example deck", "This is an example deck");
+    /// Package::new(vec![deck], vec![])?
+    ///     .format(PackageFormat::Anki21b)
+    ///     .write_to_file("output.apkg")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn format(self, format: PackageFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Sets what happens to notes which generate no cards, for all decks which do not set their
+    /// own [`ZeroCardPolicy`]. Default is to write them anyway.
+    pub fn zero_card_policy(self, zero_card_policy: ZeroCardPolicy) -> Self {
+        Self {
+            zero_card_policy,
+            ..self
+        }
     }
 
     /// Writes the package to any writer that implements Write and Seek
@@ -64,6 +293,28 @@ impl Package {
     }
 
     /// Writes the package to any writer that implements Write and Seek using a timestamp
+    ///
+    /// The timestamp is used for all modification times and ids, so writing the same package with
+    /// the same timestamp produces byte-identical output, e.g. for checking decks into version
+    /// control:
+    ///
+    /// ```rust
+    /// use genanki_rs::{basic_model, Deck, Error, Note, Package};
+    /// use std::io::Cursor;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let mut deck = Deck::new(1234, "Example deck", "");
+    /// deck.add_note(Note::new(basic_model(), vec!["Capital of France", "Paris"])?);
+    /// let mut package = Package::new(vec![deck], vec![])?;
+    ///
+    /// let mut first = Cursor::new(vec![]);
+    /// package.write_timestamp(&mut first, 1_600_000_000.0)?;
+    /// let mut second = Cursor::new(vec![]);
+    /// package.write_timestamp(&mut second, 1_600_000_000.0)?;
+    /// assert_eq!(first.into_inner(), second.into_inner());
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn write_timestamp<W: Write + Seek>(
         &mut self,
         writer: W,
@@ -80,7 +331,8 @@ impl Package {
         self.write_maybe_timestamp(file, None)
     }
 
-    /// Writes the package to a file using a timestamp
+    /// Writes the package to a file using a timestamp, which makes the file reproducible like
+    /// [`Package::write_timestamp`]
     ///
     /// Returns `Err` if the `file` cannot be created
     pub fn write_to_file_timestamp(&mut self, file: &str, timestamp: f64) -> Result<(), Error> {
@@ -93,79 +345,116 @@ impl Package {
         writer: W,
         timestamp: Option<f64>,
     ) -> Result<(), Error> {
-        let db_file = NamedTempFile::new()?.into_temp_path();
-
-        let mut conn = Connection::open(&db_file).map_err(database_error)?;
-        let transaction = conn.transaction().map_err(database_error)?;
+        // Check the decks before anything is written
+        let parents = deck::missing_parent_decks(&self.decks)?;
+        deck::check_card_decks(&self.decks, &parents)?;
 
         let timestamp = if let Some(timestamp) = timestamp {
             timestamp
         } else {
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64()
         };
+        let mut package_writer = PackageWriter::new_with_options(writer, self.format, timestamp)?
+            .zero_card_policy(self.zero_card_policy);
+        for deck in &mut self.decks {
+            package_writer.write_deck(deck)?;
+        }
+        for media_file in &self.media_files {
+            package_writer.write_media_file(media_file)?;
+        }
+        package_writer.finish()?;
+        Ok(())
+    }
+}
 
-        self.write_to_db(&transaction, timestamp)?;
-        transaction.commit().map_err(database_error)?;
-        conn.close().expect("Should always close");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::all_notes;
+    use crate::{
+        basic_and_reversed_card_model, cloze_model, DeckConfig, Field, Model, Note, Template,
+    };
+    use std::io::Cursor;
 
-        let mut outzip = ZipWriter::new(writer);
-        outzip
-            .start_file("collection.anki2", FileOptions::default())
-            .map_err(zip_error)?;
-        outzip.write_all(&read_file_bytes(db_file)?)?;
+    fn package(format: PackageFormat) -> Package {
+        let mut deck =
+            Deck::new(1234, "Textbook::Chapter 1", "").with_config(DeckConfig::new(7, "Course"));
+        deck.add_note(
+            Note::new(
+                cloze_model(),
+                vec!["{{c3::Paris}} {{c1::is}} {{c2::the}} {{c4::capital}}"],
+            )
+            .unwrap(),
+        );
+        deck.add_note(
+            Note::new(basic_and_reversed_card_model(), vec!["Berlin", "Germany"])
+                .unwrap()
+                .tags(["capital", "europe"]),
+        );
+        let mut other = Deck::new(5678, "Other", "");
+        other.add_note(Note::new(basic_and_reversed_card_model(), vec!["Rome", "Italy"]).unwrap());
+        let media = (0..20)
+            .map(|idx| MediaFile::from_bytes(&format!("{}.mp3", idx), vec![idx as u8]).unwrap())
+            .collect();
+        Package::new_with_media(vec![deck, other], media).format(format)
+    }
 
-        let media_file_idx_to_path = self
-            .media_files
-            .iter()
-            .enumerate()
-            .collect::<HashMap<usize, &PathBuf>>();
-        let media_map = media_file_idx_to_path
-            .clone()
-            .into_iter()
-            .map(|(id, path)| {
-                (
-                    id.to_string(),
-                    path.file_name()
-                        .expect("Should always have a filename")
-                        .to_str()
-                        .expect("should always have string"),
-                )
-            })
-            .collect::<HashMap<String, &str>>();
-        let media_json = serde_json::to_string(&media_map).map_err(json_error)?;
-        outzip
-            .start_file("media", FileOptions::default())
-            .map_err(zip_error)?;
-        outzip.write_all(media_json.as_bytes())?;
-
-        for (idx, &path) in &media_file_idx_to_path {
-            outzip
-                .start_file(idx.to_string(), FileOptions::default())
-                .map_err(zip_error)?;
-            outzip.write_all(&read_file_bytes(path)?)?;
+    #[test]
+    fn output_is_reproducible() {
+        for format in [
+            PackageFormat::Legacy,
+            PackageFormat::Anki21,
+            PackageFormat::Anki21b,
+        ] {
+            let mut outputs = vec![];
+            for _ in 0..2 {
+                let mut buffer = Cursor::new(vec![]);
+                package(format)
+                    .write_timestamp(&mut buffer, 1_600_000_000.0)
+                    .unwrap();
+                outputs.push(buffer.into_inner());
+            }
+            assert!(outputs[0] == outputs[1], "{:?} output differs", format);
         }
-        outzip.finish().map_err(zip_error)?;
-        Ok(())
     }
 
-    fn write_to_db(&mut self, transaction: &Transaction, timestamp: f64) -> Result<(), Error> {
-        let mut id_gen = ((timestamp * 1000.0) as usize)..;
-        transaction
-            .execute_batch(APKG_SCHEMA)
-            .map_err(database_error)?;
-        transaction
-            .execute_batch(APKG_COL)
-            .map_err(database_error)?;
-        for deck in &mut self.decks {
-            deck.write_to_db(&transaction, timestamp, &mut id_gen)?;
+    #[test]
+    fn models_with_the_same_name() {
+        let model = |id| {
+            Model::new(
+                id,
+                "Same",
+                vec![Field::new("Front")],
+                vec![Template::new("Card 1").qfmt("{{Front}}").afmt("{{Front}}")],
+            )
+        };
+        for (format, second_name) in [
+            (PackageFormat::Legacy, "Same"),
+            (PackageFormat::Anki21, "Same+"),
+            (PackageFormat::Anki21b, "Same+"),
+        ] {
+            let mut deck = Deck::new(1234, "Example deck", "");
+            deck.add_note(Note::new(model(1), vec!["Paris"]).unwrap());
+            deck.add_note(Note::new(model(2), vec!["Berlin"]).unwrap());
+            let mut buffer = Cursor::new(vec![]);
+            Package::new(vec![deck], vec![])
+                .unwrap()
+                .format(format)
+                .write_timestamp(&mut buffer, 1_600_000_000.0)
+                .unwrap();
+            buffer.set_position(0);
+            let package = Package::read(buffer).unwrap();
+            let mut models = all_notes(package.decks())
+                .iter()
+                .map(|note| (note.model().id, note.model().get_name().to_string()))
+                .collect::<Vec<_>>();
+            models.sort();
+            assert_eq!(
+                models,
+                [(1, "Same".to_string()), (2, second_name.to_string())],
+                "{:?}",
+                format
+            );
         }
-        Ok(())
     }
 }
-
-fn read_file_bytes<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
-    let mut handle = File::open(path)?;
-    let mut data = Vec::new();
-    handle.read_to_end(&mut data)?;
-    Ok(data)
-}
@@ -0,0 +1,371 @@
+use rusqlite::Connection;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::ops::RangeFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::{NamedTempFile, TempPath};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::collection::Schema;
+use crate::deck::{self, Deck};
+use crate::error::{database_error, json_error, zip_error};
+use crate::media::MediaFile;
+use crate::note::Note;
+use crate::package::PackageFormat;
+use crate::protobuf::Message;
+use crate::util::zip_date_time;
+use crate::zero_cards::ZeroCardPolicy;
+use crate::Error;
+
+/// Number of notes collected before they are inserted into the collection in one transaction
+const BATCH_SIZE: usize = 1000;
+
+/// Writes a package incrementally, for decks too large to be kept in memory as a `Package`
+///
+/// Notes are inserted into a temporary collection as they are added, and media files are
+/// streamed into the archive right away. The collection itself is streamed into the archive by
+/// [`PackageWriter::finish`], which has to be called to complete the package.
+///
+/// Example:
+/// ```rust,no_run
+/// use genanki_rs::{basic_model, Deck, Error, MediaFile, Note, PackageWriter};
+/// use std::fs::File;
+///
+/// # fn main() -> Result<(), Error> {
+/// let mut writer = PackageWriter::new(File::create("output.apkg")?)?;
+/// writer.add_deck(Deck::new(1234, "Large deck", ""))?;
+/// for i in 0..1_000_000 {
+///     let question = format!("What is {} + {}?", i, i);
+///     let answer = (2 * i).to_string();
+///     writer.add_note(1234, Note::new(basic_model(), vec![&question, &answer])?)?;
+/// }
+/// writer.add_media_file(MediaFile::from_path("sound.mp3")?)?;
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct PackageWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    file_options: FileOptions,
+    format: PackageFormat,
+    schema: Schema,
+    timestamp: f64,
+    zero_card_policy: ZeroCardPolicy,
+    db_file: TempPath,
+    conn: Connection,
+    id_gen: RangeFrom<usize>,
+    pending_notes: Vec<(i64, Note)>,
+    // Full names and ids of all decks and subdecks written so far
+    deck_names: Vec<(String, i64)>,
+    // Decks cards were put into with `Note::card_deck`, checked once all decks are known
+    card_deck_ids: BTreeSet<i64>,
+    model_ids: HashSet<i64>,
+    // Name, size and SHA1 hash of every media file written so far, in the order of their index
+    media: Vec<(String, u64, Vec<u8>)>,
+    media_hashes: HashMap<String, Vec<u8>>,
+}
+
+impl<W: Write + Seek> PackageWriter<W> {
+    /// Starts writing a package in the default `PackageFormat` into `writer`, using the current
+    /// time as timestamp
+    pub fn new(writer: W) -> Result<Self, Error> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+        Self::new_with_options(writer, PackageFormat::default(), timestamp)
+    }
+
+    /// Starts writing a package in `format` into `writer`, using `timestamp` for modification
+    /// times and as the start of the generated note and card ids
+    pub fn new_with_options(
+        writer: W,
+        format: PackageFormat,
+        timestamp: f64,
+    ) -> Result<Self, Error> {
+        let db_file = NamedTempFile::new()?.into_temp_path();
+        let schema = format.schema();
+        let mut conn = Connection::open(&db_file).map_err(database_error)?;
+        schema.prepare_connection(&conn)?;
+        let transaction = conn.transaction().map_err(database_error)?;
+        schema.create(&transaction)?;
+        transaction.commit().map_err(database_error)?;
+        Ok(Self {
+            zip: ZipWriter::new(writer),
+            file_options: FileOptions::default().last_modified_time(zip_date_time(timestamp)),
+            format,
+            schema,
+            timestamp,
+            zero_card_policy: ZeroCardPolicy::default(),
+            db_file,
+            conn,
+            id_gen: ((timestamp * 1000.0) as usize)..,
+            pending_notes: vec![],
+            deck_names: vec![],
+            card_deck_ids: BTreeSet::new(),
+            model_ids: HashSet::new(),
+            media: vec![],
+            media_hashes: HashMap::new(),
+        })
+    }
+
+    /// Sets what happens to notes which generate no cards, for the notes added with
+    /// [`PackageWriter::add_note`] and the decks which do not set their own [`ZeroCardPolicy`].
+    /// Default is to write them anyway.
+    pub fn zero_card_policy(self, zero_card_policy: ZeroCardPolicy) -> Self {
+        Self {
+            zero_card_policy,
+            ..self
+        }
+    }
+
+    /// Writes `deck` together with its notes and subdecks. Notes can be added to the deck and its
+    /// subdecks afterwards with [`PackageWriter::add_note`].
+    pub fn add_deck(&mut self, mut deck: Deck) -> Result<(), Error> {
+        self.write_deck(&mut deck)
+    }
+
+    pub(crate) fn write_deck(&mut self, deck: &mut Deck) -> Result<(), Error> {
+        let decks = std::slice::from_ref(deck);
+        deck::collect_deck_names(decks, None, &mut self.deck_names);
+        deck::collect_card_deck_ids(decks, &mut self.card_deck_ids);
+        let transaction = self.conn.transaction().map_err(database_error)?;
+        deck.write_to_db(
+            &transaction,
+            self.timestamp,
+            self.schema,
+            None,
+            self.zero_card_policy,
+            &mut self.id_gen,
+        )?;
+        transaction.commit().map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Adds `note` to the deck with `deck_id`, which has to be added with
+    /// [`PackageWriter::add_deck`] before, either directly or as a subdeck
+    ///
+    /// Notes are buffered and inserted in batches, so errors of invalid notes may be returned by
+    /// a later call or by [`PackageWriter::finish`].
+    pub fn add_note(&mut self, deck_id: i64, note: Note) -> Result<(), Error> {
+        if !self.deck_names.iter().any(|(_, id)| *id == deck_id) {
+            return Err(Error::DeckNotFound(deck_id));
+        }
+        self.card_deck_ids
+            .extend(note.cards().iter().filter_map(|card| card.deck_id()));
+        self.pending_notes.push((deck_id, note));
+        if self.pending_notes.len() >= BATCH_SIZE {
+            self.write_pending_notes()?;
+        }
+        Ok(())
+    }
+
+    fn write_pending_notes(&mut self) -> Result<(), Error> {
+        let transaction = self.conn.transaction().map_err(database_error)?;
+        for (deck_id, note) in self.pending_notes.drain(..) {
+            if !self.zero_card_policy.admits(&note)? {
+                continue;
+            }
+            let model = note.model();
+            if self.model_ids.insert(model.id) {
+                self.schema.add_model(
+                    &transaction,
+                    &model.to_model_db_entry(self.timestamp, deck_id)?,
+                )?;
+            }
+            self.schema.add_tags(&transaction, note.get_tags())?;
+            note.write_to_db(&transaction, self.timestamp, deck_id, &mut self.id_gen)?;
+        }
+        transaction.commit().map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Streams `media_file` into the package
+    ///
+    /// Adding a file with the same name and content twice stores it once. Returns `Err` if a
+    /// different file with the same name was added before.
+    pub fn add_media_file(&mut self, media_file: MediaFile) -> Result<(), Error> {
+        self.write_media_file(&media_file)
+    }
+
+    pub(crate) fn write_media_file(&mut self, media_file: &MediaFile) -> Result<(), Error> {
+        let name = media_file.name();
+        if let Some(hash) = self.media_hashes.get(name) {
+            let mut reader = HashingReader::new(media_file.open()?);
+            io::copy(&mut reader, &mut io::sink())?;
+            if reader.hasher.finalize().to_vec() == *hash {
+                return Ok(());
+            }
+            return Err(Error::MediaNameCollision(name.to_string()));
+        }
+
+        self.zip
+            .start_file(self.media.len().to_string(), self.file_options)
+            .map_err(zip_error)?;
+        let mut reader = HashingReader::new(media_file.open()?);
+        if self.format == PackageFormat::Anki21b {
+            zstd::stream::copy_encode(&mut reader, &mut self.zip, 0)?;
+        } else {
+            io::copy(&mut reader, &mut self.zip)?;
+        }
+        let hash = reader.hasher.finalize().to_vec();
+        self.media_hashes.insert(name.to_string(), hash.clone());
+        self.media.push((name.to_string(), reader.len, hash));
+        Ok(())
+    }
+
+    /// Writes the remaining notes, creates missing parent decks and streams the collection and
+    /// the media index into the package
+    ///
+    /// Returns the underlying writer, or `Err` if the decks conflict, a card is put into a deck
+    /// which was never added or writing fails
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_pending_notes()?;
+        let parents = deck::missing_parents(&self.deck_names)?;
+        deck::check_deck_ids(&self.card_deck_ids, &self.deck_names, &parents)?;
+        let transaction = self.conn.transaction().map_err(database_error)?;
+        for mut parent in parents {
+            parent.write_to_db(
+                &transaction,
+                self.timestamp,
+                self.schema,
+                None,
+                self.zero_card_policy,
+                &mut self.id_gen,
+            )?;
+        }
+        transaction.commit().map_err(database_error)?;
+        self.conn
+            .close()
+            .map_err(|(_, error)| database_error(error))?;
+
+        let mut zip = self.zip;
+        zip.start_file(self.format.collection_name(), self.file_options)
+            .map_err(zip_error)?;
+        if self.format == PackageFormat::Anki21b {
+            zstd::stream::copy_encode(File::open(&self.db_file)?, &mut zip, 0)?;
+            zip.start_file("meta", self.file_options)
+                .map_err(zip_error)?;
+            zip.write_all(&Message::new().uint(1, 3).into_bytes())?;
+        } else {
+            io::copy(&mut File::open(&self.db_file)?, &mut zip)?;
+        }
+
+        zip.start_file("media", self.file_options)
+            .map_err(zip_error)?;
+        if self.format == PackageFormat::Anki21b {
+            // `MediaEntries` message, the index of an entry is the name of its file in the zip
+            let mut media_entries = Message::new();
+            for (name, len, hash) in &self.media {
+                media_entries = media_entries.message(
+                    1,
+                    Message::new().string(1, name).uint(2, *len).bytes(3, hash),
+                );
+            }
+            zip.write_all(&zstd::encode_all(&media_entries.into_bytes()[..], 0)?)?;
+        } else {
+            let media_map = self
+                .media
+                .iter()
+                .enumerate()
+                .map(|(idx, (name, _, _))| (idx, name.as_str()))
+                .collect::<BTreeMap<usize, &str>>();
+            zip.write_all(
+                serde_json::to_string(&media_map)
+                    .map_err(json_error)?
+                    .as_bytes(),
+            )?;
+        }
+        zip.finish().map_err(zip_error)
+    }
+}
+
+/// Computes the SHA1 hash and the length of everything read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha1,
+    len: u64,
+}
+
+impl<R> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha1::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, Package};
+    use std::io::Cursor;
+
+    #[test]
+    fn writes_incrementally() {
+        let mut writer = PackageWriter::new_with_options(
+            Cursor::new(vec![]),
+            PackageFormat::Anki21b,
+            1_600_000_000.0,
+        )
+        .unwrap();
+        let mut deck = Deck::new(1234, "Numbers::Large", "");
+        deck.add_subdeck(Deck::new(5678, "Small", "")).unwrap();
+        writer.add_deck(deck).unwrap();
+        // One more note than fits into a batch, so the last one is written by `finish`
+        let note = Note::new(basic_model(), vec!["question", "answer"]).unwrap();
+        for i in 0..=BATCH_SIZE {
+            let deck_id = if i < BATCH_SIZE { 1234 } else { 5678 };
+            writer.add_note(deck_id, note.clone()).unwrap();
+        }
+        assert!(matches!(
+            writer.add_note(42, Note::new(basic_model(), vec!["q", "a"]).unwrap()),
+            Err(Error::DeckNotFound(42))
+        ));
+        let sound = MediaFile::from_bytes("sound.mp3", b"sound".to_vec()).unwrap();
+        writer.add_media_file(sound.clone()).unwrap();
+        writer.add_media_file(sound).unwrap();
+        assert!(matches!(
+            writer.add_media_file(MediaFile::from_bytes("sound.mp3", b"other".to_vec()).unwrap()),
+            Err(Error::MediaNameCollision(_))
+        ));
+        let mut buffer = writer.finish().unwrap();
+        buffer.set_position(0);
+
+        let package = Package::read(buffer).unwrap();
+        let numbers = package.deck("Numbers").unwrap();
+        assert_eq!(numbers.notes().len(), 0);
+        let large = package.deck("Numbers::Large").unwrap();
+        assert_eq!(large.id(), 1234);
+        assert_eq!(large.notes().len(), BATCH_SIZE);
+        let small = package.deck("Numbers::Large::Small").unwrap();
+        assert_eq!(small.notes().len(), 1);
+        assert_eq!(package.media_files().len(), 1);
+        assert_eq!(package.media_files()[0].data().unwrap(), b"sound");
+    }
+
+    #[test]
+    fn card_decks_are_checked_on_finish() {
+        let mut writer = PackageWriter::new(Cursor::new(vec![])).unwrap();
+        writer.add_deck(Deck::new(1234, "Deck", "")).unwrap();
+        let note = Note::new(basic_model(), vec!["q", "a"])
+            .unwrap()
+            .card_deck(0, 5678)
+            .unwrap();
+        writer.add_note(1234, note).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(Error::CardDeckNotFound(5678))
+        ));
+    }
+}
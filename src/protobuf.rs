@@ -0,0 +1,337 @@
+//! Minimal protobuf encoding and decoding for the config blobs of the schema 18 collection tables.
+//!
+//! Anki stores notetype, field, template, deck and deck option settings as protobuf messages.
+//! Only the handful of scalar types used by those messages is supported, and as in proto3 fields
+//! holding their default value are omitted.
+
+use crate::Error;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+/// A protobuf message which is built field by field
+#[derive(Default)]
+pub(crate) struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn uint(mut self, field: u32, value: u64) -> Self {
+        if value != 0 {
+            self.key(field, WIRE_VARINT);
+            write_varint(&mut self.buf, value);
+        }
+        self
+    }
+
+    pub(crate) fn int(self, field: u32, value: i64) -> Self {
+        self.uint(field, value as u64)
+    }
+
+    pub(crate) fn bool(self, field: u32, value: bool) -> Self {
+        self.uint(field, value as u64)
+    }
+
+    pub(crate) fn float(mut self, field: u32, value: f32) -> Self {
+        if value != 0.0 {
+            self.key(field, WIRE_FIXED32);
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+        self
+    }
+
+    pub(crate) fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+        if !value.is_empty() {
+            self.key(field, WIRE_LENGTH_DELIMITED);
+            write_varint(&mut self.buf, value.len() as u64);
+            self.buf.extend_from_slice(value);
+        }
+        self
+    }
+
+    pub(crate) fn string(self, field: u32, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    /// Writes a nested message. Unlike scalars it is always written, even if empty, because its
+    /// presence is meaningful for `oneof` fields.
+    pub(crate) fn message(mut self, field: u32, value: Message) -> Self {
+        self.key(field, WIRE_LENGTH_DELIMITED);
+        write_varint(&mut self.buf, value.buf.len() as u64);
+        self.buf.extend_from_slice(&value.buf);
+        self
+    }
+
+    pub(crate) fn packed_uints(self, field: u32, values: impl IntoIterator<Item = u64>) -> Self {
+        let mut packed = vec![];
+        values
+            .into_iter()
+            .for_each(|value| write_varint(&mut packed, value));
+        self.bytes(field, &packed)
+    }
+
+    pub(crate) fn packed_floats(self, field: u32, values: impl IntoIterator<Item = f32>) -> Self {
+        let packed = values
+            .into_iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.bytes(field, &packed)
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        write_varint(&mut self.buf, ((field as u64) << 3) | wire_type);
+    }
+}
+
+/// A decoded protobuf message. Unknown fields are kept and simply never looked at.
+pub(crate) struct DecodedMessage<'a> {
+    fields: Vec<(u32, DecodedValue<'a>)>,
+}
+
+#[derive(Clone, Copy)]
+enum DecodedValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> DecodedMessage<'a> {
+    pub(crate) fn decode(mut buf: &'a [u8]) -> Result<Self, Error> {
+        let mut fields = vec![];
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf)?;
+            let field = (key >> 3) as u32;
+            let value = match key & 0x7 {
+                WIRE_VARINT => DecodedValue::Varint(read_varint(&mut buf)?),
+                1 => {
+                    take(&mut buf, 8)?;
+                    DecodedValue::Fixed64
+                }
+                WIRE_LENGTH_DELIMITED => {
+                    let len = read_varint(&mut buf)? as usize;
+                    DecodedValue::Bytes(take(&mut buf, len)?)
+                }
+                WIRE_FIXED32 => {
+                    let bytes = take(&mut buf, 4)?;
+                    DecodedValue::Fixed32(u32::from_le_bytes([
+                        bytes[0], bytes[1], bytes[2], bytes[3],
+                    ]))
+                }
+                wire_type => {
+                    return Err(Error::InvalidPackage(format!(
+                        "unsupported protobuf wire type {}",
+                        wire_type
+                    )))
+                }
+            };
+            fields.push((field, value));
+        }
+        Ok(Self { fields })
+    }
+
+    fn last(&self, field: u32) -> Option<DecodedValue<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == field)
+            .map(|(_, value)| *value)
+    }
+
+    pub(crate) fn uint(&self, field: u32) -> u64 {
+        match self.last(field) {
+            Some(DecodedValue::Varint(value)) => value,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn int(&self, field: u32) -> i64 {
+        self.uint(field) as i64
+    }
+
+    pub(crate) fn bool(&self, field: u32) -> bool {
+        self.uint(field) != 0
+    }
+
+    pub(crate) fn float(&self, field: u32) -> f32 {
+        match self.last(field) {
+            Some(DecodedValue::Fixed32(bits)) => f32::from_bits(bits),
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn bytes(&self, field: u32) -> &'a [u8] {
+        match self.last(field) {
+            Some(DecodedValue::Bytes(bytes)) => bytes,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn string(&self, field: u32) -> String {
+        String::from_utf8_lossy(self.bytes(field)).into_owned()
+    }
+
+    pub(crate) fn message(&self, field: u32) -> Result<DecodedMessage<'a>, Error> {
+        DecodedMessage::decode(self.bytes(field))
+    }
+
+    /// Returns all occurrences of a repeated message field
+    pub(crate) fn messages(&self, field: u32) -> Result<Vec<DecodedMessage<'a>>, Error> {
+        self.fields
+            .iter()
+            .filter(|(f, _)| *f == field)
+            .filter_map(|(_, value)| match value {
+                DecodedValue::Bytes(bytes) => Some(DecodedMessage::decode(bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns all values of a repeated float field, whether packed or not
+    pub(crate) fn floats(&self, field: u32) -> Result<Vec<f32>, Error> {
+        let mut values = vec![];
+        for (_, value) in self.fields.iter().filter(|(f, _)| *f == field) {
+            match value {
+                DecodedValue::Fixed32(bits) => values.push(f32::from_bits(*bits)),
+                DecodedValue::Bytes(mut packed) => {
+                    while !packed.is_empty() {
+                        let bytes = take(&mut packed, 4)?;
+                        values.push(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns all values of a repeated varint field, whether packed or not
+    pub(crate) fn uints(&self, field: u32) -> Result<Vec<u64>, Error> {
+        let mut values = vec![];
+        for (_, value) in self.fields.iter().filter(|(f, _)| *f == field) {
+            match value {
+                DecodedValue::Varint(value) => values.push(*value),
+                DecodedValue::Bytes(mut packed) => {
+                    while !packed.is_empty() {
+                        values.push(read_varint(&mut packed)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(values)
+    }
+}
+
+fn truncated() -> Error {
+    Error::InvalidPackage("truncated protobuf message".to_string())
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if buf.len() < len {
+        return Err(truncated());
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first().ok_or_else(truncated)?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidPackage(
+        "protobuf varint is too long".to_string(),
+    ))
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        // Reference encodings taken from the protobuf documentation
+        assert_eq!(
+            Message::new().uint(1, 150).into_bytes(),
+            vec![0x08, 0x96, 0x01]
+        );
+        assert_eq!(
+            Message::new().string(2, "testing").into_bytes(),
+            b"\x12\x07testing".to_vec()
+        );
+        assert_eq!(
+            Message::new()
+                .message(3, Message::new().uint(1, 150))
+                .into_bytes(),
+            vec![0x1a, 0x03, 0x08, 0x96, 0x01]
+        );
+        assert_eq!(
+            Message::new().packed_uints(4, [3, 270, 86942]).into_bytes(),
+            vec![0x22, 0x06, 0x03, 0x8e, 0x02, 0x9e, 0xa7, 0x05]
+        );
+        assert_eq!(
+            Message::new().int(1, -1).into_bytes(),
+            vec![0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn decoding() {
+        let bytes = Message::new()
+            .uint(1, 150)
+            .string(2, "testing")
+            .message(3, Message::new().int(1, -5))
+            .message(3, Message::new().int(1, 7))
+            .packed_uints(4, [3, 270, 86942])
+            .float(5, 2.5)
+            .packed_floats(6, [1.0, 10.0])
+            .into_bytes();
+        let message = DecodedMessage::decode(&bytes).unwrap();
+        assert_eq!(message.uint(1), 150);
+        assert_eq!(message.string(2), "testing");
+        let nested = message.messages(3).unwrap();
+        assert_eq!(nested.len(), 2);
+        assert_eq!(nested[0].int(1), -5);
+        assert_eq!(message.message(3).unwrap().int(1), 7);
+        assert_eq!(message.uints(4).unwrap(), vec![3, 270, 86942]);
+        assert_eq!(message.float(5), 2.5);
+        assert_eq!(message.floats(6).unwrap(), vec![1.0, 10.0]);
+        assert_eq!(message.uint(7), 0);
+        assert!(DecodedMessage::decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn defaults_are_omitted() {
+        let message = Message::new()
+            .uint(1, 0)
+            .bool(2, false)
+            .float(3, 0.0)
+            .string(4, "")
+            .packed_floats(5, []);
+        assert!(message.into_bytes().is_empty());
+    }
+}
@@ -0,0 +1,671 @@
+use crate::card::{Card, CardState};
+use crate::collection::Schema;
+use crate::db_entries::{
+    DeckConfigDbEntry, DeckDbEntry, Fld, LapseConfigDbEntry, ModelDbEntry, NewConfigDbEntry,
+    RevConfigDbEntry, Tmpl,
+};
+use crate::deck::Deck;
+use crate::deck_config::DeckConfig;
+use crate::error::{database_error, json_error, zip_error};
+use crate::media::MediaFile;
+use crate::model::Model;
+use crate::note::Note;
+use crate::protobuf::DecodedMessage;
+use crate::revlog::ReviewLogEntry;
+use crate::Error;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+const DEFAULT_DECK_ID: i64 = 1;
+
+/// First schema version in which notetypes and decks have their own tables
+const SCHEMA_WITH_TABLES: i64 = 15;
+
+/// Names of the collection inside an `.apkg`, in order of preference.
+///
+/// Packages exported by Anki 2.1 contain a `collection.anki21` or a zstd-compressed
+/// `collection.anki21b` next to a legacy `collection.anki2`, which only exists for compatibility
+/// with old clients.
+const COLLECTION_NAMES: [&str; 3] = [
+    "collection.anki21b",
+    "collection.anki21",
+    "collection.anki2",
+];
+
+/// Copies the collection database of the package into `db_file`.
+///
+/// Returns whether the package uses the compressed `anki21b` layout, in which media files are
+/// compressed as well.
+pub(crate) fn extract_collection<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    db_file: &Path,
+) -> Result<bool, Error> {
+    let name = *COLLECTION_NAMES
+        .iter()
+        .find(|&&name| archive.by_name(name).is_ok())
+        .ok_or_else(|| Error::InvalidPackage("no collection found in package".to_string()))?;
+    let mut entry = archive.by_name(name).map_err(zip_error)?;
+    let compressed = name == "collection.anki21b";
+    if compressed {
+        zstd::stream::copy_decode(&mut entry, File::create(db_file)?)?;
+    } else {
+        std::io::copy(&mut entry, &mut File::create(db_file)?)?;
+    }
+    Ok(compressed)
+}
+
+/// Extracts all media files of the package into `dir` and returns them
+pub(crate) fn extract_media<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    dir: &Path,
+    compressed: bool,
+) -> Result<Vec<MediaFile>, Error> {
+    let mut media = vec![];
+    match archive.by_name("media") {
+        Ok(mut entry) => entry.read_to_end(&mut media)?,
+        Err(_) => return Ok(vec![]),
+    };
+    let media_map = if compressed {
+        // `MediaEntries` message, the index of an entry is the name of its file in the zip
+        let media = zstd::decode_all(&media[..])?;
+        DecodedMessage::decode(&media)?
+            .messages(1)?
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| (idx, (idx.to_string(), entry.string(1))))
+            .collect::<BTreeMap<_, _>>()
+    } else {
+        let media_map: HashMap<String, String> =
+            serde_json::from_slice(&media).map_err(json_error)?;
+        media_map
+            .into_iter()
+            .map(|(idx, name)| {
+                idx.parse::<usize>()
+                    .map(|idx| (idx, (idx.to_string(), name)))
+                    .map_err(|_| Error::InvalidPackage(format!("invalid media index {:?}", idx)))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?
+    };
+
+    let mut media_files = vec![];
+    for (idx, name) in media_map.values() {
+        if Path::new(name).file_name().and_then(|n| n.to_str()) != Some(name.as_str()) {
+            return Err(Error::InvalidPackage(format!(
+                "invalid media file name {:?}",
+                name
+            )));
+        }
+        let mut entry = archive.by_name(idx).map_err(|_| {
+            Error::InvalidPackage(format!("media file {:?} is missing from the package", name))
+        })?;
+        let path = dir.join(name);
+        if compressed {
+            zstd::stream::copy_decode(&mut entry, File::create(&path)?)?;
+        } else {
+            std::io::copy(&mut entry, &mut File::create(&path)?)?;
+        }
+        media_files.push(MediaFile::from_path(path)?);
+    }
+    Ok(media_files)
+}
+
+/// Reads all decks of the collection stored in `db_file`, together with their notes
+pub(crate) fn read_decks(db_file: &Path) -> Result<Vec<Deck>, Error> {
+    let conn = Connection::open_with_flags(db_file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(database_error)?;
+    Schema::V18.prepare_connection(&conn)?;
+    let (version, creation_time): (i64, i64) = conn
+        .query_row("SELECT ver, crt FROM col", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .map_err(database_error)?;
+    let (model_entries, deck_entries, config_entries) = if version >= SCHEMA_WITH_TABLES {
+        (
+            read_notetypes(&conn)?,
+            read_deck_rows(&conn)?,
+            read_deck_config_rows(&conn)?,
+        )
+    } else {
+        let (models_json, decks_json, dconf_json): (String, String, String) = conn
+            .query_row("SELECT models, decks, dconf FROM col", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .map_err(database_error)?;
+        (
+            serde_json::from_str::<HashMap<String, ModelDbEntry>>(&models_json)
+                .map_err(json_error)?
+                .into_values()
+                .collect(),
+            serde_json::from_str::<HashMap<String, DeckDbEntry>>(&decks_json)
+                .map_err(json_error)?
+                .into_values()
+                .collect(),
+            serde_json::from_str::<HashMap<String, DeckConfigDbEntry>>(&dconf_json)
+                .map_err(json_error)?
+                .into_values()
+                .collect(),
+        )
+    };
+    let models = model_entries
+        .into_iter()
+        .map(|entry| Model::from_model_db_entry(entry).map(|model| (model.id, model)))
+        .collect::<Result<HashMap<i64, Model>, Error>>()?;
+    let deck_entries = deck_entries
+        .into_iter()
+        .map(|entry| (entry.id, entry))
+        .collect::<BTreeMap<i64, DeckDbEntry>>();
+    let configs = config_entries
+        .into_iter()
+        .map(|entry| (entry.id, DeckConfig::from_deck_config_db_entry(entry)))
+        .collect::<HashMap<i64, DeckConfig>>();
+
+    let mut reviews: HashMap<i64, Vec<ReviewLogEntry>> = HashMap::new();
+    let mut statement = conn
+        .prepare("SELECT cid, id, ease, ivl, lastIvl, factor, time, type FROM revlog ORDER BY id")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let column = |idx| row.get::<_, i64>(idx).map_err(database_error);
+        reviews
+            .entry(column(0)?)
+            .or_default()
+            .push(ReviewLogEntry::from_columns(
+                column(1)?,
+                column(2)?,
+                column(3)?,
+                column(4)?,
+                column(5)?,
+                column(6)?,
+                column(7)?,
+            ));
+    }
+
+    let mut cards: HashMap<i64, Vec<(i64, Card)>> = HashMap::new();
+    let mut statement = conn
+        .prepare(
+            "SELECT nid, did, ord, type, queue, due, ivl, factor, reps, lapses, left, id, \
+             flags FROM cards ORDER BY nid, ord",
+        )
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let column = |idx| row.get::<_, i64>(idx).map_err(database_error);
+        let mut card = Card::from_columns(column(2)?, column(4)?, column(12)?, None);
+        card.state = CardState::from_columns(
+            column(3)?,
+            column(5)?,
+            column(6)?,
+            column(7)?,
+            column(8)?,
+            column(9)?,
+            column(10)?,
+            creation_time,
+        );
+        card.reviews = reviews.remove(&column(11)?).unwrap_or_default();
+        cards
+            .entry(column(0)?)
+            .or_default()
+            .push((column(1)?, card));
+    }
+
+    let mut notes: BTreeMap<i64, Vec<Note>> = BTreeMap::new();
+    let mut statement = conn
+        .prepare("SELECT id, guid, mid, mod, tags, flds FROM notes ORDER BY id")
+        .map_err(database_error)?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })
+        .map_err(database_error)?;
+    for row in rows {
+        let (note_id, guid, model_id, modified, tags, fields) = row.map_err(database_error)?;
+        let model = models.get(&model_id).cloned().ok_or_else(|| {
+            Error::InvalidPackage(format!("note {} uses unknown model {}", note_id, model_id))
+        })?;
+        let note_cards = cards.remove(&note_id).unwrap_or_default();
+        // A note belongs to the deck its first card is in
+        let deck_id = note_cards
+            .first()
+            .map(|(deck_id, _)| *deck_id)
+            .unwrap_or(DEFAULT_DECK_ID);
+        let note = Note::from_db(
+            model,
+            fields.split('\x1f').map(str::to_string).collect(),
+            tags.split_whitespace().map(str::to_string).collect(),
+            note_id,
+            guid,
+            modified,
+            note_cards
+                .into_iter()
+                .map(|(card_deck_id, mut card)| {
+                    if card_deck_id != deck_id {
+                        card.deck_id = Some(card_deck_id);
+                    }
+                    card
+                })
+                .collect(),
+        );
+        notes.entry(deck_id).or_default().push(note);
+    }
+
+    let mut decks = vec![];
+    for (id, entry) in &deck_entries {
+        let deck_notes = notes.remove(id).unwrap_or_default();
+        if *id == DEFAULT_DECK_ID && deck_notes.is_empty() {
+            continue;
+        }
+        let mut deck = Deck::new(*id, &entry.name, &entry.desc);
+        if let Some(config) = configs.get(&entry.conf) {
+            deck = deck.with_config(config.clone());
+        }
+        deck_notes.into_iter().for_each(|note| deck.add_note(note));
+        decks.push(deck);
+    }
+    // Cards pointing to decks that are not in the collection end up in a deck of their own
+    for (id, deck_notes) in notes {
+        let mut deck = Deck::new(id, &id.to_string(), "");
+        deck_notes.into_iter().for_each(|note| deck.add_note(note));
+        decks.push(deck);
+    }
+    Ok(decks)
+}
+
+/// Reads the notetypes of a schema 18 collection into the legacy JSON representation
+fn read_notetypes(conn: &Connection) -> Result<Vec<ModelDbEntry>, Error> {
+    let mut fields: HashMap<i64, Vec<Fld>> = HashMap::new();
+    let mut statement = conn
+        .prepare("SELECT ntid, ord, name, config FROM fields ORDER BY ntid, ord")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let config: Vec<u8> = row.get(3).map_err(database_error)?;
+        let config = DecodedMessage::decode(&config)?;
+        fields
+            .entry(row.get(0).map_err(database_error)?)
+            .or_default()
+            .push(Fld {
+                name: row.get(2).map_err(database_error)?,
+                media: vec![],
+                sticky: config.bool(1),
+                rtl: config.bool(2),
+                ord: row.get(1).map_err(database_error)?,
+                font: config.string(3),
+                size: config.int(4),
+            });
+    }
+
+    let mut templates: HashMap<i64, Vec<Tmpl>> = HashMap::new();
+    let mut statement = conn
+        .prepare("SELECT ntid, ord, name, config FROM templates ORDER BY ntid, ord")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let config: Vec<u8> = row.get(3).map_err(database_error)?;
+        let config = DecodedMessage::decode(&config)?;
+        let did = config.int(5);
+        templates
+            .entry(row.get(0).map_err(database_error)?)
+            .or_default()
+            .push(Tmpl {
+                name: row.get(2).map_err(database_error)?,
+                qfmt: config.string(1),
+                did: if did > 0 { Some(did as usize) } else { None },
+                bafmt: config.string(4),
+                afmt: config.string(2),
+                ord: row.get(1).map_err(database_error)?,
+                bqfmt: config.string(3),
+            });
+    }
+
+    let mut entries = vec![];
+    let mut statement = conn
+        .prepare("SELECT id, name, mtime_secs, usn, config FROM notetypes")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let id: i64 = row.get(0).map_err(database_error)?;
+        let config: Vec<u8> = row.get(4).map_err(database_error)?;
+        let config = DecodedMessage::decode(&config)?;
+        let req = config
+            .messages(8)?
+            .iter()
+            .map(|req| {
+                let any_or_all = match req.uint(2) {
+                    1 => "any",
+                    2 => "all",
+                    _ => "none",
+                };
+                Ok((
+                    req.uint(1) as usize,
+                    any_or_all.to_string(),
+                    req.uints(3)?.into_iter().map(|ord| ord as usize).collect(),
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        entries.push(ModelDbEntry {
+            vers: vec![],
+            name: row.get(1).map_err(database_error)?,
+            tags: vec![],
+            did: None,
+            usn: row.get(3).map_err(database_error)?,
+            req,
+            flds: fields.remove(&id).unwrap_or_default(),
+            sortf: config.int(2),
+            tmpls: templates.remove(&id).unwrap_or_default(),
+            model_db_entry_mod: row.get(2).map_err(database_error)?,
+            latex_post: config.string(6),
+            model_db_entry_type: config.int(1),
+            id: id.to_string(),
+            css: config.string(3),
+            latex_pre: config.string(5),
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads the decks of a schema 18 collection into the legacy JSON representation
+fn read_deck_rows(conn: &Connection) -> Result<Vec<DeckDbEntry>, Error> {
+    let mut entries = vec![];
+    let mut statement = conn
+        .prepare("SELECT id, name, mtime_secs, usn, kind FROM decks")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let name: String = row.get(1).map_err(database_error)?;
+        let kind: Vec<u8> = row.get(4).map_err(database_error)?;
+        let kind = DecodedMessage::decode(&kind)?;
+        // Filtered decks store their settings in field 2 and have no description
+        let normal = kind.message(1)?;
+        entries.push(DeckDbEntry {
+            conf: normal.int(1),
+            desc: normal.string(4),
+            deck_db_entry_dyn: if kind.bytes(2).is_empty() { 0 } else { 1 },
+            extend_new: normal.int(2),
+            extend_rev: normal.int(3),
+            id: row.get(0).map_err(database_error)?,
+            deck_db_entry_mod: row.get(2).map_err(database_error)?,
+            name: name.replace('\x1f', "::"),
+            usn: row.get(3).map_err(database_error)?,
+            ..Default::default()
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads the deck options of a schema 18 collection into the legacy JSON representation.
+///
+/// This is the inverse of `Schema::add_deck_config`.
+fn read_deck_config_rows(conn: &Connection) -> Result<Vec<DeckConfigDbEntry>, Error> {
+    let mut entries = vec![];
+    let mut statement = conn
+        .prepare("SELECT id, name, mtime_secs, usn, config FROM deck_config")
+        .map_err(database_error)?;
+    let mut rows = statement.query([]).map_err(database_error)?;
+    while let Some(row) = rows.next().map_err(database_error)? {
+        let config: Vec<u8> = row.get(4).map_err(database_error)?;
+        let config = DecodedMessage::decode(&config)?;
+        let steps = |field| -> Result<Vec<f64>, Error> {
+            Ok(config.floats(field)?.into_iter().map(round_float).collect())
+        };
+        let defaults = DeckConfigDbEntry::default();
+        entries.push(DeckConfigDbEntry {
+            autoplay: !config.bool(23),
+            id: row.get(0).map_err(database_error)?,
+            lapse: LapseConfigDbEntry {
+                delays: steps(2)?,
+                leech_action: config.int(21),
+                leech_fails: config.int(22),
+                min_int: config.int(17),
+                mult: round_float(config.float(14)),
+            },
+            max_taken: config.int(24),
+            deck_config_db_entry_mod: row.get(2).map_err(database_error)?,
+            name: row.get(1).map_err(database_error)?,
+            new: NewConfigDbEntry {
+                bury: config.bool(27),
+                delays: steps(1)?,
+                initial_factor: (config.float(11) as f64 * 1000.0).round() as i64,
+                ints: vec![config.int(18), config.int(19), 7],
+                order: if config.uint(20) == 1 { 0 } else { 1 },
+                per_day: config.int(9),
+                ..defaults.new
+            },
+            replayq: !config.bool(26),
+            rev: RevConfigDbEntry {
+                bury: config.bool(28),
+                ease4: round_float(config.float(12)),
+                ivl_fct: round_float(config.float(15)),
+                max_ivl: config.int(16),
+                per_day: config.int(10),
+                ..defaults.rev
+            },
+            timer: config.bool(25) as i64,
+            usn: row.get(3).map_err(database_error)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Converts a protobuf `f32` back into the `f64` it was written from, e.g. `1.3` instead of
+/// `1.2999999523162842`
+fn round_float(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(value as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basic_model, cloze_model, CardState, CardType, Deck, DeckConfig, Ease, Flag, LeechAction,
+        MediaFile, Note, Package, PackageFormat, ReviewKind, ReviewLogEntry,
+    };
+    use std::io::{Cursor, Write};
+    use std::time::Duration;
+    use tempfile::NamedTempFile;
+
+    fn round_trip(format: PackageFormat) {
+        let review = ReviewLogEntry::new(1_599_000_000.5, Ease::Hard, ReviewKind::Relearning)
+            .interval(12)
+            .last_interval(-600)
+            .factor(2.1)
+            .time_taken(Duration::from_millis(4500));
+        let mut deck = Deck::new(1234, "Capitals", "Capitals of the world");
+        deck.add_note(
+            Note::new(basic_model(), vec!["Capital of France", "Paris"])
+                .unwrap()
+                .tags(["europe", "geography"]),
+        );
+        deck.add_note(
+            Note::new(
+                cloze_model(),
+                vec!["{{c1::Rome}} is the capital of {{c2::Italy}}"],
+            )
+            .unwrap()
+            .guid("custom-guid")
+            .modified(1_500_000_000)
+            .with_card_state(1, CardState::review(1_600_000_000, 12, 2.1).reps(3))
+            .unwrap()
+            .with_review(1, review.clone())
+            .unwrap()
+            .suspend_card(1)
+            .unwrap()
+            .card_deck(1, 5678)
+            .unwrap()
+            .bury_card(0)
+            .unwrap()
+            .flag_card(0, Flag::Purple)
+            .unwrap(),
+        );
+        let config = DeckConfig::new(1_500_000_000, "Course")
+            .new_steps(vec![1.0, 10.0, 60.0])
+            .new_per_day(42)
+            .easy_bonus(1.5)
+            .lapse_new_interval(0.3)
+            .leech_action(LeechAction::TagOnly)
+            .autoplay(false);
+        let other_deck = Deck::new(5678, "Empty", "").with_config(config.clone());
+
+        let mut media = NamedTempFile::new().unwrap();
+        media.write_all(b"not really an mp3").unwrap();
+        let media_path = media.into_temp_path();
+
+        let mut buffer = Cursor::new(vec![]);
+        let mut package = Package::new(vec![deck, other_deck], vec![media_path.to_str().unwrap()])
+            .unwrap()
+            .format(format);
+        package
+            .add_media_file(MediaFile::from_bytes("generated.png", b"in memory".to_vec()).unwrap());
+        package
+            .write_timestamp(&mut buffer, 1_600_000_000.0)
+            .unwrap();
+        buffer.set_position(0);
+
+        let package = Package::read(buffer).unwrap();
+        let decks = package.decks();
+        assert_eq!(decks.len(), 2);
+        assert_eq!(decks[0].id(), 1234);
+        assert_eq!(decks[0].name(), "Capitals");
+        assert_eq!(decks[0].description(), "Capitals of the world");
+        assert_eq!(decks[1].name(), "Empty");
+        assert!(decks[1].notes().is_empty());
+        assert_eq!(decks[0].config().unwrap().id(), 1);
+        assert_eq!(decks[1].config(), Some(&config));
+
+        let notes = decks[0].notes();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].fields(), vec!["Capital of France", "Paris"]);
+        assert_eq!(notes[0].get_tags(), vec!["europe", "geography"]);
+        let model = notes[0].model();
+        assert_eq!(model.id, basic_model().id);
+        assert_eq!(model.fields()[1].name, "Back");
+        assert_eq!(model.fields()[1].font, "Arial");
+        assert_eq!(model.templates()[0].qfmt, "{{Front}}");
+        assert_eq!(model.req().unwrap(), basic_model().req().unwrap());
+        assert!(notes[1].model().get_model_type() == crate::ModelType::Cloze);
+        assert_eq!(notes[1].get_guid(), "custom-guid");
+        assert_eq!(notes[0].get_modified(), Some(1_600_000_000));
+        assert_eq!(notes[1].get_modified(), Some(1_500_000_000));
+        assert!(notes[0].get_id().is_some());
+        let mut ords = notes[1]
+            .cards()
+            .iter()
+            .map(|card| card.ord())
+            .collect::<Vec<_>>();
+        ords.sort_unstable();
+        assert_eq!(ords, vec![0, 1]);
+        for card in notes[1].cards() {
+            let state = card.state();
+            if card.ord() == 1 {
+                assert_eq!(state.card_type(), CardType::Review);
+                // Review cards are due on a day, so the time of day is lost
+                assert_eq!(state.due(), 1_599_994_800);
+                assert_eq!(state.interval(), 12);
+                assert_eq!(state.ease(), 2.1);
+                assert_eq!(state.get_reps(), 3);
+                assert_eq!(card.reviews(), vec![review.clone()]);
+                assert!(card.is_suspended());
+                assert_eq!(card.deck_id(), Some(5678));
+                assert_eq!(card.flag(), None);
+            } else {
+                assert_eq!(state, &CardState::new());
+                assert!(card.reviews().is_empty());
+                assert!(card.is_buried());
+                assert!(!card.is_suspended());
+                assert_eq!(card.deck_id(), None);
+                assert_eq!(card.flag(), Some(Flag::Purple));
+            }
+        }
+
+        let media_files = package.media_files();
+        assert_eq!(media_files.len(), 2);
+        assert_eq!(
+            Some(media_files[0].name()),
+            media_path.file_name().and_then(|name| name.to_str())
+        );
+        assert_eq!(media_files[0].data().unwrap(), b"not really an mp3");
+        assert_eq!(media_files[1].name(), "generated.png");
+        assert_eq!(media_files[1].data().unwrap(), b"in memory");
+    }
+
+    #[test]
+    fn round_trip_legacy() {
+        round_trip(PackageFormat::Legacy);
+    }
+
+    #[test]
+    fn round_trip_anki21() {
+        round_trip(PackageFormat::Anki21);
+    }
+
+    #[test]
+    fn round_trip_anki21b() {
+        round_trip(PackageFormat::Anki21b);
+    }
+
+    #[test]
+    fn nested_decks() {
+        let mut section = Deck::new(3, "Section 1", "");
+        section.add_note(Note::new(basic_model(), vec!["Question", "Answer"]).unwrap());
+        let mut chapter = Deck::new(2, "Chapter 1", "");
+        chapter.add_subdeck(section).unwrap();
+        let mut textbook = Deck::new(10, "Library::Textbook", "");
+        textbook.add_subdeck(chapter).unwrap();
+
+        for format in [PackageFormat::Legacy, PackageFormat::Anki21b] {
+            let mut buffer = Cursor::new(vec![]);
+            Package::new(vec![textbook.clone()], vec![])
+                .unwrap()
+                .format(format)
+                .write(&mut buffer)
+                .unwrap();
+            buffer.set_position(0);
+
+            let package = Package::read(buffer).unwrap();
+            let mut names = package
+                .decks()
+                .iter()
+                .map(|deck| deck.name())
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            assert_eq!(
+                names,
+                vec![
+                    "Library",
+                    "Library::Textbook",
+                    "Library::Textbook::Chapter 1",
+                    "Library::Textbook::Chapter 1::Section 1",
+                ]
+            );
+            let section = package
+                .deck("Library::Textbook::Chapter 1::Section 1")
+                .unwrap();
+            assert_eq!(section.id(), 3);
+            assert_eq!(section.notes().len(), 1);
+        }
+    }
+
+    #[test]
+    fn missing_collection() {
+        let mut buffer = Cursor::new(vec![]);
+        let mut zip = zip::ZipWriter::new(&mut buffer);
+        zip.start_file("media", Default::default()).unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+        drop(zip);
+        buffer.set_position(0);
+        assert!(matches!(
+            Package::read(buffer),
+            Err(crate::Error::InvalidPackage(_))
+        ));
+    }
+}
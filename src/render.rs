@@ -0,0 +1,773 @@
+//! Renders the cards of a note into HTML with Anki's template language, to preview them without
+//! importing them into Anki.
+//!
+//! Supported are field replacements (`{{Field}}`), conditionals (`{{#Field}}`, `{{^Field}}`),
+//! the special fields `FrontSide`, `Tags`, `Type`, `Deck`, `Subdeck`, `Card` and `CardFlag` and
+//! the filters `cloze`, `cloze-only`, `hint`, `type`, `text`, `furigana`, `kana` and `kanji`.
+//! Unknown filters leave the text unchanged. Image occlusions are rendered into the elements the
+//! script of the Image Occlusion templates draws its masks from.
+
+use crate::card::Card;
+use crate::cloze;
+use crate::db_entries::{Fld, Tmpl};
+use crate::model::ModelType;
+use crate::note::Note;
+use crate::Error;
+use fancy_regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The question and answer side of a card rendered into HTML
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderedCard {
+    ord: i64,
+    question: String,
+    answer: String,
+    css: String,
+}
+
+impl RenderedCard {
+    /// Returns the ordinal of the template the card was generated from
+    pub fn ord(&self) -> i64 {
+        self.ord
+    }
+
+    /// Returns the HTML of the question side
+    pub fn question(&self) -> &str {
+        &self.question
+    }
+
+    /// Returns the HTML of the answer side
+    pub fn answer(&self) -> &str {
+        &self.answer
+    }
+
+    /// Returns the CSS of the model, which styles both sides
+    pub fn css(&self) -> &str {
+        &self.css
+    }
+}
+
+/// Renders all cards of `note` as if it was in the deck called `deck_name`
+pub(crate) fn render_note(note: &Note, deck_name: &str) -> Result<Vec<RenderedCard>, Error> {
+    let model = note.model();
+    let fields = model.fields();
+    let templates = model.templates();
+    let mut rendered = vec![];
+    for card in note.cards() {
+        let template = match model.get_model_type() {
+            ModelType::Cloze => templates.first(),
+            ModelType::FrontBack => templates.get(card.ord() as usize),
+        }
+        .ok_or_else(|| {
+            Error::TemplateRender(format!("the model has no template for card {}", card.ord()))
+        })?;
+        let context = Context::new(note, &fields, template, card, deck_name, model.get_name());
+        let question_nodes = parse(&template.qfmt)?;
+        let answer_nodes = parse(&template.afmt)?;
+        let question = context.render(&question_nodes, Side::Question)?;
+        let front_side = context.render(&question_nodes, Side::QuestionOnAnswer)?;
+        let answer = Context {
+            front_side: Some(front_side),
+            ..context
+        }
+        .render(&answer_nodes, Side::Answer)?;
+        rendered.push(RenderedCard {
+            ord: card.ord(),
+            question,
+            answer,
+            css: model.get_css().to_string(),
+        });
+    }
+    Ok(rendered)
+}
+
+#[derive(Debug, PartialEq)]
+enum Node<'a> {
+    Text(&'a str),
+    Replacement {
+        key: &'a str,
+        filters: Vec<&'a str>,
+    },
+    Conditional {
+        key: &'a str,
+        negated: bool,
+        children: Vec<Node<'a>>,
+    },
+}
+
+/// Parses a template into a tree of nodes, checking that all conditionals are closed
+fn parse(template: &str) -> Result<Vec<Node<'_>>, Error> {
+    // Each open conditional with its key, whether it is negated and the nodes it contains
+    let mut stack: Vec<(&str, bool, Vec<Node>)> = vec![("", false, vec![])];
+    let mut rest = template;
+    while !rest.is_empty() {
+        let (text, tag) = match rest.find("{{") {
+            Some(start) => {
+                let end = rest[start..].find("}}").ok_or_else(|| {
+                    Error::TemplateRender(format!("unclosed tag in {:?}", &rest[start..]))
+                })? + start;
+                let tag = &rest[start + 2..end];
+                let text = &rest[..start];
+                rest = &rest[end + 2..];
+                (text, Some(tag.trim()))
+            }
+            None => {
+                let text = rest;
+                rest = "";
+                (text, None)
+            }
+        };
+        let nodes = &mut stack.last_mut().expect("Should always have a root").2;
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        let tag = match tag {
+            Some(tag) => tag,
+            None => continue,
+        };
+        if let Some(key) = tag.strip_prefix('#') {
+            stack.push((key.trim(), false, vec![]));
+        } else if let Some(key) = tag.strip_prefix('^') {
+            stack.push((key.trim(), true, vec![]));
+        } else if let Some(key) = tag.strip_prefix('/') {
+            let key = key.trim();
+            if stack.len() == 1 || stack.last().map(|(open, _, _)| *open) != Some(key) {
+                return Err(Error::TemplateRender(format!(
+                    "found {{{{/{}}}}} without a matching {{{{#{}}}}}",
+                    key, key
+                )));
+            }
+            let (key, negated, children) = stack.pop().expect("Checked above");
+            stack
+                .last_mut()
+                .expect("Checked above")
+                .2
+                .push(Node::Conditional {
+                    key,
+                    negated,
+                    children,
+                });
+        } else if tag.starts_with('!') {
+            // A comment
+        } else {
+            let mut parts = tag.split(':').map(str::trim).collect::<Vec<_>>();
+            let key = parts.pop().expect("split returns at least one part");
+            nodes.push(Node::Replacement {
+                key,
+                filters: parts,
+            });
+        }
+    }
+    if stack.len() > 1 {
+        let (key, _, _) = stack.last().expect("Checked above");
+        return Err(Error::TemplateRender(format!(
+            "missing {{{{/{}}}}} for {{{{#{}}}}}",
+            key, key
+        )));
+    }
+    Ok(stack.pop().expect("Should always have a root").2)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Question,
+    /// The question as it is shown on the answer side, which has no input for typed answers
+    QuestionOnAnswer,
+    Answer,
+}
+
+struct Context<'a> {
+    fields: HashMap<&'a str, (&'a str, &'a Fld)>,
+    special_fields: HashMap<&'static str, String>,
+    front_side: Option<String>,
+    card_ord: i64,
+}
+
+impl<'a> Context<'a> {
+    fn new(
+        note: &'a Note,
+        fields: &'a [Fld],
+        template: &'a Tmpl,
+        card: &'a Card,
+        deck_name: &str,
+        model_name: &str,
+    ) -> Self {
+        let fields = fields
+            .iter()
+            .zip(note.fields())
+            .map(|(field, value)| (field.name.as_str(), (value.as_str(), field)))
+            .collect();
+        let mut special_fields = HashMap::new();
+        special_fields.insert("Tags", note.get_tags().join(" "));
+        special_fields.insert("Type", model_name.to_string());
+        special_fields.insert("Deck", deck_name.to_string());
+        special_fields.insert(
+            "Subdeck",
+            deck_name.rsplit("::").next().unwrap_or("").to_string(),
+        );
+        special_fields.insert("Card", template.name.clone());
+        special_fields.insert(
+            "CardFlag",
+            card.flag()
+                .map(|flag| format!("flag{}", flag as i64))
+                .unwrap_or_default(),
+        );
+        Self {
+            fields,
+            special_fields,
+            front_side: None,
+            card_ord: card.ord(),
+        }
+    }
+
+    fn render(&self, nodes: &[Node], side: Side) -> Result<String, Error> {
+        let mut out = String::new();
+        self.render_into(nodes, side, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_into(&self, nodes: &[Node], side: Side, out: &mut String) -> Result<(), Error> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Replacement { key, filters } => {
+                    out.push_str(&self.replace(key, filters, side)?);
+                }
+                Node::Conditional {
+                    key,
+                    negated,
+                    children,
+                } => {
+                    if self.is_nonempty(key)? != *negated {
+                        self.render_into(children, side, out)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_nonempty(&self, key: &str) -> Result<bool, Error> {
+        if let Some((value, _)) = self.fields.get(key) {
+            return Ok(!field_is_empty(value));
+        }
+        if let Some(value) = self.special_fields.get(key) {
+            return Ok(!value.is_empty());
+        }
+        // `{{#c2}}` is true when the card of the second cloze deletion is rendered
+        if let Some(Ok(number)) = key.strip_prefix('c').map(str::parse::<i64>) {
+            return Ok(number == self.card_ord + 1);
+        }
+        Err(unknown_field(key))
+    }
+
+    fn replace(&self, key: &str, filters: &[&str], side: Side) -> Result<String, Error> {
+        if key == "FrontSide" {
+            return Ok(self.front_side.clone().unwrap_or_default());
+        }
+        let (mut text, field) = if let Some((value, field)) = self.fields.get(key) {
+            (value.to_string(), Some(*field))
+        } else if let Some(value) = self.special_fields.get(key) {
+            (value.clone(), None)
+        } else {
+            return Err(unknown_field(key));
+        };
+        // Filters are applied from the one next to the field name to the first one
+        for (idx, filter) in filters.iter().enumerate().rev() {
+            text = match *filter {
+                "type" => return Ok(self.type_answer(&text, &filters[idx + 1..], field, side)),
+                "cloze" => reveal_clozes(&text, self.card_ord + 1, side == Side::Answer),
+                "cloze-only" => cloze_only(&text, self.card_ord + 1, side == Side::Answer),
+                "hint" => hint(&text, key),
+                "text" => strip_html(&text),
+                "furigana" => furigana(&text),
+                "kana" => ruby(&text, "$2"),
+                "kanji" => ruby(&text, "$1"),
+                _ => text,
+            };
+        }
+        Ok(text)
+    }
+
+    /// Renders the input box for a typed answer on the question and the expected answer on the
+    /// answer side. `filters` are the filters between `type` and the field name.
+    fn type_answer(&self, text: &str, filters: &[&str], field: Option<&Fld>, side: Side) -> String {
+        let expected = if filters.contains(&"cloze") {
+            cloze_only(text, self.card_ord + 1, true)
+        } else {
+            text.to_string()
+        };
+        match side {
+            Side::QuestionOnAnswer => String::new(),
+            Side::Question => {
+                let style = field
+                    .map(|field| {
+                        format!(
+                            " style=\"font-family: '{}'; font-size: {}px;\"",
+                            field.font, field.size
+                        )
+                    })
+                    .unwrap_or_default();
+                format!(
+                    "<center><input id=\"typeans\" type=\"text\"{}></center>",
+                    style
+                )
+            }
+            Side::Answer => format!(
+                "<center><code id=\"typeans\">{}</code></center>",
+                strip_html(&expected)
+            ),
+        }
+    }
+}
+
+/// Names Anki fills in for every card, in addition to the fields of the note
+const SPECIAL_FIELDS: &[&str] = &[
+    "FrontSide",
+    "Tags",
+    "Type",
+    "Deck",
+    "Subdeck",
+    "Card",
+    "CardFlag",
+];
+
+/// Returns the names of the note fields `template` refers to, leaving out special fields like
+/// `{{Tags}}` and cloze conditionals like `{{#c1}}`
+pub(crate) fn referenced_fields(template: &str) -> Result<Vec<&str>, Error> {
+    fn collect<'a>(nodes: &[Node<'a>], keys: &mut Vec<&'a str>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Replacement { key, .. } => keys.push(key),
+                Node::Conditional { key, children, .. } => {
+                    let is_cloze = key
+                        .strip_prefix('c')
+                        .is_some_and(|number| number.parse::<i64>().is_ok());
+                    if !is_cloze {
+                        keys.push(key);
+                    }
+                    collect(children, keys);
+                }
+            }
+        }
+    }
+    let mut keys = vec![];
+    collect(&parse(template)?, &mut keys);
+    keys.retain(|key| !key.is_empty() && !SPECIAL_FIELDS.contains(key));
+    Ok(keys)
+}
+
+/// Returns the names of the fields `template` takes cloze deletions from, i.e. the fields with
+/// a `cloze` filter like `{{cloze:Text}}` or `{{type:cloze:Text}}`
+pub(crate) fn cloze_fields(template: &str) -> Result<Vec<&str>, Error> {
+    fn collect<'a>(nodes: &[Node<'a>], keys: &mut Vec<&'a str>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Replacement { key, filters } => {
+                    if filters.contains(&"cloze") {
+                        keys.push(key);
+                    }
+                }
+                Node::Conditional { children, .. } => collect(children, keys),
+            }
+        }
+    }
+    let mut keys = vec![];
+    collect(&parse(template)?, &mut keys);
+    Ok(keys)
+}
+
+fn unknown_field(key: &str) -> Error {
+    Error::TemplateRender(format!("there is no field called {:?}", key))
+}
+
+/// A field counts as empty if it only contains whitespace and line breaks
+fn field_is_empty(text: &str) -> bool {
+    static EMPTY: OnceLock<Regex> = OnceLock::new();
+    let regex = cached_regex(&EMPTY, r"(?i)^(?:\s|<br\s*/?>|</?div>|&nbsp;)*$");
+    regex.is_match(text).unwrap_or(false)
+}
+
+/// Replaces the cloze deletions of `text` as they are shown on the card of cloze number
+/// `active`. Cards of other numbers show their text. If `text` has no deletion with the active
+/// number, it is not shown on this card at all.
+fn reveal_clozes(text: &str, active: i64, answer: bool) -> String {
+    fn reveal(node: &cloze::Node, active: i64, answer: bool, found: &mut bool, out: &mut String) {
+        let cloze = match node {
+            cloze::Node::Text(text) => return out.push_str(text),
+            cloze::Node::Cloze(cloze) => cloze,
+        };
+        let is_active = i64::from(cloze.ordinal()) == active;
+        *found |= is_active;
+        if let Some(shape) = cloze.text().strip_prefix("image-occlusion:") {
+            return out.push_str(&occlusion_mask(shape, cloze.ordinal(), is_active, answer));
+        }
+        if is_active && !answer {
+            out.push_str(&format!(
+                "<span class=\"cloze\" data-cloze=\"{}\" data-ordinal=\"{}\">[{}]</span>",
+                escape_attribute(&cloze.text()),
+                cloze.ordinal(),
+                cloze.hint().unwrap_or("...")
+            ));
+            return;
+        }
+        let class = if is_active { "cloze" } else { "cloze-inactive" };
+        out.push_str(&format!(
+            "<span class=\"{}\" data-ordinal=\"{}\">",
+            class,
+            cloze.ordinal()
+        ));
+        for node in cloze.nodes() {
+            reveal(node, active, answer, found, out);
+        }
+        out.push_str("</span>");
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut found = false;
+    for node in &cloze::parse(text).nodes {
+        reveal(node, active, answer, &mut found, &mut out);
+    }
+    if found {
+        out
+    } else {
+        String::new()
+    }
+}
+
+/// Turns the shape of an image occlusion like `rect:left=.1:top=.2` into the element the script
+/// of the Image Occlusion templates draws a mask for. Shapes of the active deletion are hidden on
+/// the question and highlighted on the answer side, `c0` shapes are always hidden.
+fn occlusion_mask(shape: &str, ordinal: u16, active: bool, answer: bool) -> String {
+    let class = if ordinal == 0 || (active && !answer) {
+        "cloze"
+    } else if active {
+        "cloze-highlight"
+    } else {
+        "cloze-inactive"
+    };
+    let mut properties = shape.split(':');
+    let mut data = String::new();
+    if let Some(kind) = properties.next().filter(|kind| !kind.is_empty()) {
+        data.push_str(&format!(" data-shape=\"{}\"", escape_attribute(kind)));
+    }
+    for (key, value) in properties.filter_map(|property| property.split_once('=')) {
+        data.push_str(&format!(
+            " data-{}=\"{}\"",
+            escape_attribute(key),
+            escape_attribute(value)
+        ));
+    }
+    format!(
+        "<div class=\"{}\" data-ordinal=\"{}\"{}></div>",
+        class, ordinal, data
+    )
+}
+
+/// Returns the texts of all cloze deletions with number `active`, or their hints on the question
+/// side, separated by commas
+fn cloze_only(text: &str, active: i64, answer: bool) -> String {
+    fn collect(nodes: &[cloze::Node], active: i64, answer: bool, out: &mut Vec<String>) {
+        for node in nodes {
+            if let cloze::Node::Cloze(cloze) = node {
+                if i64::from(cloze.ordinal()) == active {
+                    out.push(if answer {
+                        cloze.text()
+                    } else {
+                        cloze.hint().unwrap_or("...").to_string()
+                    });
+                }
+                collect(cloze.nodes(), active, answer, out);
+            }
+        }
+    }
+    let mut out = vec![];
+    collect(&cloze::parse(text).nodes, active, answer, &mut out);
+    out.join(", ")
+}
+
+fn hint(text: &str, field_name: &str) -> String {
+    if field_is_empty(text) {
+        return String::new();
+    }
+    let id = format!(
+        "hint{}",
+        field_name
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+    );
+    format!(
+        "<a class=hint href=\"#\" onclick=\"this.style.display='none';\
+         document.getElementById('{id}').style.display='block';return false;\">{name}</a>\
+         <div id=\"{id}\" class=hint style=\"display: none\">{text}</div>",
+        id = id,
+        name = field_name,
+        text = text
+    )
+}
+
+/// Removes all HTML tags and comments and decodes common entities
+pub(crate) fn strip_html(text: &str) -> String {
+    static BLOCKS: OnceLock<Regex> = OnceLock::new();
+    static TAGS: OnceLock<Regex> = OnceLock::new();
+    if !text.contains(&['<', '&'][..]) {
+        return text.to_string();
+    }
+    let blocks = cached_regex(&BLOCKS, r"(?is)<!--.*?-->|<(style|script)[^>]*>.*?</\1>");
+    let tags = cached_regex(&TAGS, r"(?s)<[^>]*>");
+    let text = replace_all(blocks, text, |_| String::new());
+    let text = replace_all(tags, &text, |_| String::new());
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Like `strip_html`, but keeps the names of images and other media as text, like Anki does
+/// for the sort field and checksum of notes
+pub(crate) fn strip_html_preserving_media_filenames(text: &str) -> String {
+    static MEDIA_TAGS: OnceLock<Regex> = OnceLock::new();
+    if !text.contains('<') {
+        return strip_html(text);
+    }
+    let media_tags = cached_regex(
+        &MEDIA_TAGS,
+        r#"(?si)<\b(?:img|audio|video|object)\b[^>]+\b(?:src|data)\b=(?:"([^"]+?)"[^>]*>|'([^']+?)'[^>]*>|([^ >]+?)(?: [^>]*>|>))"#,
+    );
+    let text = expand_all(media_tags, text, " ${1}${2}${3} ");
+    strip_html(&text)
+}
+
+/// Compiles `pattern` the first time it is used. Used by functions that run for every note.
+fn cached_regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("static regex"))
+}
+
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn furigana_regex() -> &'static Regex {
+    static FURIGANA: OnceLock<Regex> = OnceLock::new();
+    cached_regex(&FURIGANA, r" ?([^ >]+?)\[(.+?)\]")
+}
+
+/// Turns `漢字[かんじ]` into ruby annotations
+fn furigana(text: &str) -> String {
+    expand_all(
+        furigana_regex(),
+        text,
+        "<ruby><rb>$1</rb><rt>$2</rt></ruby>",
+    )
+}
+
+/// Keeps only the base text (`$1`) or only the reading (`$2`) of `漢字[かんじ]`
+fn ruby(text: &str, replacement: &str) -> String {
+    expand_all(furigana_regex(), text, replacement)
+}
+
+/// Replaces every match of `regex` in `text` with the result of `replacer`
+fn replace_all(regex: &Regex, text: &str, mut replacer: impl FnMut(&Captures) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in regex.captures_iter(text).filter_map(Result::ok) {
+        let whole = caps.get(0).expect("group 0 is the whole match");
+        out.push_str(&text[last..whole.start()]);
+        out.push_str(&replacer(&caps));
+        last = whole.end();
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Replaces every match of `regex` in `text` with `replacement`, in which `$1` refers to groups
+fn expand_all(regex: &Regex, text: &str, replacement: &str) -> String {
+    replace_all(regex, text, |caps| {
+        let mut out = String::new();
+        caps.expand(replacement, &mut out);
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, cloze_model, Field, Flag, Model, Template};
+
+    fn render(note: &Note) -> Vec<RenderedCard> {
+        render_note(note, "Languages::French").unwrap()
+    }
+
+    #[test]
+    fn basic_card() {
+        let note = Note::new(basic_model(), vec!["Capital of France", "Paris"]).unwrap();
+        let cards = render(&note);
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].question(), "Capital of France");
+        assert_eq!(
+            cards[0].answer(),
+            "Capital of France\n\n<hr id=answer>\n\nParis"
+        );
+        assert!(cards[0].css().contains(".card"));
+    }
+
+    #[test]
+    fn conditionals_and_special_fields() {
+        let model = Model::new(
+            1,
+            "Vocabulary",
+            vec![Field::new("Word"), Field::new("Example")],
+            vec![Template::new("Recognition")
+                .qfmt("{{Word}}{{#Example}} ({{ Example }}){{/Example}}{{^Example}}!{{/Example}}")
+                .afmt("{{Deck}}/{{Subdeck}}/{{Card}}/{{Type}}/{{Tags}}/{{CardFlag}}")],
+        );
+        let note = Note::new(model.clone(), vec!["chat", "<br>"])
+            .unwrap()
+            .tags(["animal", "noun"])
+            .flag_card(0, Flag::Blue)
+            .unwrap();
+        let cards = render(&note);
+        assert_eq!(cards[0].question(), "chat!");
+        assert_eq!(
+            cards[0].answer(),
+            "Languages::French/French/Recognition/Vocabulary/animal noun/flag4"
+        );
+
+        let note = Note::new(model, vec!["chat", "le chat"]).unwrap();
+        assert_eq!(render(&note)[0].question(), "chat (le chat)");
+    }
+
+    #[test]
+    fn cloze() {
+        let note = Note::new(
+            cloze_model(),
+            vec![
+                "{{c1::Paris}} is the capital of {{c2::France::country}}",
+                "",
+            ],
+        )
+        .unwrap();
+        let mut cards = render(&note);
+        cards.sort_by_key(RenderedCard::ord);
+        assert_eq!(
+            cards[0].question(),
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"1\">[...]</span> is the \
+             capital of <span class=\"cloze-inactive\" data-ordinal=\"2\">France</span>"
+        );
+        assert!(cards[1].question().contains(
+            "<span class=\"cloze\" data-cloze=\"France\" data-ordinal=\"2\">[country]</span>"
+        ));
+        assert!(cards[1]
+            .answer()
+            .contains("<span class=\"cloze\" data-ordinal=\"2\">France</span>"));
+    }
+
+    // Cases from the tests of Anki's cloze module
+    #[test]
+    fn anki_clozes() {
+        let text = "{{c2::te}}{{c1::s}}t{{";
+        assert_eq!(strip_html(&reveal_clozes(text, 1, false)), "te[...]t{{");
+        assert_eq!(strip_html(&reveal_clozes(text, 2, false)), "[...]st{{");
+        assert_eq!(strip_html(&reveal_clozes(text, 1, true)), "test{{");
+        assert_eq!(reveal_clozes(text, 3, true), "");
+
+        let text = "{{c1::foo {{c2::bar}} baz}}";
+        assert_eq!(strip_html(&reveal_clozes(text, 1, false)), "[...]");
+        assert_eq!(strip_html(&reveal_clozes(text, 2, false)), "foo [...] baz");
+        assert_eq!(strip_html(&reveal_clozes(text, 1, true)), "foo bar baz");
+
+        let text = "foo {{c1::bar {{c2::baz::qux}}::quux}}";
+        assert_eq!(strip_html(&reveal_clozes(text, 1, false)), "foo [quux]");
+        assert_eq!(strip_html(&reveal_clozes(text, 2, false)), "foo bar [qux]");
+
+        assert_eq!(
+            reveal_clozes("{{c2::te{{c1::s}}}}t{{", 1, false),
+            "<span class=\"cloze-inactive\" data-ordinal=\"2\">te\
+             <span class=\"cloze\" data-cloze=\"s\" data-ordinal=\"1\">[...]</span></span>t{{"
+        );
+        assert_eq!(
+            reveal_clozes("{{c1::a {{c2::b}} c}}", 1, true),
+            "<span class=\"cloze\" data-ordinal=\"1\">a \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">b</span> c</span>"
+        );
+    }
+
+    #[test]
+    fn cloze_only() {
+        let text = "{{c1::a}} {{c2::b}} {{c1::c::hint}}";
+        assert_eq!(super::cloze_only(text, 1, true), "a, c");
+        assert_eq!(super::cloze_only(text, 1, false), "..., hint");
+        assert_eq!(super::cloze_only(text, 3, true), "");
+    }
+
+    #[test]
+    fn filters() {
+        let model = Model::new(
+            1,
+            "Filters",
+            vec![
+                Field::new("Text").font("Arial").size(12),
+                Field::new("Extra"),
+            ],
+            vec![Template::new("Card 1")
+                .qfmt("{{text:Text}}|{{type:Text}}|{{hint:Extra}}|{{kanji:Extra}}")
+                .afmt("{{FrontSide}}|{{type:Text}}|{{furigana:Extra}}|{{kana:Extra}}")],
+        );
+        let note = Note::new(model, vec!["<b>bold</b> &amp; more", "日本[にほん]"]).unwrap();
+        let card = &render(&note)[0];
+        assert!(card.question().starts_with(
+            "bold & more|<center><input id=\"typeans\" type=\"text\" \
+             style=\"font-family: 'Arial'; font-size: 12px;\"></center>|<a class=hint"
+        ));
+        assert!(card.question().ends_with("|日本"));
+        assert!(card.answer().starts_with("bold & more||<a class=hint"));
+        assert!(card.answer().ends_with(
+            "|<center><code id=\"typeans\">bold & more</code></center>|\
+             <ruby><rb>日本</rb><rt>にほん</rt></ruby>|にほん"
+        ));
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(parse("{{#A}}text").is_err());
+        assert!(parse("{{#A}}text{{/B}}").is_err());
+        assert!(parse("text{{/A}}").is_err());
+        assert!(parse("{{A").is_err());
+        assert_eq!(
+            parse("a{{! comment }}{{#B}}{{text:C}}{{/B}}").unwrap(),
+            vec![
+                Node::Text("a"),
+                Node::Conditional {
+                    key: "B",
+                    negated: false,
+                    children: vec![Node::Replacement {
+                        key: "C",
+                        filters: vec!["text"]
+                    }]
+                }
+            ]
+        );
+
+        let model = Model::new(
+            1,
+            "Unknown field",
+            vec![Field::new("Front")],
+            vec![Template::new("Card 1").qfmt("{{Front}}{{Back}}").afmt("")],
+        );
+        // The card is generated because `Front` is not empty
+        let note = Note::new(model, vec!["front"]).unwrap();
+        assert!(matches!(
+            render_note(&note, "Default"),
+            Err(Error::TemplateRender(_))
+        ));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::error::database_error;
+use crate::Error;
+use rusqlite::{params, Transaction};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
+
+/// The answer button pressed in a review
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ease {
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+/// How a card was studied in a review
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewKind {
+    /// A review of a card in its initial learning steps
+    Learning,
+    /// A review of a graduated card
+    Review,
+    /// A review of a forgotten card in its relearning steps
+    Relearning,
+    /// A review in a filtered deck
+    Filtered,
+    /// The card was rescheduled by hand instead of being answered
+    Manual,
+}
+
+/// One entry of the review history of a card, as stored in the `revlog` table.
+///
+/// Entries are attached to cards with [`Note::with_review`](crate::Note::with_review), so that
+/// statistics and FSRS parameters can be computed from the imported history. Intervals are
+/// given like Anki stores them: positive values are days, negative values are seconds.
+///
+/// Example:
+///
+/// ```rust
+/// use genanki_rs::{Ease, ReviewKind, ReviewLogEntry};
+/// use std::time::Duration;
+///
+/// let entry = ReviewLogEntry::new(1609459200.0, Ease::Good, ReviewKind::Review)
+///     .interval(10)
+///     .last_interval(4)
+///     .factor(2.5)
+///     .time_taken(Duration::from_secs(7));
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewLogEntry {
+    timestamp: f64,
+    ease: Ease,
+    kind: ReviewKind,
+    #[serde(default)]
+    interval: i64,
+    #[serde(default)]
+    last_interval: i64,
+    #[serde(default)]
+    factor: f64,
+    /// Serialized as seconds
+    #[serde(
+        default,
+        serialize_with = "serialize_seconds",
+        deserialize_with = "deserialize_seconds"
+    )]
+    time_taken: Duration,
+}
+
+impl ReviewLogEntry {
+    /// Creates an entry for a review at `timestamp` (a Unix timestamp in seconds) in which `ease`
+    /// was pressed. Intervals, factor and time taken default to `0`.
+    pub fn new(timestamp: f64, ease: Ease, kind: ReviewKind) -> Self {
+        Self {
+            timestamp,
+            ease,
+            kind,
+            interval: 0,
+            last_interval: 0,
+            factor: 0.0,
+            time_taken: Duration::from_secs(0),
+        }
+    }
+
+    /// Sets the interval the card got after this review
+    pub fn interval(self, interval: i64) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Sets the interval the card had before this review
+    pub fn last_interval(self, last_interval: i64) -> Self {
+        Self {
+            last_interval,
+            ..self
+        }
+    }
+
+    /// Sets the ease factor of the card after this review, e.g. `2.5` for 250%
+    pub fn factor(self, factor: f64) -> Self {
+        Self { factor, ..self }
+    }
+
+    /// Sets how long it took to answer the card
+    pub fn time_taken(self, time_taken: Duration) -> Self {
+        Self { time_taken, ..self }
+    }
+
+    /// Returns the time of the review as a Unix timestamp in seconds
+    pub fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+
+    /// Returns the answer button pressed in the review
+    pub fn ease(&self) -> Ease {
+        self.ease
+    }
+
+    /// Returns how the card was studied
+    pub fn kind(&self) -> ReviewKind {
+        self.kind
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.timestamp < 0.0 {
+            return Err(Error::InvalidReviewLogEntry(
+                "reviews cannot happen before 1970".to_string(),
+            ));
+        }
+        if self.factor < 0.0 {
+            return Err(Error::InvalidReviewLogEntry(
+                "the factor of a review cannot be negative".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the entry for the card with `card_id`. The id of an entry is the time of the review
+    /// in milliseconds. If another review already has that id, e.g. because histories only
+    /// record the day of a review, the next free millisecond is used, like Anki does on import.
+    pub(crate) fn write_to_db(
+        &self,
+        transaction: &Transaction,
+        card_id: usize,
+    ) -> Result<(), Error> {
+        // Rescheduling a card by hand does not press a button
+        let ease = match (self.kind, self.ease) {
+            (ReviewKind::Manual, _) => 0,
+            (_, Ease::Again) => 1,
+            (_, Ease::Hard) => 2,
+            (_, Ease::Good) => 3,
+            (_, Ease::Easy) => 4,
+        };
+        let kind = match self.kind {
+            ReviewKind::Learning => 0,
+            ReviewKind::Review => 1,
+            ReviewKind::Relearning => 2,
+            ReviewKind::Filtered => 3,
+            ReviewKind::Manual => 4,
+        };
+        let mut id = (self.timestamp * 1000.0) as i64;
+        while transaction
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM revlog WHERE id = ?)",
+                params![id],
+                |row| row.get(0),
+            )
+            .map_err(database_error)?
+        {
+            id += 1;
+        }
+        transaction
+            .execute(
+                "INSERT INTO revlog VALUES(?,?,?,?,?,?,?,?,?);",
+                params![
+                    id,                                    // id
+                    card_id,                               // cid
+                    -1,                                    // usn
+                    ease,                                  // ease
+                    self.interval,                         // ivl
+                    self.last_interval,                    // lastIvl
+                    (self.factor * 1000.0).round() as i64, // factor
+                    self.time_taken.as_millis() as i64,    // time
+                    kind,                                  // type
+                ],
+            )
+            .map_err(database_error)?;
+        Ok(())
+    }
+
+    /// Rebuilds an entry from the columns of a row of the `revlog` table
+    pub(crate) fn from_columns(
+        id: i64,
+        ease: i64,
+        interval: i64,
+        last_interval: i64,
+        factor: i64,
+        time: i64,
+        kind: i64,
+    ) -> Self {
+        let kind = match kind {
+            0 => ReviewKind::Learning,
+            2 => ReviewKind::Relearning,
+            3 => ReviewKind::Filtered,
+            4 => ReviewKind::Manual,
+            _ => ReviewKind::Review,
+        };
+        let ease = match ease {
+            1 => Ease::Again,
+            2 => Ease::Hard,
+            4 => Ease::Easy,
+            _ => Ease::Good,
+        };
+        Self {
+            timestamp: id as f64 / 1000.0,
+            ease,
+            kind,
+            interval,
+            last_interval,
+            factor: factor as f64 / 1000.0,
+            time_taken: Duration::from_millis(time.max(0) as u64),
+        }
+    }
+}
+
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apkg_col::APKG_COL;
+    use crate::apkg_schema::APKG_SCHEMA;
+    use crate::deck::all_notes;
+    use crate::{basic_model, Deck, Note, Package};
+    use rusqlite::Connection;
+    use std::io::Cursor;
+
+    #[test]
+    fn manual_reviews_have_no_ease() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(APKG_SCHEMA).unwrap();
+        conn.execute_batch(APKG_COL).unwrap();
+        let transaction = conn.transaction().unwrap();
+        ReviewLogEntry::new(1_600_000_000.0, Ease::Again, ReviewKind::Manual)
+            .write_to_db(&transaction, 1)
+            .unwrap();
+        let (ease, kind): (i64, i64) = transaction
+            .query_row("SELECT ease, type FROM revlog", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((ease, kind), (0, 4));
+    }
+
+    #[test]
+    fn reviews_at_the_same_time() {
+        let review = ReviewLogEntry::new(1_600_000_000.0, Ease::Good, ReviewKind::Review);
+        let mut deck = Deck::new(1234, "Example deck", "");
+        for front in ["France", "Germany"] {
+            deck.add_note(
+                Note::new(basic_model(), vec![front, "Capital"])
+                    .unwrap()
+                    .with_review(0, review.clone())
+                    .unwrap(),
+            );
+        }
+        let mut buffer = Cursor::new(vec![]);
+        Package::new(vec![deck], vec![])
+            .unwrap()
+            .write_timestamp(&mut buffer, 1_600_000_000.0)
+            .unwrap();
+        buffer.set_position(0);
+        let package = Package::read(buffer).unwrap();
+        let timestamps = all_notes(package.decks())
+            .iter()
+            .map(|note| note.cards()[0].reviews()[0].timestamp())
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, [1_600_000_000.0, 1_600_000_000.001]);
+    }
+
+    #[test]
+    fn invalid_entries() {
+        assert!(ReviewLogEntry::new(-1.0, Ease::Good, ReviewKind::Review)
+            .validate()
+            .is_err());
+        assert!(ReviewLogEntry::new(0.0, Ease::Good, ReviewKind::Review)
+            .factor(-2.5)
+            .validate()
+            .is_err());
+    }
+}
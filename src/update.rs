@@ -0,0 +1,282 @@
+use crate::deck::{all_notes, all_notes_mut, Deck};
+use crate::note::Note;
+use crate::package::Package;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashSet};
+
+/// The GUIDs and ids of the notes of a previous version of a deck, by the value of a key field
+///
+/// Notes get their GUID from all their fields by default, so a note whose content was edited
+/// arrives in Anki as a new note and learners lose their progress on it. A manifest of the
+/// previous version lets the notes of the new version keep the identity of the previous note
+/// with the same key, e.g. the same `Front`:
+///
+/// ```rust,no_run
+/// use genanki_rs::{Deck, Error, NoteManifest, Package};
+///
+/// # fn main() -> Result<(), Error> {
+/// # let mut deck = Deck::new(1234, "Course", "");
+/// let previous = Package::read_from_file("course-v1.apkg")?;
+/// let manifest = NoteManifest::new("Front", previous.decks())?;
+/// // let mut deck = ... the regenerated deck
+/// let update = manifest.update(&mut deck)?;
+/// println!(
+///     "{} added, {} changed, {} removed",
+///     update.added().len(),
+///     update.changed().len(),
+///     update.removed().len()
+/// );
+/// deck.write_to_file("course-v2.apkg")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Manifests can be serialized with serde, so only the manifest of each release has to be kept
+/// instead of the whole package.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoteManifest {
+    key_field: String,
+    notes: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    guid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    /// Hash of the model id, fields and tags, which tells whether the note was changed
+    hash: String,
+}
+
+/// What `NoteManifest::update` found when comparing a deck with the previous version. Notes are
+/// identified by their key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeckUpdate {
+    added: Vec<String>,
+    changed: Vec<String>,
+    unchanged: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl DeckUpdate {
+    /// Returns the keys of the notes which were not in the previous version
+    pub fn added(&self) -> &[String] {
+        &self.added
+    }
+
+    /// Returns the keys of the notes whose fields, tags or model changed
+    pub fn changed(&self) -> &[String] {
+        &self.changed
+    }
+
+    /// Returns the keys of the notes which are the same as in the previous version
+    pub fn unchanged(&self) -> &[String] {
+        &self.unchanged
+    }
+
+    /// Returns the keys of the notes of the previous version which are gone
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+}
+
+impl NoteManifest {
+    /// Creates the manifest of the notes in `decks` and their subdecks, keyed by the value of the
+    /// field called `key_field`
+    ///
+    /// Returns `Err` if a note has no such field or if two notes have the same key
+    pub fn new(key_field: &str, decks: &[Deck]) -> Result<Self, Error> {
+        let mut notes = BTreeMap::new();
+        for note in all_notes(decks) {
+            let key = key(note, key_field)?;
+            let entry = ManifestEntry {
+                guid: note.get_guid().to_string(),
+                id: note.get_id(),
+                hash: content_hash(note),
+            };
+            if notes.insert(key.to_string(), entry).is_some() {
+                return Err(Error::DuplicateKey(key.to_string()));
+            }
+        }
+        Ok(Self {
+            key_field: key_field.to_string(),
+            notes,
+        })
+    }
+
+    /// Returns the name of the field the notes are identified by
+    pub fn key_field(&self) -> &str {
+        &self.key_field
+    }
+
+    /// Returns the GUID of the note with `key`, if the manifest has one
+    pub fn guid(&self, key: &str) -> Option<&str> {
+        self.notes.get(key).map(|entry| entry.guid.as_str())
+    }
+
+    /// Gives the notes of `deck` and its subdecks the GUID and id of the note with the same key in
+    /// the manifest, replacing GUIDs which were set before, and reports which notes were added,
+    /// changed and removed
+    ///
+    /// Returns `Err` if a note has no key field or if two notes have the same key. The deck is
+    /// not modified in that case.
+    pub fn update(&self, deck: &mut Deck) -> Result<DeckUpdate, Error> {
+        self.update_decks(std::slice::from_mut(deck))
+    }
+
+    /// Updates all decks of `package` like `NoteManifest::update`
+    pub fn update_package(&self, package: &mut Package) -> Result<DeckUpdate, Error> {
+        self.update_decks(package.decks_mut())
+    }
+
+    fn update_decks(&self, decks: &mut [Deck]) -> Result<DeckUpdate, Error> {
+        let mut keys = HashSet::new();
+        for note in all_notes(decks) {
+            let key = key(note, &self.key_field)?;
+            if !keys.insert(key.to_string()) {
+                return Err(Error::DuplicateKey(key.to_string()));
+            }
+        }
+
+        let mut update = DeckUpdate::default();
+        for note in all_notes_mut(decks) {
+            let key = key(note, &self.key_field)?.to_string();
+            match self.notes.get(&key) {
+                None => update.added.push(key),
+                Some(entry) => {
+                    if entry.hash == content_hash(note) {
+                        update.unchanged.push(key);
+                    } else {
+                        update.changed.push(key);
+                    }
+                    note.set_guid(entry.guid.clone());
+                    note.set_id(entry.id);
+                }
+            }
+        }
+        update.removed = self
+            .notes
+            .keys()
+            .filter(|key| !keys.contains(*key))
+            .cloned()
+            .collect();
+        Ok(update)
+    }
+}
+
+fn key<'a>(note: &'a Note, key_field: &str) -> Result<&'a str, Error> {
+    note.field(key_field)
+        .ok_or_else(|| Error::UnknownField(key_field.to_string()))
+}
+
+fn content_hash(note: &Note) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(note.model().id.to_string());
+    for field in note.fields() {
+        hasher.update("\x1f");
+        hasher.update(field);
+    }
+    hasher.update("\x1e");
+    hasher.update(note.get_tags().join(" "));
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_model;
+    use std::io::Cursor;
+
+    fn deck(notes: &[(&str, &str)]) -> Deck {
+        let mut deck = Deck::new(1234, "Capitals", "");
+        for (front, back) in notes {
+            deck.add_note(Note::new(basic_model(), vec![front, back]).unwrap());
+        }
+        deck
+    }
+
+    #[test]
+    fn notes_keep_their_identity() {
+        let mut previous = Package::new(
+            vec![deck(&[
+                ("France", "Paris"),
+                ("Germany", "Bonn"),
+                ("Spain", "Madrid"),
+            ])],
+            vec![],
+        )
+        .unwrap();
+        let mut buffer = Cursor::new(vec![]);
+        previous
+            .write_timestamp(&mut buffer, 1_600_000_000.0)
+            .unwrap();
+        buffer.set_position(0);
+        let previous = Package::read(buffer).unwrap();
+        let manifest = NoteManifest::new("Front", previous.decks()).unwrap();
+        let old_notes = previous.decks()[0].notes();
+
+        let mut next = deck(&[
+            ("Germany", "Berlin"),
+            ("France", "Paris"),
+            ("Italy", "Rome"),
+        ]);
+        let update = manifest.update(&mut next).unwrap();
+        assert_eq!(update.added(), ["Italy"]);
+        assert_eq!(update.changed(), ["Germany"]);
+        assert_eq!(update.unchanged(), ["France"]);
+        assert_eq!(update.removed(), ["Spain"]);
+
+        let notes = next.notes();
+        assert_eq!(notes[0].get_guid(), old_notes[1].get_guid());
+        assert_eq!(notes[0].get_id(), old_notes[1].get_id());
+        assert_eq!(notes[1].get_guid(), old_notes[0].get_guid());
+        assert_eq!(notes[2].get_guid(), crate::guid_for(&["Italy", "Rome"]));
+        assert_eq!(notes[2].get_id(), None);
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let manifest = NoteManifest::new("Back", &[deck(&[("France", "Paris")])]).unwrap();
+        let json = serde_json::to_string(&manifest).unwrap();
+        let deserialized: NoteManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, manifest);
+        assert_eq!(
+            deserialized.guid("Paris"),
+            Some(crate::guid_for(&["France", "Paris"]).as_str())
+        );
+        assert_eq!(deserialized.key_field(), "Back");
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let duplicate = deck(&[("France", "Paris"), ("France", "Lyon")]);
+        assert!(matches!(
+            NoteManifest::new("Front", std::slice::from_ref(&duplicate)),
+            Err(Error::DuplicateKey(key)) if key == "France"
+        ));
+        assert!(matches!(
+            NoteManifest::new("Question", &[deck(&[])]).unwrap().update(&mut duplicate.clone()),
+            Err(Error::UnknownField(name)) if name == "Question"
+        ));
+
+        let manifest = NoteManifest::new("Front", &[deck(&[("France", "Marseille")])]).unwrap();
+        let mut next = duplicate;
+        assert!(manifest.update(&mut next).is_err());
+        assert_eq!(
+            next.notes()[0].get_guid(),
+            crate::guid_for(&["France", "Paris"])
+        );
+        assert_eq!(
+            next.notes()[1].get_guid(),
+            crate::guid_for(&["France", "Lyon"])
+        );
+    }
+}
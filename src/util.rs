@@ -1,15 +1,115 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use sha2::{Digest, Sha256};
 
-pub fn guid_for(fields: &Vec<String>) -> String {
-    fields
+/// Characters of the base91 encoding Anki uses for guids
+const BASE91_TABLE: &[u8; 91] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&()*+,-./:;<=>?@[]^_`{|}~";
+
+/// Computes a guid from `values` exactly like `genanki.guid_for` in Python, so decks generated by
+/// both libraries update the same notes: the first 8 bytes of the SHA256 hash of the values
+/// joined by `__`, encoded in base91.
+///
+/// Notes use the guid of all their fields by default. To keep a note when other fields change,
+/// pass the guid of the identifying fields to [`Note::guid`](crate::Note::guid):
+///
+/// ```rust
+/// use genanki_rs::{basic_model, guid_for, Error, Note};
+///
+/// # fn main() -> Result<(), Error> {
+/// let note = Note::new(basic_model(), vec!["Capital of France", "Paris"])?
+///     .guid(guid_for(&["Capital of France"]));
+/// # Ok(())
+/// # }
+/// ```
+pub fn guid_for(values: &[impl AsRef<str>]) -> String {
+    let joined = values
         .iter()
-        .map(|f| u64::to_string(&hash_str(&f)))
-        .collect()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join("__");
+    let hash = Sha256::digest(joined.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    let mut value = u64::from_be_bytes(bytes);
+    let mut reversed = vec![];
+    while value > 0 {
+        reversed.push(BASE91_TABLE[(value % 91) as usize]);
+        value /= 91;
+    }
+    reversed.iter().rev().map(|&byte| byte as char).collect()
 }
 
-fn hash_str(to_hash: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    to_hash.hash(&mut s);
-    s.finish()
+/// Converts a Unix `timestamp` into the time stored in zip entries (UTC). Times which cannot be
+/// represented in a zip file are clamped to its first or last representable second.
+pub(crate) fn zip_date_time(timestamp: f64) -> zip::DateTime {
+    let seconds = timestamp.floor() as i64;
+    let days = seconds.div_euclid(86400);
+    let second_of_day = seconds.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if year < 1980 {
+        return zip::DateTime::default();
+    }
+    if year > 2107 {
+        return zip::DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58)
+            .expect("Should be a valid zip time");
+    }
+    zip::DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        (second_of_day / 3600) as u8,
+        (second_of_day / 60 % 60) as u8,
+        (second_of_day % 60) as u8,
+    )
+    .expect("Should be a valid zip time")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guids_match_python_genanki() {
+        assert_eq!(
+            guid_for(&["What is the capital of France?", "Paris"]),
+            "v#rAo?Y]yM"
+        );
+        assert_eq!(guid_for(&["a".to_string()]), "IkF(BOZ;]l");
+        assert_eq!(guid_for(&["Ünïcödé", "", ""]), "F08zFRz(M?");
+    }
+
+    #[test]
+    fn zip_times() {
+        let time = zip_date_time(1_600_000_000.5);
+        assert_eq!(
+            (
+                time.year(),
+                time.month(),
+                time.day(),
+                time.hour(),
+                time.minute(),
+                time.second()
+            ),
+            (2020, 9, 13, 12, 26, 40)
+        );
+        let time = zip_date_time(951_782_400.0);
+        assert_eq!((time.year(), time.month(), time.day()), (2000, 2, 29));
+        assert_eq!(zip_date_time(0.0).year(), 1980);
+        assert_eq!(zip_date_time(1e12).year(), 2107);
+    }
 }
@@ -0,0 +1,360 @@
+use crate::cloze;
+use crate::deck::{all_notes, Deck};
+use crate::model::ModelType;
+use crate::note::{invalid_html_tags, Note};
+use crate::zero_cards::{empty_note, CardRequirement};
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+/// Default for [`Validator::max_field_size`], in bytes
+const DEFAULT_MAX_FIELD_SIZE: usize = 128 * 1024;
+
+type Sink<'a> = Box<dyn FnMut(&ValidationIssue) + 'a>;
+
+/// Kind of problem found by a [`Validator`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IssueKind {
+    /// The field contains a tag which is not valid HTML, e.g. `<@b>`
+    InvalidHtml,
+    /// A cloze deletion like `{{c1::` is not closed, or a `}}` closes no cloze deletion
+    UnbalancedCloze,
+    /// The note generates no cards, because it does not meet the requirement of any template
+    EmptyCards { unmet: Vec<CardRequirement> },
+    /// An earlier note of the same model has the same sort field, so Anki shows them as
+    /// duplicates
+    DuplicateSortField { first_guid: String },
+    /// The field is larger than [`Validator::max_field_size`]
+    OversizedField { size: usize, limit: usize },
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::InvalidHtml => write!(f, "invalid HTML tag"),
+            IssueKind::UnbalancedCloze => write!(f, "unbalanced cloze deletion"),
+            IssueKind::EmptyCards { unmet } => {
+                let unmet = unmet.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "no cards, {}", unmet.join(", "))
+            }
+            IssueKind::DuplicateSortField { first_guid } => {
+                write!(f, "same sort field as note {:?}", first_guid)
+            }
+            IssueKind::OversizedField { size, limit } => {
+                write!(f, "{} bytes, more than the limit of {}", size, limit)
+            }
+        }
+    }
+}
+
+/// A problem with a note, found by a [`Validator`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationIssue {
+    note_guid: String,
+    field: Option<String>,
+    kind: IssueKind,
+    span: Option<Range<usize>>,
+}
+
+impl ValidationIssue {
+    /// Returns the GUID of the note with the problem
+    pub fn note_guid(&self) -> &str {
+        &self.note_guid
+    }
+
+    /// Returns the name of the field with the problem, or `None` if it concerns the whole note
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// Returns what the problem is
+    pub fn kind(&self) -> &IssueKind {
+        &self.kind
+    }
+
+    /// Returns the byte range of the problem in the field value, if it is in a specific place
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "note {:?}", self.note_guid)?;
+        if let Some(field) = &self.field {
+            write!(f, ", field {:?}", field)?;
+        }
+        write!(f, ": {}", self.kind)?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}..{}", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// The problems [`Validator::validate`] found, in the order of the notes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns all problems which were found
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    /// Returns whether no problems were found
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl IntoIterator for ValidationReport {
+    type Item = ValidationIssue;
+    type IntoIter = std::vec::IntoIter<ValidationIssue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.issues.into_iter()
+    }
+}
+
+/// Checks notes for problems which do not stop a package from being written but make Anki show
+/// broken or surprising cards
+///
+/// Nothing is printed. The problems are returned as a [`ValidationReport`] and also passed to
+/// the sink, if one was set, as soon as they are found, e.g. to forward them to a logger:
+///
+/// ```rust
+/// use genanki_rs::{basic_model, Deck, Error, IssueKind, Note, Validator};
+///
+/// # fn main() -> Result<(), Error> {
+/// let mut deck = Deck::new(1234, "Example deck", "");
+/// deck.add_note(Note::new(basic_model(), vec!["Capital of <@France>", "Paris"])?);
+/// deck.add_note(Note::new(basic_model(), vec!["", ""])?);
+///
+/// let mut warnings = vec![];
+/// let report = Validator::new()
+///     .sink(|issue| warnings.push(issue.to_string()))
+///     .validate(std::slice::from_ref(&deck));
+/// assert_eq!(report.issues()[0].kind(), &IssueKind::InvalidHtml);
+/// assert_eq!(report.issues()[0].span(), Some(11..20));
+/// assert!(matches!(report.issues()[1].kind(), IssueKind::EmptyCards { .. }));
+/// assert_eq!(warnings.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Validator<'a> {
+    max_field_size: usize,
+    sink: Option<Sink<'a>>,
+}
+
+impl Default for Validator<'_> {
+    fn default() -> Self {
+        Self {
+            max_field_size: DEFAULT_MAX_FIELD_SIZE,
+            sink: None,
+        }
+    }
+}
+
+impl<'a> Validator<'a> {
+    /// Creates a validator with the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size in bytes above which fields are reported, default is 128 KiB
+    pub fn max_field_size(self, max_field_size: usize) -> Self {
+        Self {
+            max_field_size,
+            ..self
+        }
+    }
+
+    /// Sets a function which is called with every problem as soon as it is found
+    pub fn sink(self, sink: impl FnMut(&ValidationIssue) + 'a) -> Self {
+        Self {
+            sink: Some(Box::new(sink)),
+            ..self
+        }
+    }
+
+    /// Checks the notes of `decks` and all of their subdecks
+    pub fn validate(&mut self, decks: &[Deck]) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let mut sort_fields: HashMap<(i64, String), &str> = HashMap::new();
+        for note in all_notes(decks) {
+            let mut issues = self.check_note(note);
+            let sort_field = note.get_sort_field();
+            if !sort_field.is_empty() {
+                match sort_fields.entry((note.model().id, sort_field)) {
+                    Entry::Occupied(first) => issues.push(issue(
+                        note,
+                        note.sort_field_name(),
+                        IssueKind::DuplicateSortField {
+                            first_guid: first.get().to_string(),
+                        },
+                        None,
+                    )),
+                    Entry::Vacant(entry) => {
+                        entry.insert(note.get_guid());
+                    }
+                }
+            }
+            for issue in issues {
+                if let Some(sink) = &mut self.sink {
+                    sink(&issue);
+                }
+                report.issues.push(issue);
+            }
+        }
+        report
+    }
+
+    fn check_note(&self, note: &Note) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        let is_cloze = note.model().get_model_type() == ModelType::Cloze;
+        for (name, value) in note.model().field_names().into_iter().zip(note.fields()) {
+            if value.len() > self.max_field_size {
+                let kind = IssueKind::OversizedField {
+                    size: value.len(),
+                    limit: self.max_field_size,
+                };
+                issues.push(issue(note, Some(name), kind, None));
+            }
+            for span in invalid_html_tags(value) {
+                issues.push(issue(note, Some(name), IssueKind::InvalidHtml, Some(span)));
+            }
+            if is_cloze {
+                for span in cloze::parse(value).unbalanced {
+                    issues.push(issue(
+                        note,
+                        Some(name),
+                        IssueKind::UnbalancedCloze,
+                        Some(span),
+                    ));
+                }
+            }
+        }
+        if let Ok(Some(empty_note)) = empty_note(note) {
+            let unmet = empty_note.unmet_requirements().to_vec();
+            issues.push(issue(note, None, IssueKind::EmptyCards { unmet }, None));
+        }
+        issues
+    }
+}
+
+fn issue(
+    note: &Note,
+    field: Option<&str>,
+    kind: IssueKind,
+    span: Option<Range<usize>>,
+) -> ValidationIssue {
+    ValidationIssue {
+        note_guid: note.get_guid().to_string(),
+        field: field.map(str::to_string),
+        kind,
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{basic_model, cloze_model, Package};
+
+    fn kinds(report: &ValidationReport) -> Vec<(Option<&str>, &IssueKind)> {
+        report
+            .issues()
+            .iter()
+            .map(|issue| (issue.field(), issue.kind()))
+            .collect()
+    }
+
+    #[test]
+    fn valid_notes_have_no_issues() {
+        let mut deck = Deck::new(1234, "Example deck", "");
+        deck.add_note(Note::new(basic_model(), vec!["<b>Capital</b> of France", "Paris"]).unwrap());
+        deck.add_note(
+            Note::new(
+                cloze_model(),
+                vec!["{{c1::Paris}} is the capital of {{c2::France {{c3::!}}}}"],
+            )
+            .unwrap(),
+        );
+        assert!(deck.validate().is_empty());
+    }
+
+    #[test]
+    fn issues_are_reported_by_note_and_field() {
+        let mut deck = Deck::new(1234, "Example deck", "");
+        deck.add_note(Note::new(basic_model(), vec!["France", "Paris"]).unwrap());
+        deck.add_note(Note::new(basic_model(), vec!["France", "<@Lyon>"]).unwrap());
+        deck.add_note(Note::new(basic_model(), vec!["Germany", &"Berlin".repeat(2)]).unwrap());
+        let mut subdeck = Deck::new(1235, "Subdeck", "");
+        subdeck.add_note(Note::new(cloze_model(), vec!["{{c1::Paris"]).unwrap());
+        subdeck.add_note(Note::new(cloze_model(), vec!["Paris"]).unwrap());
+        deck.add_subdeck(subdeck).unwrap();
+
+        let empty_cloze = IssueKind::EmptyCards {
+            unmet: empty_note(&Note::new(cloze_model(), vec![""]).unwrap())
+                .unwrap()
+                .unwrap()
+                .unmet_requirements()
+                .to_vec(),
+        };
+        let report = Validator::new()
+            .max_field_size(11)
+            .validate(&[deck.clone()]);
+        assert_eq!(
+            kinds(&report),
+            [
+                (Some("Back"), &IssueKind::InvalidHtml),
+                (
+                    Some("Front"),
+                    &IssueKind::DuplicateSortField {
+                        first_guid: crate::guid_for(&["France", "Paris"])
+                    }
+                ),
+                (
+                    Some("Back"),
+                    &IssueKind::OversizedField {
+                        size: 12,
+                        limit: 11
+                    }
+                ),
+                (Some("Text"), &IssueKind::UnbalancedCloze),
+                (None, &empty_cloze),
+                (None, &empty_cloze),
+            ]
+        );
+        let issue = &report.issues()[0];
+        assert_eq!(issue.note_guid(), crate::guid_for(&["France", "<@Lyon>"]));
+        assert_eq!(issue.span(), Some(0..7));
+        assert_eq!(
+            issue.to_string(),
+            format!(
+                "note {:?}, field \"Back\": invalid HTML tag at 0..7",
+                issue.note_guid()
+            )
+        );
+
+        let package = Package::new(vec![deck], vec![]).unwrap();
+        assert_eq!(package.validate().issues().len(), 5);
+    }
+
+    #[test]
+    fn sink_receives_every_issue() {
+        let mut deck = Deck::new(1234, "Example deck", "");
+        deck.add_note(Note::new(basic_model(), vec!["", ""]).unwrap());
+        deck.add_note(Note::new(basic_model(), vec!["<@>", ""]).unwrap());
+        let mut received = vec![];
+        let report = Validator::new()
+            .sink(|issue| received.push(issue.clone()))
+            .validate(&[deck]);
+        assert_eq!(received, report.issues());
+        assert_eq!(received.len(), 2);
+    }
+}
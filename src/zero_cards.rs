@@ -0,0 +1,282 @@
+use crate::model::ModelType;
+use crate::note::{cloze_field_names, cloze_ords, Note};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What happens to notes which generate no cards when a deck is written
+///
+/// Anki keeps such notes but lists them under Tools > Empty Cards for cleanup. The policy of a
+/// deck applies to its subdecks unless they set their own, and decks without one follow the
+/// policy of the `Package` or `PackageWriter`.
+///
+/// Example:
+/// ```rust
+/// use genanki_rs::{basic_model, Deck, Error, Note, Package, ZeroCardPolicy};
+/// use std::io::Cursor;
+///
+/// # fn main() -> Result<(), Error> {
+/// let mut deck = Deck::new(1234, "Example deck", "");
+/// deck.add_note(Note::new(basic_model(), vec!["", "Paris"])?);
+/// let mut package = Package::new(vec![deck], vec![])?.zero_card_policy(ZeroCardPolicy::Reject);
+/// match package.write(Cursor::new(vec![])) {
+///     Err(Error::NoCards(note)) => {
+///         assert_eq!(note.unmet_requirements()[0].to_string(), "template \"Card 1\" needs \"Front\"")
+///     }
+///     _ => panic!("the note has no cards"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZeroCardPolicy {
+    /// Writes the notes anyway. [`Package::validate`](crate::Package::validate) reports them.
+    #[default]
+    Warn,
+    /// Leaves the notes out of the package
+    Skip,
+    /// Fails writing with [`Error::NoCards`]
+    Reject,
+}
+
+impl ZeroCardPolicy {
+    /// Returns whether `note` should be written, or `Err` if it generates no cards and the
+    /// policy is to reject it
+    pub(crate) fn admits(self, note: &Note) -> Result<bool, Error> {
+        if self == ZeroCardPolicy::Warn {
+            return Ok(true);
+        }
+        match empty_note(note)? {
+            None => Ok(true),
+            Some(_) if self == ZeroCardPolicy::Skip => Ok(false),
+            Some(empty_note) => Err(Error::NoCards(empty_note)),
+        }
+    }
+}
+
+/// How many of the fields of a [`CardRequirement`] have to be filled in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequirementKind {
+    /// At least one of the fields must not be empty
+    Any,
+    /// None of the fields may be empty
+    All,
+    /// At least one of the fields must contain a cloze deletion like `{{c1::...}}`
+    Cloze,
+}
+
+/// Rule by which Anki decides whether a template generates a card for a note, derived from the
+/// fields its front side refers to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CardRequirement {
+    template: String,
+    kind: RequirementKind,
+    fields: Vec<String>,
+}
+
+impl CardRequirement {
+    /// Returns the name of the template
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Returns how many of the fields have to be filled in
+    pub fn kind(&self) -> RequirementKind {
+        self.kind
+    }
+
+    /// Returns the names of the fields the requirement is about
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+}
+
+impl fmt::Display for CardRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| format!("{:?}", field))
+            .collect::<Vec<_>>();
+        write!(f, "template {:?} needs ", self.template)?;
+        match self.kind {
+            RequirementKind::Any => write!(f, "{}", fields.join(" or ")),
+            RequirementKind::All => write!(f, "{}", fields.join(" and ")),
+            RequirementKind::Cloze => write!(f, "a cloze deletion in {}", fields.join(" or ")),
+        }
+    }
+}
+
+/// A note which generates no cards, together with the requirements of its templates which it
+/// does not meet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmptyNote {
+    guid: String,
+    fields: Vec<String>,
+    unmet_requirements: Vec<CardRequirement>,
+}
+
+impl EmptyNote {
+    /// Returns the GUID of the note
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    /// Returns the fields of the note
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Returns the requirement of every template of the model, none of which the note meets
+    pub fn unmet_requirements(&self) -> &[CardRequirement] {
+        &self.unmet_requirements
+    }
+}
+
+impl fmt::Display for EmptyNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requirements = self
+            .unmet_requirements
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "The note {:?} generates no cards: {}",
+            self.guid,
+            requirements.join(", ")
+        )
+    }
+}
+
+/// Returns the unmet requirements of `note` if it generates no cards
+pub(crate) fn empty_note(note: &Note) -> Result<Option<EmptyNote>, Error> {
+    let model = note.model();
+    let fields = note.fields();
+    let field_names = model.field_names();
+    let templates = model.templates();
+    let is_filled = |&ord: &usize| fields.get(ord).is_some_and(|field| !field.is_empty());
+    let mut unmet_requirements = vec![];
+    match model.get_model_type() {
+        ModelType::FrontBack => {
+            for (template_ord, any_or_all, field_ords) in model.req()? {
+                let (kind, met) = match any_or_all.as_str() {
+                    "any" => (RequirementKind::Any, field_ords.iter().any(is_filled)),
+                    _ => (RequirementKind::All, field_ords.iter().all(is_filled)),
+                };
+                if met {
+                    return Ok(None);
+                }
+                unmet_requirements.push(CardRequirement {
+                    template: templates[template_ord].name.clone(),
+                    kind,
+                    fields: field_ords
+                        .iter()
+                        .map(|&ord| field_names[ord].to_string())
+                        .collect(),
+                });
+            }
+        }
+        ModelType::Cloze => {
+            if !cloze_ords(model, fields).is_empty() {
+                return Ok(None);
+            }
+            unmet_requirements.push(CardRequirement {
+                template: templates[0].name.clone(),
+                kind: RequirementKind::Cloze,
+                fields: cloze_field_names(model),
+            });
+        }
+    }
+    Ok(Some(EmptyNote {
+        guid: note.get_guid().to_string(),
+        fields: fields.to_vec(),
+        unmet_requirements,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deck::all_notes;
+    use crate::{basic_and_reversed_card_model, basic_model, cloze_model, Deck, Package};
+    use std::io::Cursor;
+
+    fn unmet(note: &Note) -> Option<Vec<String>> {
+        empty_note(note).unwrap().map(|empty_note| {
+            empty_note
+                .unmet_requirements()
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn requirements() {
+        let note = Note::new(basic_and_reversed_card_model(), vec!["", ""]).unwrap();
+        assert_eq!(
+            unmet(&note),
+            Some(vec![
+                "template \"Card 1\" needs \"Front\"".to_string(),
+                "template \"Card 2\" needs \"Back\"".to_string()
+            ])
+        );
+        let note = Note::new(basic_and_reversed_card_model(), vec!["", "Paris"]).unwrap();
+        assert_eq!(unmet(&note), None);
+
+        let note = Note::new(cloze_model(), vec!["Paris"]).unwrap();
+        assert_eq!(
+            unmet(&note),
+            Some(vec![
+                "template \"Cloze\" needs a cloze deletion in \"Text\"".to_string()
+            ])
+        );
+        let note = Note::new(cloze_model(), vec!["{{c1::Paris}}"]).unwrap();
+        assert_eq!(unmet(&note), None);
+    }
+
+    fn package(deck_policy: Option<ZeroCardPolicy>, policy: ZeroCardPolicy) -> Package {
+        let mut subdeck = Deck::new(1235, "Subdeck", "");
+        subdeck.add_note(Note::new(basic_model(), vec!["", "Lyon"]).unwrap());
+        let mut deck = Deck::new(1234, "Example deck", "");
+        deck.add_note(Note::new(basic_model(), vec!["France", "Paris"]).unwrap());
+        deck.add_subdeck(subdeck).unwrap();
+        if let Some(deck_policy) = deck_policy {
+            deck = deck.with_zero_card_policy(deck_policy);
+        }
+        Package::new(vec![deck], vec![])
+            .unwrap()
+            .zero_card_policy(policy)
+    }
+
+    fn written_notes(mut package: Package) -> Result<usize, Error> {
+        let mut buffer = Cursor::new(vec![]);
+        package.write_timestamp(&mut buffer, 1_600_000_000.0)?;
+        buffer.set_position(0);
+        let package = Package::read(buffer)?;
+        Ok(all_notes(package.decks()).len())
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(
+            written_notes(package(None, ZeroCardPolicy::Warn)).unwrap(),
+            2
+        );
+        assert_eq!(
+            written_notes(package(None, ZeroCardPolicy::Skip)).unwrap(),
+            1
+        );
+        assert!(matches!(
+            written_notes(package(None, ZeroCardPolicy::Reject)),
+            Err(Error::NoCards(note)) if note.fields() == ["", "Lyon"]
+        ));
+        assert_eq!(
+            written_notes(package(Some(ZeroCardPolicy::Warn), ZeroCardPolicy::Reject)).unwrap(),
+            2
+        );
+    }
+}